@@ -0,0 +1,323 @@
+//! Debugger and instrumentation detection, run by the stub before decryption.
+//!
+//! Which checks run, and what happens when one fires, is chosen at build
+//! time and stored in the container header.
+
+use std::fmt;
+use std::path::Path;
+
+/// Environment variables that make the dynamic loader inject code.
+pub const PRELOAD_VARS: &[&str] = &["LD_PRELOAD", "LD_AUDIT"];
+
+/// Libraries the stub itself links against. Anything else mapped into the
+/// process was injected.
+const EXPECTED_LIBRARIES: &[&str] = &[
+    "ld-linux", "ld-musl", "libc.", "libc-", "libm.", "libm-", "libgcc_s.", "libpthread.",
+    "libdl.", "librt.", "libutil.",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    /// `TracerPid` in `/proc/self/status`.
+    Tracer,
+    /// Attach probe: fails if a tracer is already attached.
+    Ptrace,
+    /// `LD_PRELOAD` / `LD_AUDIT` in the environment.
+    Preload,
+    /// Unexpected shared libraries in `/proc/self/maps`.
+    Maps,
+}
+
+pub const ALL_CHECKS: [Check; 4] = [Check::Tracer, Check::Ptrace, Check::Preload, Check::Maps];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Report the finding and continue.
+    Log,
+    /// Report the finding and refuse to decrypt.
+    Refuse,
+    /// Strip the injection from the environment of the protected program.
+    /// A tracer cannot be removed, and neither can libraries that are
+    /// already mapped, through `/etc/ld.so.preload` or at run time, so the
+    /// tracer and maps checks treat this as `Refuse`.
+    Sanitize,
+}
+
+impl Check {
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::Tracer => "tracer",
+            Check::Ptrace => "ptrace",
+            Check::Preload => "preload",
+            Check::Maps => "maps",
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            Check::Tracer => 1,
+            Check::Ptrace => 2,
+            Check::Preload => 3,
+            Check::Maps => 4,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        ALL_CHECKS.into_iter().find(|c| c.id() == id)
+    }
+}
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Log => "log",
+            Action::Refuse => "refuse",
+            Action::Sanitize => "sanitize",
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            Action::Log => 1,
+            Action::Refuse => 2,
+            Action::Sanitize => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        [Action::Log, Action::Refuse, Action::Sanitize]
+            .into_iter()
+            .find(|a| a.id() == id)
+    }
+}
+
+/// Parses a `<check>=<action>` build option; `all` selects every check.
+pub fn parse_setting(s: &str) -> Result<Vec<(Check, Action)>, String> {
+    let (check, action) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected <check>=<action>, got '{}'", s))?;
+
+    let action = [Action::Log, Action::Refuse, Action::Sanitize]
+        .into_iter()
+        .find(|a| a.name() == action)
+        .ok_or_else(|| format!("Unknown action '{}' (expected log, refuse or sanitize)", action))?;
+
+    if check == "all" {
+        return Ok(ALL_CHECKS.iter().map(|&c| (c, action)).collect());
+    }
+    let check = ALL_CHECKS
+        .into_iter()
+        .find(|c| c.name() == check)
+        .ok_or_else(|| format!("Unknown check '{}' (expected tracer, ptrace, preload, maps or all)", check))?;
+    if (check, action) == (Check::Maps, Action::Sanitize) {
+        return Err("maps=sanitize cannot unload mapped libraries; use maps=refuse".into());
+    }
+    Ok(vec![(check, action)])
+}
+
+/// Something a check detected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub check: Check,
+    pub detail: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} check: {}", self.check.name(), self.detail)
+    }
+}
+
+/// Runs a single check against the live process.
+pub fn run_check(check: Check, exe_path: &Path) -> Option<Finding> {
+    let detail = match check {
+        Check::Tracer => {
+            let status = std::fs::read_to_string("/proc/self/status").ok()?;
+            let pid = tracer_pid(&status).filter(|&pid| pid != 0)?;
+            format!("traced by process {}", pid)
+        }
+        Check::Ptrace => {
+            if !ptrace_probe()? {
+                return None;
+            }
+            "another tracer is attached".to_string()
+        }
+        Check::Preload => {
+            let vars = preload_vars(std::env::vars_os().map(|(k, _)| k.to_string_lossy().into_owned()));
+            if vars.is_empty() {
+                return None;
+            }
+            format!("{} set", vars.join(", "))
+        }
+        Check::Maps => {
+            let maps = std::fs::read_to_string("/proc/self/maps").ok()?;
+            let exe = std::fs::canonicalize(exe_path).unwrap_or_else(|_| exe_path.to_path_buf());
+            let libs = unexpected_libraries(&maps, &exe);
+            if libs.is_empty() {
+                return None;
+            }
+            format!("unexpected libraries loaded: {}", libs.join(", "))
+        }
+    };
+    Some(Finding { check, detail })
+}
+
+/// Removes loader injection variables so they do not reach the protected
+/// program. Must run before the stub starts any threads.
+pub fn sanitize_environment() {
+    for var in PRELOAD_VARS {
+        unsafe { std::env::remove_var(var) };
+    }
+}
+
+/// Extracts `TracerPid` from the contents of `/proc/<pid>/status`.
+pub fn tracer_pid(status: &str) -> Option<u32> {
+    status
+        .lines()
+        .find_map(|l| l.strip_prefix("TracerPid:"))
+        .and_then(|v| v.trim().parse().ok())
+}
+
+/// Returns the injection variables present among `names`.
+pub fn preload_vars(names: impl Iterator<Item = String>) -> Vec<String> {
+    names.filter(|n| PRELOAD_VARS.contains(&n.as_str())).collect()
+}
+
+/// Lists shared objects in a `/proc/self/maps` dump other than the
+/// executable itself and the libraries the stub links against.
+pub fn unexpected_libraries(maps: &str, exe: &Path) -> Vec<String> {
+    let mut found: Vec<String> = Vec::new();
+    for line in maps.lines() {
+        // address perms offset dev inode pathname
+        let Some(path) = line.split_whitespace().nth(5) else { continue };
+        if !path.starts_with('/') || Path::new(path) == exe {
+            continue;
+        }
+        let name = path.rsplit('/').next().unwrap_or(path);
+        if !name.contains(".so") || EXPECTED_LIBRARIES.iter().any(|p| name.starts_with(p)) {
+            continue;
+        }
+        if !found.iter().any(|f| f == path) {
+            found.push(path.to_string());
+        }
+    }
+    found
+}
+
+/// Probes for an attached tracer by having a helper process try to attach
+/// to us; the kernel allows only one tracer per process. Returns `None`
+/// when the probe is inconclusive (unsupported or forbidden by policy).
+#[cfg(target_os = "linux")]
+pub fn ptrace_probe() -> Option<bool> {
+    // Yama modes 2 and 3 forbid the attach regardless of existing tracers.
+    if let Ok(scope) = std::fs::read_to_string("/proc/sys/kernel/yama/ptrace_scope")
+        && scope.trim().parse::<u32>().is_ok_and(|s| s >= 2)
+    {
+        return None;
+    }
+
+    unsafe {
+        let parent = libc::getpid();
+        let mut fds = [0; 2];
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return None;
+        }
+
+        let child = libc::fork();
+        if child == -1 {
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+            return None;
+        }
+        if child == 0 {
+            // Wait until the parent has allowed us to attach.
+            let mut go = 0u8;
+            libc::close(fds[1]);
+            libc::read(fds[0], &mut go as *mut u8 as *mut libc::c_void, 1);
+            let code = if libc::ptrace(libc::PTRACE_SEIZE, parent, 0, 0) == 0 {
+                0
+            } else if std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM) {
+                1
+            } else {
+                2
+            };
+            // Exiting detaches from the parent again.
+            libc::_exit(code);
+        }
+
+        libc::close(fds[0]);
+        libc::prctl(libc::PR_SET_PTRACER, child as libc::c_ulong, 0, 0, 0);
+        libc::write(fds[1], [1u8].as_ptr() as *const libc::c_void, 1);
+        libc::close(fds[1]);
+
+        let mut status = 0;
+        let waited = libc::waitpid(child, &mut status, 0);
+        libc::prctl(libc::PR_SET_PTRACER, 0, 0, 0, 0);
+
+        if waited != child || !libc::WIFEXITED(status) {
+            return None;
+        }
+        match libc::WEXITSTATUS(status) {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn ptrace_probe() -> Option<bool> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracer_pid() {
+        let status = "Name:\tstub\nState:\tR (running)\nTracerPid:\t4242\nUid:\t0\n";
+        assert_eq!(tracer_pid(status), Some(4242));
+        assert_eq!(tracer_pid("TracerPid:\t0\n"), Some(0));
+        assert_eq!(tracer_pid("Name:\tstub\n"), None);
+    }
+
+    #[test]
+    fn test_preload_vars() {
+        let names = ["PATH", "LD_PRELOAD", "HOME", "LD_AUDIT", "LD_LIBRARY_PATH"];
+        let found = preload_vars(names.iter().map(|s| s.to_string()));
+        assert_eq!(found, ["LD_PRELOAD", "LD_AUDIT"]);
+    }
+
+    #[test]
+    fn test_unexpected_libraries() {
+        let maps = "\
+55d0c0a00000-55d0c0a20000 r--p 00000000 08:01 100 /opt/app/tool.secured
+7f1000000000-7f1000020000 r-xp 00000000 08:01 200 /usr/lib/x86_64-linux-gnu/libc.so.6
+7f1000100000-7f1000120000 r-xp 00000000 08:01 201 /usr/lib/x86_64-linux-gnu/libgcc_s.so.1
+7f1000200000-7f1000220000 r-xp 00000000 08:01 300 /tmp/libhook.so
+7f1000220000-7f1000230000 rw-p 00020000 08:01 300 /tmp/libhook.so
+7f1000300000-7f1000320000 r-xp 00000000 08:01 202 /usr/lib/x86_64-linux-gnu/ld-linux-x86-64.so.2
+7ffd00000000-7ffd00021000 rw-p 00000000 00:00 0 [stack]
+7ffd00100000-7ffd00102000 r-xp 00000000 00:00 0 [vdso]
+";
+        let found = unexpected_libraries(maps, Path::new("/opt/app/tool.secured"));
+        assert_eq!(found, ["/tmp/libhook.so"]);
+    }
+
+    #[test]
+    fn test_parse_setting() {
+        assert_eq!(parse_setting("tracer=refuse"), Ok(vec![(Check::Tracer, Action::Refuse)]));
+        assert_eq!(parse_setting("all=log").unwrap().len(), ALL_CHECKS.len());
+        assert!(parse_setting("tracer").is_err());
+        assert!(parse_setting("tracer=ignore").is_err());
+        assert!(parse_setting("strace=log").is_err());
+        assert!(parse_setting("maps=sanitize").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_ptrace_probe_untraced() {
+        assert_ne!(ptrace_probe(), Some(true));
+    }
+}
@@ -0,0 +1,135 @@
+//! Files encrypted together with the program: the shared libraries of an
+//! executable, or the other files of a script directory.
+//!
+//! A bundle is an index, then the program, then each file in index order.
+//! The header records how many libraries there are, or the entry point of a
+//! script directory; other builds encrypt the program alone, as before.
+
+use std::io::{self, Read};
+
+/// More files than any real bundle has; bounds what a damaged index can
+/// make the stub allocate.
+pub const MAX_FILES: u32 = 65_536;
+
+/// Sizes of the parts that follow the index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Index {
+    pub program_size: u64,
+    /// Relative path and size of each file; for a library, the file name
+    /// the loader looks for.
+    pub files: Vec<(String, u64)>,
+}
+
+impl Index {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = self.program_size.to_le_bytes().to_vec();
+        out.extend_from_slice(&(self.files.len() as u32).to_le_bytes());
+        for (name, size) in &self.files {
+            out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(&size.to_le_bytes());
+        }
+        out
+    }
+
+    /// Reads the index from the start of the plaintext, leaving `reader` at
+    /// the program.
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let program_size = u64::from_le_bytes(read_array(reader)?);
+        let count = u32::from_le_bytes(read_array(reader)?);
+        if count > MAX_FILES {
+            return Err(invalid("too many bundled files"));
+        }
+        let mut files = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut name = vec![0; u16::from_le_bytes(read_array(reader)?) as usize];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|_| invalid("invalid file name"))?;
+            if !valid_path(&name) {
+                return Err(invalid("invalid file name"));
+            }
+            files.push((name, u64::from_le_bytes(read_array(reader)?)));
+        }
+        Ok(Index { program_size, files })
+    }
+}
+
+/// Whether `name` can be used as the file name of a bundled library.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\0']) && name.len() <= u16::MAX as usize
+}
+
+/// Whether `path` is a relative path that stays inside the directory it is
+/// unpacked into.
+pub fn valid_path(path: &str) -> bool {
+    path.len() <= u16::MAX as usize && path.split('/').all(valid_name)
+}
+
+/// The plaintext of a build with bundled files.
+pub fn pack(program: &[u8], files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let index = Index {
+        program_size: program.len() as u64,
+        files: files.iter().map(|(name, data)| (name.clone(), data.len() as u64)).collect(),
+    };
+    let mut out = index.to_bytes();
+    out.extend_from_slice(program);
+    for (_, data) in files {
+        out.extend_from_slice(data);
+    }
+    out
+}
+
+/// The program and the named files of a bundle.
+pub type Parts<'a> = (&'a [u8], Vec<(String, &'a [u8])>);
+
+/// Splits a plaintext made by [`pack`] into the program and the files.
+pub fn unpack(data: &[u8]) -> io::Result<Parts<'_>> {
+    let mut rest = data;
+    let index = Index::read_from(&mut rest)?;
+    let mut take = |size: u64| {
+        let size = usize::try_from(size).ok().filter(|&n| n <= rest.len());
+        let size = size.ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "bundle is truncated"))?;
+        let (part, tail) = rest.split_at(size);
+        rest = tail;
+        Ok::<_, io::Error>(part)
+    };
+    let program = take(index.program_size)?;
+    let mut files = Vec::new();
+    for (name, size) in index.files {
+        files.push((name, take(size)?));
+    }
+    if !rest.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "data after the last bundled file"));
+    }
+    Ok((program, files))
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_roundtrip() {
+        let libraries = vec![("libfoo.so.1".to_string(), b"foo".to_vec()), ("libbar.so".to_string(), Vec::new())];
+        let packed = pack(b"program", &libraries);
+        let (program, unpacked) = unpack(&packed).unwrap();
+        assert_eq!(program, b"program");
+        assert_eq!(unpacked, vec![("libfoo.so.1".to_string(), &b"foo"[..]), ("libbar.so".to_string(), &b""[..])]);
+
+        assert!(unpack(&packed[..packed.len() - 1]).is_err());
+        assert!(unpack(&[&packed[..], b"x"].concat()).is_err());
+        let bad_name = pack(b"program", &[("../libc.so".to_string(), Vec::new())]);
+        assert!(unpack(&bad_name).is_err());
+        assert!(valid_path("lib/helper.py"));
+        for path in ["/etc/passwd", "lib//x", "lib/../..", ""] {
+            assert!(!valid_path(path), "{}", path);
+        }
+    }
+}
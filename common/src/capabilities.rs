@@ -0,0 +1,189 @@
+//! What a stub build supports, so `sbb` can refuse options a stub cannot
+//! enforce.
+//!
+//! Every stub carries a capability record in its image, written by
+//! [`capability_record!`], which `sbb` can read without running the stub,
+//! including stubs for other architectures. Stub registries keep the same
+//! record as a JSON sidecar next to each stub.
+
+use std::error::Error;
+
+use serde_json::{Value, json};
+
+use crate::header::{HEADER_VERSION, Header};
+
+const RECORD_START: &[u8] = b"--SBB_CAPS--";
+const RECORD_END: &[u8] = b"--SBB_CAPS_END--";
+
+/// Cipher suite names, as listed in capability records.
+pub const CIPHER_AES_256_GCM: &str = "aes-256-gcm";
+pub const CIPHER_AES_256_GCM_SIV: &str = "aes-256-gcm-siv";
+
+/// Capability record of a stub built from this source tree for `$target`,
+/// as a string literal suitable for a `#[used]` static.
+#[macro_export]
+macro_rules! capability_record {
+    ($($target:tt)+) => {
+        concat!(
+            "--SBB_CAPS--{\"target\":\"",
+            $($target)+,
+            "\",\"container_versions\":[1],",
+            "\"ciphers\":[\"aes-256-gcm\",\"aes-256-gcm-siv\"],",
+            "\"features\":[\"validity-window\",\"launch-limits\",\"launch-policy\",",
+            "\"anti-debug\",\"integrity\",\"diagnose\",\"manifest\",\"input-hash\",",
+            "\"compression\",\"bundle-libs\",\"scripts\"]}",
+            "--SBB_CAPS_END--"
+        )
+    };
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub target: String,
+    pub container_versions: Vec<u8>,
+    pub ciphers: Vec<String>,
+    pub features: Vec<String>,
+}
+
+impl Capabilities {
+    /// Finds the capability record in a stub image.
+    pub fn find_in(image: &[u8]) -> Option<Self> {
+        let mut rest = image;
+        while let Some(pos) = rest.windows(RECORD_START.len()).position(|w| w == RECORD_START) {
+            rest = &rest[pos + RECORD_START.len()..];
+            let Some(end) = rest.windows(RECORD_END.len()).position(|w| w == RECORD_END) else { break };
+            if let Ok(value) = serde_json::from_slice(&rest[..end])
+                && let Ok(caps) = Capabilities::from_json(&value)
+            {
+                return Some(caps);
+            }
+        }
+        None
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, Box<dyn Error>> {
+        let strings = |key: &str| -> Result<Vec<String>, Box<dyn Error>> {
+            value[key]
+                .as_array()
+                .ok_or(format!("Missing '{}' list", key))?
+                .iter()
+                .map(|v| v.as_str().map(String::from).ok_or_else(|| format!("Invalid '{}' entry", key).into()))
+                .collect()
+        };
+        let container_versions = value["container_versions"]
+            .as_array()
+            .ok_or("Missing 'container_versions' list")?
+            .iter()
+            .map(|v| v.as_u64().and_then(|n| u8::try_from(n).ok()).ok_or("Invalid container version"))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Capabilities {
+            target: value["target"].as_str().ok_or("Missing 'target'")?.to_string(),
+            container_versions,
+            ciphers: strings("ciphers")?,
+            features: strings("features")?,
+        })
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "target": self.target,
+            "container_versions": self.container_versions,
+            "ciphers": self.ciphers,
+            "features": self.features,
+        })
+    }
+
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    /// Requirements of a build that this stub does not meet, as readable
+    /// phrases; empty if the stub can handle it.
+    pub fn missing_for(&self, header: &Header, cipher: &str) -> Vec<String> {
+        let mut missing = Vec::new();
+        if !self.container_versions.contains(&HEADER_VERSION) {
+            missing.push(format!("container version {}", HEADER_VERSION));
+        }
+        if !self.ciphers.iter().any(|c| c == cipher) {
+            missing.push(format!("cipher {}", cipher));
+        }
+        for feature in required_features(header) {
+            if !self.has_feature(feature) {
+                missing.push(format!("feature {}", feature));
+            }
+        }
+        missing
+    }
+}
+
+/// Stub features needed to enforce what `header` asks for.
+pub fn required_features(header: &Header) -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if header.not_before.is_some() || header.not_after.is_some() {
+        features.push("validity-window");
+    }
+    if header.max_launches.is_some() || header.max_instances.is_some() {
+        features.push("launch-limits");
+    }
+    if !header.policy.is_empty() {
+        features.push("launch-policy");
+    }
+    if !header.anti_debug.is_empty() {
+        features.push("anti-debug");
+    }
+    if header.manifest_arg.is_some() {
+        features.push("manifest");
+    }
+    if header.compression.is_some() {
+        features.push("compression");
+    }
+    if header.libraries > 0 {
+        features.push("bundle-libs");
+    }
+    if header.interpreter.is_some() {
+        features.push("scripts");
+    }
+    features
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current() -> Capabilities {
+        Capabilities::find_in(capability_record!("x86_64-unknown-linux-musl").as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let caps = current();
+        assert_eq!(caps.target, "x86_64-unknown-linux-musl");
+        assert_eq!(caps.container_versions, vec![HEADER_VERSION]);
+        assert!(caps.ciphers.iter().any(|c| c == CIPHER_AES_256_GCM));
+        assert_eq!(Capabilities::from_json(&caps.to_json()).unwrap(), caps);
+    }
+
+    #[test]
+    fn test_find_skips_stray_markers() {
+        let mut image = b"code --SBB_CAPS-- data --SBB_CAPS_END-- more".to_vec();
+        image.extend_from_slice(capability_record!("aarch64-unknown-linux-gnu").as_bytes());
+        assert_eq!(Capabilities::find_in(&image).unwrap().target, "aarch64-unknown-linux-gnu");
+        assert_eq!(Capabilities::find_in(b"old stub without a record"), None);
+    }
+
+    #[test]
+    fn test_missing_requirements() {
+        let mut caps = current();
+        caps.features.retain(|f| f != "launch-limits");
+        caps.ciphers.retain(|c| c == CIPHER_AES_256_GCM);
+        let mut header = Header::new();
+        assert!(caps.missing_for(&header, CIPHER_AES_256_GCM).is_empty());
+
+        header.max_launches = Some(3);
+        assert_eq!(
+            caps.missing_for(&header, "aes-256-gcm-siv"),
+            vec!["cipher aes-256-gcm-siv", "feature launch-limits"]
+        );
+    }
+}
@@ -0,0 +1,196 @@
+//! Compression of the program before encryption.
+//!
+//! The codec and the size of the original program are recorded in the
+//! container header; the stub decompresses straight into the file it
+//! executes, so the whole program is never held in memory twice.
+
+use std::io::{self, Read, Write};
+
+/// Compression codec of a payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Zstd,
+    Lz4,
+    Xz,
+}
+
+impl Codec {
+    pub const ALL: [Codec; 3] = [Codec::Zstd, Codec::Lz4, Codec::Xz];
+
+    pub fn id(self) -> u8 {
+        match self {
+            Codec::Zstd => 1,
+            Codec::Lz4 => 2,
+            Codec::Xz => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.id() == id)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Zstd => "zstd",
+            Codec::Lz4 => "lz4",
+            Codec::Xz => "xz",
+        }
+    }
+
+    /// Accepted compression levels, or `None` for a codec without levels.
+    fn levels(self) -> Option<(u32, u32)> {
+        match self {
+            Codec::Zstd => Some((1, 22)),
+            Codec::Lz4 => None,
+            Codec::Xz => Some((0, 9)),
+        }
+    }
+
+    fn default_level(self) -> u32 {
+        match self {
+            Codec::Zstd => 3,
+            Codec::Lz4 => 0,
+            Codec::Xz => 6,
+        }
+    }
+}
+
+/// Codec and level chosen for a build, parsed from `<codec>[:<level>]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Setting {
+    pub codec: Codec,
+    pub level: u32,
+}
+
+pub fn parse_setting(s: &str) -> Result<Setting, String> {
+    let (name, level) = match s.split_once(':') {
+        Some((name, level)) => (name, Some(level)),
+        None => (s, None),
+    };
+    let codec = Codec::ALL.into_iter().find(|c| c.name() == name).ok_or_else(|| {
+        let names: Vec<_> = Codec::ALL.iter().map(|c| c.name()).collect();
+        format!("Unknown compression '{}' (supported: {})", name, names.join(", "))
+    })?;
+    let level = match (level, codec.levels()) {
+        (None, _) => codec.default_level(),
+        (Some(_), None) => return Err(format!("{} has no compression levels", name)),
+        (Some(level), Some((min, max))) => match level.parse() {
+            Ok(n) if (min..=max).contains(&n) => n,
+            _ => return Err(format!("{} level must be between {} and {}, got '{}'", name, min, max, level)),
+        },
+    };
+    Ok(Setting { codec, level })
+}
+
+/// Header record of a compressed payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub codec: Codec,
+    /// Size of the program before compression.
+    pub original_size: u64,
+}
+
+pub fn compress(setting: Setting, data: &[u8]) -> io::Result<Vec<u8>> {
+    match setting.codec {
+        Codec::Zstd => zstd::encode_all(data, setting.level as i32),
+        Codec::Lz4 => {
+            let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+            encoder.write_all(data)?;
+            encoder.finish().map_err(io::Error::other)
+        }
+        Codec::Xz => {
+            let mut encoder = xz2::write::XzEncoder::new(Vec::new(), setting.level);
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+    }
+}
+
+/// Reader over the plaintext held in `payload`, decompressing it on the way
+/// if `compression` is set. Reading fails if the result does not have the
+/// recorded size.
+pub fn reader<'a>(compression: Option<&Compression>, payload: &'a [u8]) -> io::Result<Box<dyn Read + 'a>> {
+    let Some(compression) = compression else { return Ok(Box::new(payload)) };
+    let inner: Box<dyn Read + 'a> = match compression.codec {
+        Codec::Zstd => Box::new(zstd::Decoder::new(payload)?),
+        Codec::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(payload)),
+        Codec::Xz => Box::new(xz2::read::XzDecoder::new(payload)),
+    };
+    Ok(Box::new(Exact { inner, expected: compression.original_size, read: 0 }))
+}
+
+/// Writes the plaintext held in `payload` to `out`, see [`reader`].
+pub fn write_program(compression: Option<&Compression>, payload: &[u8], out: &mut impl Write) -> io::Result<()> {
+    io::copy(&mut reader(compression, payload)?, out)?;
+    Ok(())
+}
+
+// Fails reads that end before or after the recorded size
+struct Exact<R> {
+    inner: R,
+    expected: u64,
+    read: u64,
+}
+
+impl<R: Read> Read for Exact<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let wrong_size = |read: u64, expected: u64| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("decompressed {} bytes, expected {}", read, expected))
+        };
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.read == self.expected {
+            // Look one byte past the recorded size, so a longer stream is noticed
+            return match self.inner.read(&mut [0])? {
+                0 => Ok(0),
+                _ => Err(wrong_size(self.read + 1, self.expected)),
+            };
+        }
+        let limit = buf.len().min(usize::try_from(self.expected - self.read).unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..limit])?;
+        if n == 0 {
+            return Err(wrong_size(self.read, self.expected));
+        }
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+/// [`write_program`] into memory.
+pub fn program(compression: Option<&Compression>, payload: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    write_program(compression, payload, &mut out)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_each_codec() {
+        let data: Vec<u8> = (0..100_000u32).flat_map(|i| (i % 251).to_le_bytes()).collect();
+        for codec in Codec::ALL {
+            let setting = parse_setting(codec.name()).unwrap();
+            let compressed = compress(setting, &data).unwrap();
+            assert!(compressed.len() < data.len() / 4, "{}", codec.name());
+            let record = Compression { codec, original_size: data.len() as u64 };
+            assert_eq!(program(Some(&record), &compressed).unwrap(), data);
+
+            let wrong_size = Compression { codec, original_size: 10 };
+            assert!(program(Some(&wrong_size), &compressed).is_err());
+            assert_eq!(Codec::from_id(codec.id()), Some(codec));
+        }
+        assert_eq!(program(None, b"plain").unwrap(), b"plain");
+    }
+
+    #[test]
+    fn test_parse_setting() {
+        assert_eq!(parse_setting("zstd:19").unwrap(), Setting { codec: Codec::Zstd, level: 19 });
+        assert_eq!(parse_setting("xz").unwrap(), Setting { codec: Codec::Xz, level: 6 });
+        assert!(parse_setting("zstd:23").is_err());
+        assert!(parse_setting("lz4:9").unwrap_err().contains("no compression levels"));
+        assert!(parse_setting("rar").unwrap_err().contains("supported: zstd, lz4, xz"));
+    }
+}
@@ -1,56 +1,190 @@
-use aes_gcm::{Aes256Gcm, KeyInit, aead::{Aead, generic_array::GenericArray}};
+use aes_gcm::{Aes256Gcm, KeyInit, aead::{Aead, Payload, generic_array::GenericArray}};
+use aes_gcm_siv::Aes256GcmSiv;
+use hmac::{Hmac, Mac};
 use rand::Rng;
 use sha2::{Digest, Sha256};
 
+use crate::capabilities::{CIPHER_AES_256_GCM, CIPHER_AES_256_GCM_SIV};
+
+/// Cipher suite of a container, recorded in its header.
+///
+/// `Aes256Gcm` keys AES-GCM with SHA-256 of the fingerprint and stores
+/// `nonce + ciphertext`. `Aes256GcmSiv` derives the key with HMAC-SHA256
+/// under a per-build salt and stores `salt + nonce + ciphertext`; being
+/// misuse resistant, it can take a nonce derived from the build inputs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Cipher {
+    #[default]
+    Aes256Gcm,
+    Aes256GcmSiv,
+}
+
+impl Cipher {
+    pub const ALL: [Cipher; 2] = [Cipher::Aes256Gcm, Cipher::Aes256GcmSiv];
+
+    pub fn id(self) -> u8 {
+        match self {
+            Cipher::Aes256Gcm => 0,
+            Cipher::Aes256GcmSiv => 1,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.id() == id)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Cipher::Aes256Gcm => CIPHER_AES_256_GCM,
+            Cipher::Aes256GcmSiv => CIPHER_AES_256_GCM_SIV,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+
+    /// How the cipher key is derived from the fingerprint.
+    pub fn kdf(self) -> &'static str {
+        match self {
+            Cipher::Aes256Gcm => "sha256",
+            Cipher::Aes256GcmSiv => "hmac-sha256",
+        }
+    }
+
+    /// Bytes [`encrypt`] adds to the plaintext: salt, nonce and tag.
+    pub fn overhead(self) -> usize {
+        match self {
+            Cipher::Aes256Gcm => 12 + 16,
+            Cipher::Aes256GcmSiv => 16 + 12 + 16,
+        }
+    }
+}
+
+/// Salt and nonce of one encryption. The salt is only used by
+/// [`Cipher::Aes256GcmSiv`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Seal {
+    pub salt: [u8; 16],
+    pub nonce: [u8; 12],
+}
+
+impl Seal {
+    pub fn random() -> Self {
+        let mut rng = rand::rng();
+        let mut seal = Seal { salt: [0; 16], nonce: [0; 12] };
+        rng.fill(&mut seal.salt);
+        rng.fill(&mut seal.nonce);
+        seal
+    }
+
+    /// Takes the salt and nonce from a 32-byte seed, for reproducible builds.
+    pub fn from_seed(seed: &[u8; 32]) -> Self {
+        Seal {
+            salt: seed[..16].try_into().unwrap(),
+            nonce: seed[16..28].try_into().unwrap(),
+        }
+    }
+}
+
+/// Encrypts `data` with `cipher`, authenticating `aad`.
+pub fn encrypt(cipher: Cipher, fingerprint: &str, data: &[u8], aad: &[u8], seal: &Seal) -> Option<Vec<u8>> {
+    let payload = Payload { msg: data, aad };
+    let nonce = GenericArray::from_slice(&seal.nonce);
+    let (prefix, ciphertext) = match cipher {
+        Cipher::Aes256Gcm => {
+            let key = Sha256::digest(fingerprint.as_bytes());
+            (Vec::new(), Aes256Gcm::new(&key).encrypt(nonce, payload).ok()?)
+        }
+        Cipher::Aes256GcmSiv => {
+            let key = salted_key(&seal.salt, fingerprint);
+            (seal.salt.to_vec(), Aes256GcmSiv::new(&key.into()).encrypt(nonce, payload).ok()?)
+        }
+    };
+    Some([prefix, seal.nonce.to_vec(), ciphertext].concat())
+}
+
+/// Decrypts data produced by [`encrypt`] with the same cipher and `aad`.
+pub fn decrypt(cipher: Cipher, fingerprint: &str, encrypted: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+    match cipher {
+        Cipher::Aes256Gcm => {
+            if encrypted.len() < 12 {
+                return None;
+            }
+            let (nonce, msg) = encrypted.split_at(12);
+            let key = Sha256::digest(fingerprint.as_bytes());
+            Aes256Gcm::new(&key).decrypt(GenericArray::from_slice(nonce), Payload { msg, aad }).ok()
+        }
+        Cipher::Aes256GcmSiv => {
+            if encrypted.len() < 28 {
+                return None;
+            }
+            let (salt, rest) = encrypted.split_at(16);
+            let (nonce, msg) = rest.split_at(12);
+            let key = salted_key(salt.try_into().ok()?, fingerprint);
+            Aes256GcmSiv::new(&key.into()).decrypt(GenericArray::from_slice(nonce), Payload { msg, aad }).ok()
+        }
+    }
+}
+
+fn salted_key(salt: &[u8; 16], fingerprint: &str) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(salt).expect("HMAC accepts any key length");
+    mac.update(fingerprint.as_bytes());
+    mac.finalize().into_bytes().into()
+}
+
 /// Encrypts the binary using the fingerprint as the key base.
 /// Returns `nonce + ciphertext` as a vector of bytes.
 pub fn encrypt_binary(fingerprint: &str, data: &[u8]) -> Option<Vec<u8>> {
-    // Hash the fingerprint to get a 256-bit key
-    let mut hasher = Sha256::new();
-    hasher.update(fingerprint.as_bytes());
-    let key_bytes = hasher.finalize();
-
-    let key = GenericArray::from_slice(&key_bytes);
-    let cipher = Aes256Gcm::new(key);
-
-    // Generate a random 96-bit nonce
-    let mut nonce_bytes = [0u8; 12];
-    rand::rng().fill(&mut nonce_bytes);
-    let nonce = GenericArray::from_slice(&nonce_bytes);
-
-    // Encrypt the binary data
-    match cipher.encrypt(nonce, data) {
-        Ok(mut ciphertext) => {
-            // Prepend nonce to the encrypted data
-            let mut output = nonce_bytes.to_vec();
-            output.append(&mut ciphertext);
-            Some(output)
-        }
-        Err(_) => None,
-    }
+    encrypt_binary_with_aad(fingerprint, data, &[])
+}
+
+/// Like [`encrypt_binary`], additionally authenticating `aad` (the container
+/// header) so it cannot be altered without breaking decryption.
+pub fn encrypt_binary_with_aad(fingerprint: &str, data: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+    encrypt(Cipher::Aes256Gcm, fingerprint, data, aad, &Seal::random())
 }
 
 /// Decrypts a binary using fingerprint-based key.
 /// Takes `nonce + ciphertext`, returns decrypted data or None.
 pub fn decrypt_binary(fingerprint: &str, encrypted: &[u8]) -> Option<Vec<u8>> {
-    if encrypted.len() < 12 {
-        return None;
-    }
-
-    let nonce = GenericArray::from_slice(&encrypted[0..12]);
-    let ciphertext = &encrypted[12..];
+    decrypt_binary_with_aad(fingerprint, encrypted, &[])
+}
 
-    let mut hasher = Sha256::new();
-    hasher.update(fingerprint.as_bytes());
-    let key_bytes = hasher.finalize();
+/// Decrypts data produced by [`encrypt_binary_with_aad`] with the same `aad`.
+pub fn decrypt_binary_with_aad(fingerprint: &str, encrypted: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+    decrypt(Cipher::Aes256Gcm, fingerprint, encrypted, aad)
+}
 
-    let key = GenericArray::from_slice(&key_bytes);
-    let cipher = Aes256Gcm::new(key);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FP: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn test_roundtrip_each_cipher() {
+        for cipher in Cipher::ALL {
+            let encrypted = encrypt(cipher, FP, b"program", b"header", &Seal::random()).unwrap();
+            assert_eq!(decrypt(cipher, FP, &encrypted, b"header").as_deref(), Some(&b"program"[..]));
+            assert_eq!(decrypt(cipher, FP, &encrypted, b"other header"), None);
+            assert_eq!(decrypt(cipher, "other key", &encrypted, b"header"), None);
+            assert_eq!(Cipher::from_name(cipher.name()), Some(cipher));
+            assert_eq!(encrypted.len(), b"program".len() + cipher.overhead());
+        }
+        assert_eq!(decrypt(Cipher::Aes256GcmSiv, FP, &[0; 20], b""), None);
+    }
 
-    cipher.decrypt(nonce, ciphertext).ok()
+    #[test]
+    fn test_seeded_encryption_is_deterministic() {
+        let seal = Seal::from_seed(&[7; 32]);
+        let a = encrypt(Cipher::Aes256GcmSiv, FP, b"program", b"header", &seal).unwrap();
+        let b = encrypt(Cipher::Aes256GcmSiv, FP, b"program", b"header", &seal).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(&a[..16], &seal.salt);
+    }
 }
 
-
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
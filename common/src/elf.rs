@@ -0,0 +1,303 @@
+//! Minimal ELF reader: the file header and program headers.
+
+use std::error::Error;
+
+use sha2::{Digest, Sha256};
+
+pub const ELF_MAGIC: &[u8] = b"\x7fELF";
+
+pub const PT_LOAD: u32 = 1;
+pub const PT_DYNAMIC: u32 = 2;
+pub const PT_INTERP: u32 = 3;
+
+pub const ET_EXEC: u16 = 2;
+pub const ET_DYN: u16 = 3;
+
+pub const DT_NULL: u64 = 0;
+pub const DT_NEEDED: u64 = 1;
+pub const DT_STRTAB: u64 = 5;
+pub const DT_SONAME: u64 = 14;
+pub const DT_RPATH: u64 = 15;
+pub const DT_RUNPATH: u64 = 29;
+pub const DT_FLAGS_1: u64 = 0x6fff_fffb;
+pub const DF_1_PIE: u64 = 0x0800_0000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Elf {
+    pub is_64: bool,
+    pub little_endian: bool,
+    pub e_type: u16,
+    pub e_machine: u16,
+    pub program_headers: Vec<ProgramHeader>,
+}
+
+impl Elf {
+    pub fn parse(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if !data.starts_with(ELF_MAGIC) || data.len() < 0x34 {
+            return Err("Not an ELF file".into());
+        }
+        let is_64 = match data[4] {
+            1 => false,
+            2 => true,
+            _ => return Err("Invalid ELF class".into()),
+        };
+        let little_endian = match data[5] {
+            1 => true,
+            2 => false,
+            _ => return Err("Invalid ELF data encoding".into()),
+        };
+        let r = Reader { data, little_endian };
+
+        let e_type = r.u16(0x10)?;
+        let e_machine = r.u16(0x12)?;
+        let (phoff, phentsize, phnum) = if is_64 {
+            (r.u64(0x20)?, r.u16(0x36)?, r.u16(0x38)?)
+        } else {
+            (r.u32(0x1c)? as u64, r.u16(0x2a)?, r.u16(0x2c)?)
+        };
+
+        let mut program_headers = Vec::with_capacity(phnum as usize);
+        for i in 0..phnum as u64 {
+            let base = phoff
+                .checked_add(i * phentsize as u64)
+                .and_then(|b| usize::try_from(b).ok())
+                .ok_or("Program header out of range")?;
+            let ph = if is_64 {
+                ProgramHeader {
+                    p_type: r.u32(base)?,
+                    flags: r.u32(base + 4)?,
+                    offset: r.u64(base + 8)?,
+                    vaddr: r.u64(base + 16)?,
+                    filesz: r.u64(base + 32)?,
+                    memsz: r.u64(base + 40)?,
+                }
+            } else {
+                ProgramHeader {
+                    p_type: r.u32(base)?,
+                    offset: r.u32(base + 4)? as u64,
+                    vaddr: r.u32(base + 8)? as u64,
+                    filesz: r.u32(base + 16)? as u64,
+                    memsz: r.u32(base + 20)? as u64,
+                    flags: r.u32(base + 24)?,
+                }
+            };
+            program_headers.push(ph);
+        }
+
+        Ok(Elf { is_64, little_endian, e_type, e_machine, program_headers })
+    }
+
+    /// Architecture name as used in Rust target triples, for the machines
+    /// stubs are built for.
+    pub fn arch(&self) -> Option<&'static str> {
+        match (self.e_machine, self.is_64) {
+            (3, false) => Some("i686"),
+            (40, false) => Some("arm"),
+            (62, true) => Some("x86_64"),
+            (183, true) => Some("aarch64"),
+            (243, true) => Some("riscv64gc"),
+            _ => None,
+        }
+    }
+
+    /// Path of the program interpreter (dynamic loader), if any.
+    pub fn interpreter(&self, data: &[u8]) -> Option<String> {
+        let ph = self.program_headers.iter().find(|ph| ph.p_type == PT_INTERP)?;
+        let bytes = self.segment(data, ph)?;
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8(bytes[..end].to_vec()).ok()
+    }
+
+    /// `(tag, value)` pairs of the dynamic section, up to `DT_NULL`.
+    pub fn dynamic_entries(&self, data: &[u8]) -> Vec<(u64, u64)> {
+        let Some(bytes) = self
+            .program_headers
+            .iter()
+            .find(|ph| ph.p_type == PT_DYNAMIC)
+            .and_then(|ph| self.segment(data, ph))
+        else {
+            return Vec::new();
+        };
+        let r = Reader { data: bytes, little_endian: self.little_endian };
+        let size = if self.is_64 { 16 } else { 8 };
+        let mut entries = Vec::new();
+        for at in (0..bytes.len() / size).map(|i| i * size) {
+            let entry = if self.is_64 {
+                r.u64(at).and_then(|tag| Ok((tag, r.u64(at + 8)?)))
+            } else {
+                r.u32(at).and_then(|tag| Ok((tag as u64, r.u32(at + 4)? as u64)))
+            };
+            match entry {
+                Ok((DT_NULL, _)) | Err(_) => break,
+                Ok(entry) => entries.push(entry),
+            }
+        }
+        entries
+    }
+
+    /// Strings of the dynamic entries with `tag`, such as the `DT_NEEDED`
+    /// library names, in file order.
+    pub fn dynamic_strings(&self, data: &[u8], tag: u64) -> Vec<String> {
+        let entries = self.dynamic_entries(data);
+        let Some(strtab) = entries
+            .iter()
+            .find(|&&(t, _)| t == DT_STRTAB)
+            .and_then(|&(_, addr)| self.file_offset(addr))
+        else {
+            return Vec::new();
+        };
+        entries
+            .iter()
+            .filter(|&&(t, _)| t == tag)
+            .filter_map(|&(_, value)| {
+                let start = usize::try_from(strtab.checked_add(value)?).ok()?;
+                let bytes = data.get(start..)?;
+                let end = bytes.iter().position(|&b| b == 0)?;
+                String::from_utf8(bytes[..end].to_vec()).ok()
+            })
+            .collect()
+    }
+
+    /// File offset of a virtual address inside a `PT_LOAD` segment.
+    pub fn file_offset(&self, addr: u64) -> Option<u64> {
+        self.program_headers
+            .iter()
+            .find(|ph| ph.p_type == PT_LOAD && addr >= ph.vaddr && addr - ph.vaddr < ph.filesz)
+            .map(|ph| ph.offset + (addr - ph.vaddr))
+    }
+
+    /// Whether this is a shared library rather than a (possibly
+    /// position-independent) executable.
+    pub fn is_shared_library(&self, data: &[u8]) -> bool {
+        self.e_type == ET_DYN
+            && !self.program_headers.iter().any(|ph| ph.p_type == PT_INTERP)
+            && !self
+                .dynamic_entries(data)
+                .iter()
+                .any(|&(tag, value)| tag == DT_FLAGS_1 && value & DF_1_PIE != 0)
+    }
+
+    /// File bytes covered by a program header, if they lie within `data`.
+    pub fn segment<'a>(&self, data: &'a [u8], ph: &ProgramHeader) -> Option<&'a [u8]> {
+        let start = usize::try_from(ph.offset).ok()?;
+        let end = start.checked_add(usize::try_from(ph.filesz).ok()?)?;
+        data.get(start..end)
+    }
+}
+
+/// SHA-256 over the file contents of every `PT_LOAD` segment, in program
+/// header order, each prefixed with its offset and size. Data appended
+/// after the image (such as embedded payloads) does not affect the result.
+pub fn measure_load_segments(data: &[u8]) -> Result<[u8; 32], Box<dyn Error>> {
+    let elf = Elf::parse(data)?;
+    let mut hasher = Sha256::new();
+    let mut loads = 0;
+    for ph in elf.program_headers.iter().filter(|ph| ph.p_type == PT_LOAD) {
+        let bytes = elf.segment(data, ph).ok_or("PT_LOAD segment outside the file")?;
+        hasher.update(ph.offset.to_le_bytes());
+        hasher.update(ph.filesz.to_le_bytes());
+        hasher.update(bytes);
+        loads += 1;
+    }
+    if loads == 0 {
+        return Err("ELF file has no PT_LOAD segments".into());
+    }
+    Ok(hasher.finalize().into())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&self, at: usize) -> Result<[u8; N], Box<dyn Error>> {
+        let slice = at
+            .checked_add(N)
+            .and_then(|end| self.data.get(at..end))
+            .ok_or("Truncated ELF file")?;
+        Ok(slice.try_into()?)
+    }
+
+    fn u16(&self, at: usize) -> Result<u16, Box<dyn Error>> {
+        let b = self.bytes::<2>(at)?;
+        Ok(if self.little_endian { u16::from_le_bytes(b) } else { u16::from_be_bytes(b) })
+    }
+
+    fn u32(&self, at: usize) -> Result<u32, Box<dyn Error>> {
+        let b = self.bytes::<4>(at)?;
+        Ok(if self.little_endian { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) })
+    }
+
+    fn u64(&self, at: usize) -> Result<u64, Box<dyn Error>> {
+        let b = self.bytes::<8>(at)?;
+        Ok(if self.little_endian { u64::from_le_bytes(b) } else { u64::from_be_bytes(b) })
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    fn own_image() -> Vec<u8> {
+        std::fs::read("/proc/self/exe").unwrap()
+    }
+
+    #[test]
+    fn test_parse_own_image() {
+        let elf = Elf::parse(&own_image()).unwrap();
+        assert!(elf.program_headers.iter().any(|ph| ph.p_type == PT_LOAD));
+    }
+
+    #[test]
+    fn test_measurement_ignores_appended_data() {
+        let mut image = own_image();
+        let before = measure_load_segments(&image).unwrap();
+        image.extend_from_slice(b"--EMBED_START--payload--EMBED_END--");
+        assert_eq!(measure_load_segments(&image).unwrap(), before);
+    }
+
+    #[test]
+    fn test_measurement_detects_patch() {
+        let mut image = own_image();
+        let before = measure_load_segments(&image).unwrap();
+        let elf = Elf::parse(&image).unwrap();
+        let text = elf
+            .program_headers
+            .iter()
+            .find(|ph| ph.p_type == PT_LOAD && ph.flags & 1 != 0)
+            .unwrap();
+        let at = (text.offset + text.filesz / 2) as usize;
+        image[at] ^= 0xff;
+        assert_ne!(measure_load_segments(&image).unwrap(), before);
+    }
+
+    #[test]
+    fn test_own_image_is_an_executable() {
+        let image = own_image();
+        let elf = Elf::parse(&image).unwrap();
+        assert!(elf.arch().is_some());
+        assert!(!elf.is_shared_library(&image));
+        if let Some(interp) = elf.interpreter(&image) {
+            assert!(interp.starts_with('/'));
+            assert!(!elf.dynamic_entries(&image).is_empty());
+            assert!(elf.dynamic_strings(&image, DT_NEEDED).iter().any(|n| n.starts_with("libc.so")));
+        }
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        assert!(measure_load_segments(b"MZ not an elf").is_err());
+        assert!(measure_load_segments(b"\x7fELF\x02\x01").is_err());
+    }
+}
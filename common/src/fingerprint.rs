@@ -3,33 +3,76 @@ use sysinfo::{System, Networks};
 use rand::Rng;
 
 
-pub fn generate_fingerprint() -> String {
-    let mut system = System::new_all();
-    system.refresh_all();
+/// Names of the machine properties recorded for diagnostics, in display order.
+pub const COMPONENT_NAMES: [&str; 4] = ["hostname", "mac", "cpu", "machine-id"];
 
-    let mut raw_data = String::new();
+/// Raw machine properties the fingerprint is derived from.
+///
+/// `machine_id` is only used for diagnostics; it is not part of the
+/// fingerprint so that existing keys stay valid.
+#[derive(Debug, Clone, Default)]
+pub struct Components {
+    pub cpu: Option<String>,
+    pub hostname: Option<String>,
+    pub mac: Option<String>,
+    pub machine_id: Option<String>,
+}
 
-    // Add CPU brand
-    if let Some(cpu) = system.cpus().get(0) {
-        raw_data += cpu.brand();
+impl Components {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        match name {
+            "hostname" => self.hostname.as_deref(),
+            "mac" => self.mac.as_deref(),
+            "cpu" => self.cpu.as_deref(),
+            "machine-id" => self.machine_id.as_deref(),
+            _ => None,
+        }
     }
+}
 
-    // Add hostname
-    if let Some(name) = System::host_name() {
-        raw_data += &name;
-    }
+pub fn collect_components() -> Components {
+    let mut system = System::new_all();
+    system.refresh_all();
+
+    let mut components = Components {
+        cpu: system.cpus().first().map(|cpu| cpu.brand().to_string()),
+        hostname: System::host_name(),
+        ..Default::default()
+    };
 
-    // Add first valid MAC address that is not all zeros
+    // First valid MAC address that is not all zeros, by interface name so
+    // the choice does not depend on hash map iteration order
     let networks = Networks::new_with_refreshed_list();
-    for (_name, net) in networks.iter() {
-        let mac = net.mac_address();
-        let mac_str = mac.to_string();
+    let mut interfaces: Vec<_> = networks.iter().collect();
+    interfaces.sort_by(|a, b| a.0.cmp(b.0));
+    for (_name, net) in interfaces {
+        let mac_str = net.mac_address().to_string();
         if mac_str != "00:00:00:00:00:00" {
-            raw_data += &mac_str;
+            components.mac = Some(mac_str);
             break;
         }
     }
 
+    components.machine_id = ["/etc/machine-id", "/var/lib/dbus/machine-id"]
+        .iter()
+        .find_map(|p| std::fs::read_to_string(p).ok())
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty());
+
+    components
+}
+
+pub fn generate_fingerprint() -> String {
+    fingerprint_from(&collect_components())
+}
+
+/// Derives the fingerprint: SHA-256 over CPU brand, hostname and MAC.
+pub fn fingerprint_from(components: &Components) -> String {
+    let mut raw_data = String::new();
+    for part in [&components.cpu, &components.hostname, &components.mac].into_iter().flatten() {
+        raw_data += part;
+    }
+
     // fallback
     if raw_data.is_empty() {
         raw_data = "fallback".to_string(); // prevent hashing empty data
@@ -41,12 +84,206 @@ pub fn generate_fingerprint() -> String {
 
 
 pub fn generate_random_key() -> String {
-    println!("[*] Generating random encryption key...");
+    crate::log_debug!("Generating random encryption key");
     let mut rng = rand::rng();
-    
+
     let random_bytes: Vec<u8> = (0..128).map(|_| rng.random()).collect();
     let hash = Sha256::digest(&random_bytes);
-    let fp = hex::encode(hash);
-    println!("random fp is {}",fp);
-    fp
-}
\ No newline at end of file
+    hex::encode(hash)
+}
+
+/// Check if a string looks like a valid fingerprint
+pub fn is_valid_fingerprint(s: &str) -> bool {
+    // Check if it's a valid hex string (for SHA256-based fingerprints)
+    if s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return true;
+    }
+    
+    // Check if it's a reasonable fingerprint format (alphanumeric with some special chars)
+    if s.len() >= 16 && s.len() <= 128 && 
+       s.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == ':') {
+        return true;
+    }
+    
+    false
+}
+
+/// Short, non-reversible identifier of a key, safe to show in logs.
+pub fn key_id(fingerprint: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"sbb-key-id");
+    hasher.update(fingerprint.as_bytes());
+    hex::encode(&hasher.finalize()[..8])
+}
+
+/// Salted hashes of individual components, so a stub can tell which one
+/// changed without the build carrying the raw values.
+///
+/// The CPU, hostname and MAC also make up the key, and each has few enough
+/// likely values to be guessed on its own. The hashes are therefore slow to
+/// compute, and builds only carry them when asked to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ComponentHashes {
+    pub salt: [u8; 16],
+    pub hashes: Vec<(String, [u8; 32])>,
+}
+
+impl ComponentHashes {
+    pub fn from_components(components: &Components) -> Self {
+        let salt: [u8; 16] = rand::random();
+        let hashes = COMPONENT_NAMES
+            .iter()
+            .filter_map(|&name| {
+                let value = components.get(name)?;
+                Some((name.to_string(), component_hash(&salt, name, value)))
+            })
+            .collect();
+        ComponentHashes { salt, hashes }
+    }
+
+    /// Compares against the current machine: `Some(true)` if the component
+    /// matches, `Some(false)` if it differs, `None` if it was not recorded
+    /// or cannot be read here.
+    pub fn matches(&self, name: &str, components: &Components) -> Option<bool> {
+        let (_, expected) = self.hashes.iter().find(|(n, _)| n == name)?;
+        let value = components.get(name)?;
+        Some(component_hash(&self.salt, name, value) == *expected)
+    }
+}
+
+/// Argon2id memory cost of a component hash, in KiB.
+const COMPONENT_HASH_MEMORY: u32 = 64 * 1024;
+/// Argon2id passes over that memory.
+const COMPONENT_HASH_PASSES: u32 = 3;
+
+/// Argon2id of `name` and `value`, so each guess at a value costs 64 MiB
+/// and a noticeable fraction of a second.
+pub fn component_hash(salt: &[u8; 16], name: &str, value: &str) -> [u8; 32] {
+    let params = argon2::Params::new(COMPONENT_HASH_MEMORY, COMPONENT_HASH_PASSES, 1, Some(32))
+        .expect("valid Argon2 parameters");
+    let argon2 = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+    let input = [name.as_bytes(), &[0], value.as_bytes()].concat();
+    let mut hash = [0; 32];
+    argon2.hash_password_into(&input, salt, &mut hash).expect("valid Argon2 input");
+    hash
+}
+
+/// Contents of a `key.txt`: the fingerprint on the first line, optionally
+/// followed by `name=value` lines with the salted component hashes. Key
+/// files from before the hashes were slow have a `salt` line instead of
+/// `argon2-salt`; their hashes are ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyFile {
+    pub fingerprint: String,
+    pub components: Option<ComponentHashes>,
+}
+
+impl KeyFile {
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let mut lines = contents.lines();
+        let fingerprint = lines
+            .next()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .ok_or("Key file is empty")?
+            .to_string();
+
+        let mut salt = None;
+        let mut legacy = false;
+        let mut hashes = Vec::new();
+        for line in lines.map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let (name, value) = line
+                .split_once('=')
+                .ok_or("Key file should contain the key on its first line and name=value lines after it")?;
+            let bytes = hex::decode(value).map_err(|_| format!("Invalid hex value for '{}' in key file", name))?;
+            if name == "argon2-salt" {
+                salt = Some(bytes.try_into().map_err(|_| "Invalid salt in key file")?);
+            } else if name == "salt" {
+                legacy = true;
+            } else if COMPONENT_NAMES.contains(&name) {
+                let hash = bytes.try_into().map_err(|_| format!("Invalid hash for '{}' in key file", name))?;
+                hashes.push((name.to_string(), hash));
+            } else {
+                return Err(format!("Unknown entry '{}' in key file", name));
+            }
+        }
+
+        let components = match (salt, hashes.is_empty()) {
+            _ if legacy => None,
+            (Some(salt), _) => Some(ComponentHashes { salt, hashes }),
+            (None, true) => None,
+            (None, false) => return Err("Key file has component hashes but no salt".into()),
+        };
+        Ok(KeyFile { fingerprint, components })
+    }
+
+    pub fn to_file_string(&self) -> String {
+        let mut out = format!("{}\n", self.fingerprint);
+        if let Some(c) = &self.components {
+            out += "# Argon2id hashes of the components, for builds with --record-components\n";
+            out += &format!("argon2-salt={}\n", hex::encode(c.salt));
+            for (name, hash) in &c.hashes {
+                out += &format!("{}={}\n", name, hex::encode(hash));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn components() -> Components {
+        Components {
+            cpu: Some("Example CPU @ 3.00GHz".into()),
+            hostname: Some("build-01".into()),
+            mac: Some("02:42:ac:11:00:02".into()),
+            machine_id: Some("0123456789abcdef0123456789abcdef".into()),
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_excludes_machine_id() {
+        let mut other = components();
+        other.machine_id = None;
+        assert_eq!(fingerprint_from(&components()), fingerprint_from(&other));
+    }
+
+    #[test]
+    fn test_component_matching() {
+        let hashes = ComponentHashes::from_components(&components());
+        let mut moved = components();
+        moved.hostname = Some("build-02".into());
+        moved.machine_id = None;
+
+        assert_eq!(hashes.matches("cpu", &moved), Some(true));
+        assert_eq!(hashes.matches("mac", &moved), Some(true));
+        assert_eq!(hashes.matches("hostname", &moved), Some(false));
+        assert_eq!(hashes.matches("machine-id", &moved), None);
+    }
+
+    #[test]
+    fn test_key_file_roundtrip() {
+        let key = KeyFile {
+            fingerprint: fingerprint_from(&components()),
+            components: Some(ComponentHashes::from_components(&components())),
+        };
+        assert_eq!(KeyFile::parse(&key.to_file_string()), Ok(key));
+    }
+
+    #[test]
+    fn test_plain_key_file() {
+        let key = KeyFile::parse("abcdef\n").unwrap();
+        assert_eq!(key.fingerprint, "abcdef");
+        assert_eq!(key.components, None);
+        assert!(KeyFile::parse("").is_err());
+        assert!(KeyFile::parse("abcdef\nsecond line\n").is_err());
+    }
+
+    #[test]
+    fn test_ignores_fast_component_hashes() {
+        let old = format!("abcdef\nsalt={}\nhostname={}\n", "00".repeat(16), "11".repeat(32));
+        assert_eq!(KeyFile::parse(&old).unwrap().components, None);
+    }
+}
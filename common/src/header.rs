@@ -0,0 +1,432 @@
+//! Container header embedded in front of the encrypted payload.
+//!
+//! The header is stored in plain text so the stub can evaluate it before
+//! decryption, and its exact bytes are passed as associated data to the
+//! cipher, so any modification makes decryption fail.
+//!
+//! Layout: `SBBH` magic, a version byte, then a sequence of records of the
+//! form `tag: u8 | len: u32 (LE) | value`.
+
+use std::error::Error;
+
+use crate::antidebug::{Action, Check};
+use crate::compress::{Codec, Compression};
+use crate::crypto::Cipher;
+use crate::fingerprint::ComponentHashes;
+use crate::policy::Policy;
+use crate::validity::format_timestamp;
+
+pub const HEADER_MAGIC: &[u8] = b"SBBH";
+pub const HEADER_VERSION: u8 = 1;
+
+const TAG_BUILD_ID: u8 = 0x01;
+const TAG_NOT_BEFORE: u8 = 0x02;
+const TAG_NOT_AFTER: u8 = 0x03;
+const TAG_MAX_LAUNCHES: u8 = 0x04;
+const TAG_MAX_INSTANCES: u8 = 0x05;
+// Launch policy entries, one record per list item.
+const TAG_ALLOW_USER: u8 = 0x06;
+const TAG_ALLOW_GROUP: u8 = 0x07;
+const TAG_ALLOW_HOST: u8 = 0x08;
+const TAG_INSTALL_PATH: u8 = 0x09;
+const TAG_ALLOW_PARENT: u8 = 0x0A;
+const TAG_ANTI_DEBUG: u8 = 0x0B;
+const TAG_STUB_MEASUREMENT: u8 = 0x0C;
+const TAG_COMPONENT_SALT: u8 = 0x0D;
+// 32-byte salted hash followed by the component name.
+const TAG_COMPONENT_HASH: u8 = 0x0E;
+// Manifest fields, readable without the key.
+const TAG_PRODUCT: u8 = 0x0F;
+const TAG_VERSION: u8 = 0x10;
+const TAG_BUILD_DATE: u8 = 0x11;
+const TAG_TARGET: u8 = 0x12;
+const TAG_MANIFEST_ARG: u8 = 0x13;
+// Omitted for the original AES-256-GCM suite.
+const TAG_CIPHER: u8 = 0x14;
+// SHA-256 of the original program; only `sbb verify` reads it.
+const TAG_INPUT_HASH: u8 = 0x15;
+// Codec id followed by the original size as a u64.
+const TAG_COMPRESSION: u8 = 0x16;
+// Number of shared libraries bundled with the program, as a u32.
+const TAG_LIBRARIES: u8 = 0x17;
+// Interpreter line of a script, without the `#!`.
+const TAG_INTERPRETER: u8 = 0x18;
+// Path of the entry script inside a bundled script directory.
+const TAG_ENTRY: u8 = 0x19;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Header {
+    /// Random identifier of this build, used to key local state.
+    pub build_id: [u8; 16],
+    /// Unix time before which the payload must not be decrypted.
+    pub not_before: Option<u64>,
+    /// Unix time after which the payload must not be decrypted.
+    pub not_after: Option<u64>,
+    /// Maximum number of launches on a machine.
+    pub max_launches: Option<u32>,
+    /// Maximum number of concurrently running instances on a machine.
+    pub max_instances: Option<u32>,
+    /// Restrictions evaluated before decryption.
+    pub policy: Policy,
+    /// Instrumentation checks to run before decryption, with their response.
+    pub anti_debug: Vec<(Check, Action)>,
+    /// SHA-256 over the stub's PT_LOAD segments, taken at build time.
+    pub stub_measurement: Option<[u8; 32]>,
+    /// Salted per-component fingerprint hashes for diagnostics.
+    pub components: Option<ComponentHashes>,
+    /// Product name shown in the manifest.
+    pub product: Option<String>,
+    /// Product version shown in the manifest.
+    pub version: Option<String>,
+    /// Unix time the container was built.
+    pub build_date: Option<u64>,
+    /// Platform the stub was built for.
+    pub target: Option<String>,
+    /// Sole argument that makes the stub print the manifest instead of
+    /// running the program.
+    pub manifest_arg: Option<String>,
+    /// Cipher suite of the encrypted payload.
+    pub cipher: Cipher,
+    /// SHA-256 of the program before encryption.
+    pub input_sha256: Option<[u8; 32]>,
+    /// How the program was compressed before encryption.
+    pub compression: Option<Compression>,
+    /// Number of shared libraries encrypted along with the program.
+    pub libraries: u32,
+    /// Interpreter line of a script, which the stub runs the script with.
+    pub interpreter: Option<String>,
+    /// For a script directory, the path of the script to run inside it. The
+    /// other files of the directory are bundled with it.
+    pub entry: Option<String>,
+}
+
+impl Header {
+    /// Creates a header with a fresh random build id.
+    pub fn new() -> Self {
+        Header {
+            build_id: rand::random(),
+            ..Default::default()
+        }
+    }
+
+    /// Hex form of the build id, used for file names and display.
+    pub fn build_id_hex(&self) -> String {
+        hex::encode(self.build_id)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = HEADER_MAGIC.to_vec();
+        out.push(HEADER_VERSION);
+
+        put_record(&mut out, TAG_BUILD_ID, &self.build_id);
+        if let Some(t) = self.not_before {
+            put_record(&mut out, TAG_NOT_BEFORE, &t.to_le_bytes());
+        }
+        if let Some(t) = self.not_after {
+            put_record(&mut out, TAG_NOT_AFTER, &t.to_le_bytes());
+        }
+        if let Some(n) = self.max_launches {
+            put_record(&mut out, TAG_MAX_LAUNCHES, &n.to_le_bytes());
+        }
+        if let Some(n) = self.max_instances {
+            put_record(&mut out, TAG_MAX_INSTANCES, &n.to_le_bytes());
+        }
+
+        let policy = &self.policy;
+        for (tag, entries) in [
+            (TAG_ALLOW_USER, &policy.users),
+            (TAG_ALLOW_GROUP, &policy.groups),
+            (TAG_ALLOW_HOST, &policy.hostnames),
+            (TAG_INSTALL_PATH, &policy.install_paths),
+            (TAG_ALLOW_PARENT, &policy.parent_processes),
+        ] {
+            for entry in entries {
+                put_record(&mut out, tag, entry.as_bytes());
+            }
+        }
+        for (check, action) in &self.anti_debug {
+            put_record(&mut out, TAG_ANTI_DEBUG, &[check.id(), action.id()]);
+        }
+        if let Some(m) = &self.stub_measurement {
+            put_record(&mut out, TAG_STUB_MEASUREMENT, m);
+        }
+        if let Some(c) = &self.components {
+            put_record(&mut out, TAG_COMPONENT_SALT, &c.salt);
+            for (name, hash) in &c.hashes {
+                put_record(&mut out, TAG_COMPONENT_HASH, &[&hash[..], name.as_bytes()].concat());
+            }
+        }
+        for (tag, value) in [
+            (TAG_PRODUCT, &self.product),
+            (TAG_VERSION, &self.version),
+            (TAG_TARGET, &self.target),
+            (TAG_MANIFEST_ARG, &self.manifest_arg),
+        ] {
+            if let Some(value) = value {
+                put_record(&mut out, tag, value.as_bytes());
+            }
+        }
+        if let Some(t) = self.build_date {
+            put_record(&mut out, TAG_BUILD_DATE, &t.to_le_bytes());
+        }
+        if self.cipher != Cipher::default() {
+            put_record(&mut out, TAG_CIPHER, &[self.cipher.id()]);
+        }
+        if let Some(hash) = &self.input_sha256 {
+            put_record(&mut out, TAG_INPUT_HASH, hash);
+        }
+        if let Some(c) = &self.compression {
+            put_record(&mut out, TAG_COMPRESSION, &[&[c.codec.id()][..], &c.original_size.to_le_bytes()].concat());
+        }
+        if self.libraries > 0 {
+            put_record(&mut out, TAG_LIBRARIES, &self.libraries.to_le_bytes());
+        }
+        for (tag, value) in [(TAG_INTERPRETER, &self.interpreter), (TAG_ENTRY, &self.entry)] {
+            if let Some(value) = value {
+                put_record(&mut out, tag, value.as_bytes());
+            }
+        }
+        out
+    }
+
+    /// Parses a header. Unknown tags are rejected rather than skipped, since
+    /// they may carry restrictions this stub does not know how to enforce.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if !is_header(data) {
+            return Err("Not a container header".into());
+        }
+        let version = data[HEADER_MAGIC.len()];
+        if version != HEADER_VERSION {
+            return Err(format!("Unsupported header version {}", version).into());
+        }
+
+        let mut header = Header::default();
+        let mut seen_build_id = false;
+        let mut pos = HEADER_MAGIC.len() + 1;
+
+        while pos < data.len() {
+            if data.len() - pos < 5 {
+                return Err("Truncated header record".into());
+            }
+            let tag = data[pos];
+            let len = u32::from_le_bytes(data[pos + 1..pos + 5].try_into()?) as usize;
+            pos += 5;
+            if data.len() - pos < len {
+                return Err("Truncated header record".into());
+            }
+            let value = &data[pos..pos + len];
+            pos += len;
+
+            match tag {
+                TAG_BUILD_ID => {
+                    header.build_id = value.try_into().map_err(|_| "Invalid build id")?;
+                    seen_build_id = true;
+                }
+                TAG_NOT_BEFORE => header.not_before = Some(read_u64(value)?),
+                TAG_NOT_AFTER => header.not_after = Some(read_u64(value)?),
+                TAG_MAX_LAUNCHES => header.max_launches = Some(read_u32(value)?),
+                TAG_MAX_INSTANCES => header.max_instances = Some(read_u32(value)?),
+                TAG_ALLOW_USER => header.policy.users.push(read_string(value)?),
+                TAG_ALLOW_GROUP => header.policy.groups.push(read_string(value)?),
+                TAG_ALLOW_HOST => header.policy.hostnames.push(read_string(value)?),
+                TAG_INSTALL_PATH => header.policy.install_paths.push(read_string(value)?),
+                TAG_ALLOW_PARENT => header.policy.parent_processes.push(read_string(value)?),
+                TAG_ANTI_DEBUG => {
+                    let (check, action) = match value {
+                        [c, a] => (Check::from_id(*c), Action::from_id(*a)),
+                        _ => (None, None),
+                    };
+                    let setting = check.zip(action).ok_or("Invalid anti-debug record")?;
+                    header.anti_debug.push(setting);
+                }
+                TAG_STUB_MEASUREMENT => {
+                    header.stub_measurement = Some(value.try_into().map_err(|_| "Invalid stub measurement")?);
+                }
+                TAG_COMPONENT_SALT => {
+                    let salt = value.try_into().map_err(|_| "Invalid component salt")?;
+                    header.components.get_or_insert_with(Default::default).salt = salt;
+                }
+                TAG_COMPONENT_HASH => {
+                    if value.len() <= 32 {
+                        return Err("Invalid component hash".into());
+                    }
+                    let (hash, name) = value.split_at(32);
+                    let components = header.components.as_mut().ok_or("Component hash before salt")?;
+                    components.hashes.push((read_string(name)?, hash.try_into()?));
+                }
+                TAG_PRODUCT => header.product = Some(read_string(value)?),
+                TAG_VERSION => header.version = Some(read_string(value)?),
+                TAG_BUILD_DATE => header.build_date = Some(read_u64(value)?),
+                TAG_TARGET => header.target = Some(read_string(value)?),
+                TAG_MANIFEST_ARG => header.manifest_arg = Some(read_string(value)?),
+                TAG_CIPHER => {
+                    let cipher = match value {
+                        [id] => Cipher::from_id(*id),
+                        _ => None,
+                    };
+                    header.cipher = cipher.ok_or("Unknown cipher")?;
+                }
+                TAG_INPUT_HASH => {
+                    header.input_sha256 = Some(value.try_into().map_err(|_| "Invalid input hash")?);
+                }
+                TAG_COMPRESSION => {
+                    let (&id, size) = value.split_first().ok_or("Invalid compression record")?;
+                    header.compression = Some(Compression {
+                        codec: Codec::from_id(id).ok_or("Unknown compression codec")?,
+                        original_size: read_u64(size)?,
+                    });
+                }
+                TAG_LIBRARIES => header.libraries = read_u32(value)?,
+                TAG_INTERPRETER => header.interpreter = Some(read_string(value)?),
+                TAG_ENTRY => header.entry = Some(read_string(value)?),
+                _ => return Err(format!("Unknown header record 0x{:02x}", tag).into()),
+            }
+        }
+
+        if !seen_build_id {
+            return Err("Header has no build id".into());
+        }
+        Ok(header)
+    }
+
+    /// Plain-text description of the build for support staff; contains
+    /// nothing that helps decrypt the payload.
+    pub fn manifest(&self) -> serde_json::Value {
+        serde_json::json!({
+            "product": self.product,
+            "version": self.version,
+            "build_id": self.build_id_hex(),
+            "build_date": self.build_date.map(format_timestamp),
+            "target": self.target,
+            "not_before": self.not_before.map(format_timestamp),
+            "not_after": self.not_after.map(format_timestamp),
+            "max_launches": self.max_launches,
+            "max_instances": self.max_instances,
+            "compression": self.compression.map(|c| c.codec.name()),
+            "bundled_libraries": self.libraries,
+            "interpreter": self.interpreter,
+        })
+    }
+
+    /// Everything the stub checks before decrypting, for build reports and
+    /// `sbb inspect`.
+    pub fn restrictions(&self) -> serde_json::Value {
+        let policy = &self.policy;
+        serde_json::json!({
+            "not_before": self.not_before.map(format_timestamp),
+            "not_after": self.not_after.map(format_timestamp),
+            "max_launches": self.max_launches,
+            "max_instances": self.max_instances,
+            "allow_user": policy.users,
+            "allow_group": policy.groups,
+            "allow_host": policy.hostnames,
+            "install_path": policy.install_paths,
+            "allow_parent": policy.parent_processes,
+            "anti_debug": self
+                .anti_debug
+                .iter()
+                .map(|(check, action)| format!("{}={}", check.name(), action.name()))
+                .collect::<Vec<_>>(),
+            "stub_integrity": self.stub_measurement.is_some(),
+        })
+    }
+}
+
+/// Checks whether a payload looks like a container header.
+pub fn is_header(data: &[u8]) -> bool {
+    data.len() > HEADER_MAGIC.len() && data.starts_with(HEADER_MAGIC)
+}
+
+fn put_record(out: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    out.push(tag);
+    out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    out.extend_from_slice(value);
+}
+
+fn read_u64(value: &[u8]) -> Result<u64, Box<dyn Error>> {
+    let bytes: [u8; 8] = value.try_into().map_err(|_| "Invalid integer record")?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_u32(value: &[u8]) -> Result<u32, Box<dyn Error>> {
+    let bytes: [u8; 4] = value.try_into().map_err(|_| "Invalid integer record")?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_string(value: &[u8]) -> Result<String, Box<dyn Error>> {
+    Ok(String::from_utf8(value.to_vec()).map_err(|_| "Invalid text record")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let mut header = Header::new();
+        header.not_before = Some(1_700_000_000);
+        header.not_after = Some(1_800_000_000);
+        header.max_launches = Some(50);
+        header.max_instances = Some(2);
+        header.policy.users = vec!["root".into(), "1000".into()];
+        header.policy.install_paths = vec!["/opt/vendor/bin".into()];
+        header.anti_debug = vec![(Check::Tracer, Action::Refuse), (Check::Preload, Action::Sanitize)];
+        header.stub_measurement = Some([7; 32]);
+        header.components = Some(ComponentHashes {
+            salt: [1; 16],
+            hashes: vec![("hostname".into(), [2; 32]), ("machine-id".into(), [3; 32])],
+        });
+        header.product = Some("Example Tool".into());
+        header.version = Some("2.4.1".into());
+        header.build_date = Some(1_750_000_000);
+        header.target = Some("linux".into());
+        header.manifest_arg = Some("--sbb-manifest".into());
+        header.cipher = Cipher::Aes256GcmSiv;
+        header.input_sha256 = Some([9; 32]);
+        header.compression = Some(Compression { codec: Codec::Xz, original_size: 123_456 });
+        header.libraries = 2;
+        header.interpreter = Some("/usr/bin/env python3".into());
+        header.entry = Some("bin/main.py".into());
+
+        let parsed = Header::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed, header);
+    }
+
+    #[test]
+    fn test_roundtrip_without_window() {
+        let header = Header::new();
+        let parsed = Header::from_bytes(&header.to_bytes()).unwrap();
+        assert_eq!(parsed.not_before, None);
+        assert_eq!(parsed.not_after, None);
+    }
+
+    #[test]
+    fn test_manifest() {
+        let mut header = Header::new();
+        header.product = Some("Example Tool".into());
+        header.not_after = Some(86_400);
+        header.manifest_arg = Some("--sbb-manifest".into());
+
+        let manifest = header.manifest();
+        assert_eq!(manifest["product"], "Example Tool");
+        assert_eq!(manifest["build_id"], header.build_id_hex());
+        assert_eq!(manifest["not_after"], "1970-01-02T00:00:00Z");
+        assert!(manifest["version"].is_null());
+        assert!(manifest.get("manifest_arg").is_none());
+    }
+
+    #[test]
+    fn test_rejects_unknown_tag() {
+        let mut bytes = Header::new().to_bytes();
+        put_record(&mut bytes, 0xEE, b"x");
+        assert!(Header::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_rejects_truncated() {
+        let bytes = Header::new().to_bytes();
+        assert!(Header::from_bytes(&bytes[..bytes.len() - 3]).is_err());
+        assert!(Header::from_bytes(b"SBBH").is_err());
+        assert!(Header::from_bytes(b"not a header").is_err());
+    }
+}
@@ -1,4 +1,17 @@
 // Export the modules so they can be used from other crates
+pub mod antidebug;
+pub mod bundle;
+pub mod capabilities;
+pub mod compress;
 pub mod crypto;
 pub mod fingerprint;
-pub mod embed;
\ No newline at end of file
+pub mod embed;
+pub mod elf;
+pub mod header;
+pub mod log;
+pub mod metering;
+pub mod pe;
+pub mod policy;
+pub mod script;
+pub mod state;
+pub mod validity;
@@ -0,0 +1,173 @@
+//! Leveled diagnostics for the stub and `sbb`, silent unless enabled.
+//!
+//! Configuration comes from the environment:
+//! - `SBB_LOG`: `off`, `error`, `warn`, `info` or `debug`
+//! - `SBB_LOG_FILE`: append to this file instead of writing to stderr
+//! - `SBB_LOG_FORMAT`: `text` (default) or `json` for JSON lines
+//!
+//! Without `SBB_LOG`, the level set at build time through the `SBB_LOG`
+//! compile-time variable applies, and otherwise only errors are reported.
+//! Nothing is ever written to stdout, and key material must never be passed
+//! to these macros; use [`crate::fingerprint::key_id`] to refer to a key.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+
+use crate::validity::{Clock, SystemClock, format_timestamp};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+
+    /// Parses a level name; `Ok(None)` means logging is off.
+    pub fn parse(s: &str) -> Result<Option<Level>, String> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" | "none" | "0" => Ok(None),
+            "error" => Ok(Some(Level::Error)),
+            "warn" | "warning" => Ok(Some(Level::Warn)),
+            "info" => Ok(Some(Level::Info)),
+            "debug" | "1" => Ok(Some(Level::Debug)),
+            other => Err(format!("Unknown log level '{}'", other)),
+        }
+    }
+}
+
+struct Logger {
+    component: &'static str,
+    max_level: Option<Level>,
+    json: bool,
+    file: Option<Mutex<File>>,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Sets the component name shown in every record and reads the
+/// configuration. Later calls have no effect.
+pub fn init(component: &'static str) {
+    let _ = LOGGER.set(Logger::from_env(component));
+}
+
+impl Logger {
+    fn from_env(component: &'static str) -> Self {
+        let configured = std::env::var("SBB_LOG").ok().or(option_env!("SBB_LOG").map(String::from));
+        let max_level = match configured {
+            Some(level) => Level::parse(&level).unwrap_or(Some(Level::Error)),
+            None => Some(Level::Error),
+        };
+        let json = std::env::var("SBB_LOG_FORMAT").is_ok_and(|f| f.eq_ignore_ascii_case("json"));
+        let file = std::env::var_os("SBB_LOG_FILE")
+            .and_then(|path| OpenOptions::new().create(true).append(true).open(path).ok())
+            .map(Mutex::new);
+
+        Logger { component, max_level, json, file }
+    }
+}
+
+fn logger() -> &'static Logger {
+    LOGGER.get_or_init(|| Logger::from_env("sbb"))
+}
+
+/// Whether records at `level` are currently written.
+pub fn enabled(level: Level) -> bool {
+    logger().max_level.is_some_and(|max| level <= max)
+}
+
+/// Writes one record. Use the `log_*!` macros instead of calling this.
+pub fn write(level: Level, args: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    let logger = logger();
+    let line = format_record(logger.component, logger.json, SystemClock.now(), level, &args.to_string());
+
+    match &logger.file {
+        Some(file) => {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.write_all(line.as_bytes());
+            }
+        }
+        None => {
+            let _ = std::io::stderr().write_all(line.as_bytes());
+        }
+    }
+}
+
+fn format_record(component: &str, json: bool, now: u64, level: Level, message: &str) -> String {
+    if json {
+        let record = serde_json::json!({
+            "ts": format_timestamp(now),
+            "level": level.name(),
+            "component": component,
+            "msg": message,
+        });
+        format!("{}\n", record)
+    } else {
+        format!("{} {:<5} {}: {}\n", format_timestamp(now), level.name(), component, message)
+    }
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Error, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Warn, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Info, format_args!($($arg)*)) };
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => { $crate::log::write($crate::log::Level::Debug, format_args!($($arg)*)) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(Level::parse("off"), Ok(None));
+        assert_eq!(Level::parse("WARN"), Ok(Some(Level::Warn)));
+        assert_eq!(Level::parse("debug"), Ok(Some(Level::Debug)));
+        assert!(Level::parse("verbose").is_err());
+        assert!(Level::Debug > Level::Info && Level::Warn > Level::Error);
+    }
+
+    #[test]
+    fn test_text_record() {
+        let line = format_record("stub", false, 86_400, Level::Info, "started");
+        assert_eq!(line, "1970-01-02T00:00:00Z info  stub: started\n");
+    }
+
+    #[test]
+    fn test_json_record() {
+        let line = format_record("sbb", true, 0, Level::Warn, "quote \" and\nnewline");
+        let value: serde_json::Value = serde_json::from_str(line.trim_end()).unwrap();
+        assert_eq!(value["level"], "warn");
+        assert_eq!(value["component"], "sbb");
+        assert_eq!(value["msg"], "quote \" and\nnewline");
+        assert!(!line.trim_end().contains('\n'));
+    }
+}
@@ -0,0 +1,321 @@
+//! Usage metering: launch counting and concurrent instance slots.
+//!
+//! The launch counter lives in the stub's state directory and carries an
+//! HMAC keyed from the machine fingerprint, so edits to the file are
+//! detected. A copy is kept in a second directory, so deleting the counter
+//! or restoring an older one is detected too. Deleting or restoring both
+//! copies together still resets the count: without a server, nothing on the
+//! machine can tell that apart from a build that never ran.
+//!
+//! Instance slots are lock files held with `flock` for as long as the
+//! protected program runs.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::header::Header;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MeterError {
+    LaunchLimitReached { max: u32 },
+    NoFreeInstance { max: u32 },
+    Tampered,
+    Io(String),
+}
+
+impl fmt::Display for MeterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeterError::LaunchLimitReached { max } => {
+                write!(f, "Launch limit reached ({} launches allowed)", max)
+            }
+            MeterError::NoFreeInstance { max } => {
+                write!(f, "Too many running instances ({} allowed)", max)
+            }
+            MeterError::Tampered => write!(f, "Usage state has been tampered with"),
+            MeterError::Io(e) => write!(f, "Usage state unavailable: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MeterError {}
+
+impl From<std::io::Error> for MeterError {
+    fn from(e: std::io::Error) -> Self {
+        MeterError::Io(e.to_string())
+    }
+}
+
+/// Launch counter for one build, stored as `launches=<n>` plus a MAC line.
+pub struct LaunchCounter {
+    path: PathBuf,
+    /// Copy of the counter in a second directory, written first.
+    journal: Option<PathBuf>,
+    build_id: [u8; 16],
+    key: [u8; 32],
+}
+
+impl LaunchCounter {
+    pub fn new(state_dir: &Path, header: &Header, fingerprint: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"sbb-meter-v1");
+        hasher.update(fingerprint.as_bytes());
+
+        LaunchCounter {
+            path: state_dir.join(format!("{}.meter", header.build_id_hex())),
+            journal: None,
+            build_id: header.build_id,
+            key: hasher.finalize().into(),
+        }
+    }
+
+    /// Keeps a copy of the counter in `dir`. Once the build has run, a
+    /// missing or older copy on either side is treated as tampering.
+    pub fn with_journal(mut self, dir: &Path) -> Self {
+        self.journal = self.path.file_name().map(|name| dir.join(name));
+        self
+    }
+
+    /// Counts one launch, failing if `max` launches have already happened.
+    /// Returns the number of launches including this one.
+    pub fn record_launch(&self, max: u32) -> Result<u32, MeterError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&self.path)?;
+        lock_exclusive(&file, true)?;
+
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        let counted = if contents.is_empty() { None } else { Some(self.parse(&contents)?) };
+        let launches = self.reconcile(counted)?;
+
+        if launches >= max {
+            return Err(MeterError::LaunchLimitReached { max });
+        }
+
+        let launches = launches + 1;
+        if let Some(journal) = &self.journal {
+            if let Some(parent) = journal.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(journal, self.serialize(launches))?;
+        }
+        file.seek(SeekFrom::Start(0))?;
+        file.set_len(0)?;
+        file.write_all(self.serialize(launches).as_bytes())?;
+        Ok(launches)
+    }
+
+    /// Number of recorded launches, without counting a new one.
+    pub fn launches(&self) -> Result<u32, MeterError> {
+        let counted = self.read(&self.path)?;
+        self.reconcile(counted)
+    }
+
+    fn read(&self, path: &Path) -> Result<Option<u32>, MeterError> {
+        match fs::read_to_string(path) {
+            Ok(contents) if contents.is_empty() => Ok(None),
+            Ok(contents) => self.parse(&contents).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // The launches so far, from the counter and its copy
+    fn reconcile(&self, counted: Option<u32>) -> Result<u32, MeterError> {
+        let Some(journal) = &self.journal else { return Ok(counted.unwrap_or(0)) };
+        match (counted, self.read(journal)?) {
+            (None, None) => Ok(0),
+            // The copy is written first, so a launch cut short between the
+            // two writes leaves it one ahead
+            (counted, Some(copy)) if copy == counted.unwrap_or(0) || copy == counted.unwrap_or(0) + 1 => Ok(copy),
+            _ => Err(MeterError::Tampered),
+        }
+    }
+
+    fn mac(&self, launches: u32) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(&self.build_id);
+        mac.update(&launches.to_le_bytes());
+        mac
+    }
+
+    fn serialize(&self, launches: u32) -> String {
+        let tag = self.mac(launches).finalize().into_bytes();
+        format!("launches={}\nmac={}\n", launches, hex::encode(tag))
+    }
+
+    fn parse(&self, contents: &str) -> Result<u32, MeterError> {
+        let mut launches = None;
+        let mut tag = None;
+        for line in contents.lines() {
+            match line.split_once('=') {
+                Some(("launches", v)) => launches = v.parse::<u32>().ok(),
+                Some(("mac", v)) => tag = hex::decode(v).ok(),
+                _ => return Err(MeterError::Tampered),
+            }
+        }
+        let (launches, tag) = launches.zip(tag).ok_or(MeterError::Tampered)?;
+        self.mac(launches)
+            .verify_slice(&tag)
+            .map_err(|_| MeterError::Tampered)?;
+        Ok(launches)
+    }
+}
+
+/// A held instance slot. The lock is released when the last copy of the
+/// descriptor is closed, i.e. when the protected program exits.
+pub struct InstanceSlot {
+    file: File,
+    pub index: u32,
+}
+
+impl InstanceSlot {
+    /// Keeps the lock descriptor open across `exec`, so the slot stays held
+    /// by the program the stub hands over to.
+    pub fn keep_across_exec(self) -> Result<(), MeterError> {
+        #[cfg(unix)]
+        {
+            use std::os::fd::IntoRawFd;
+            let fd = self.file.into_raw_fd();
+            let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+            if flags == -1 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) } == -1 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            Ok(())
+        }
+
+        #[cfg(not(unix))]
+        {
+            drop(self.file);
+            Err(MeterError::Io("instance limits are not supported on this platform".into()))
+        }
+    }
+}
+
+/// Takes the first free of `max` instance slots for this build.
+pub fn acquire_slot(state_dir: &Path, header: &Header, max: u32) -> Result<InstanceSlot, MeterError> {
+    fs::create_dir_all(state_dir)?;
+    for index in 0..max {
+        let path = state_dir.join(format!("{}.slot{}", header.build_id_hex(), index));
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        if lock_exclusive(&file, false).is_ok() {
+            return Ok(InstanceSlot { file, index });
+        }
+    }
+    Err(MeterError::NoFreeInstance { max })
+}
+
+#[cfg(unix)]
+fn lock_exclusive(file: &File, wait: bool) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let op = if wait { libc::LOCK_EX } else { libc::LOCK_EX | libc::LOCK_NB };
+    if unsafe { libc::flock(file.as_raw_fd(), op) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Without flock, counter updates are unserialized and slots cannot be held.
+#[cfg(not(unix))]
+fn lock_exclusive(_file: &File, wait: bool) -> std::io::Result<()> {
+    if wait {
+        return Ok(());
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "file locking is not supported on this platform",
+    ))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const FP: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn test_launch_limit() {
+        let dir = TempDir::new().unwrap();
+        let counter = LaunchCounter::new(dir.path(), &Header::new(), FP);
+
+        assert_eq!(counter.record_launch(2), Ok(1));
+        assert_eq!(counter.record_launch(2), Ok(2));
+        assert_eq!(counter.record_launch(2), Err(MeterError::LaunchLimitReached { max: 2 }));
+        assert_eq!(counter.launches(), Ok(2));
+    }
+
+    #[test]
+    fn test_detects_tampering() {
+        let dir = TempDir::new().unwrap();
+        let header = Header::new();
+        let counter = LaunchCounter::new(dir.path(), &header, FP);
+        counter.record_launch(5).unwrap();
+
+        let path = dir.path().join(format!("{}.meter", header.build_id_hex()));
+        let edited = fs::read_to_string(&path).unwrap().replace("launches=1", "launches=0");
+        fs::write(&path, edited).unwrap();
+        assert_eq!(counter.record_launch(5), Err(MeterError::Tampered));
+
+        // A state file written for another machine does not verify either.
+        fs::write(&path, LaunchCounter::new(dir.path(), &header, "other").serialize(0)).unwrap();
+        assert_eq!(counter.launches(), Err(MeterError::Tampered));
+    }
+
+    #[test]
+    fn test_detects_deleted_or_restored_counter() {
+        let (state, journal) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let header = Header::new();
+        let counter = LaunchCounter::new(state.path(), &header, FP).with_journal(journal.path());
+        assert_eq!(counter.record_launch(5), Ok(1));
+        let path = state.path().join(format!("{}.meter", header.build_id_hex()));
+        let first = fs::read(&path).unwrap();
+        assert_eq!(counter.record_launch(5), Ok(2));
+        assert_eq!(counter.record_launch(5), Ok(3));
+
+        // An older copy, or none, on either side
+        fs::write(&path, &first).unwrap();
+        assert_eq!(counter.record_launch(5), Err(MeterError::Tampered));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(counter.launches(), Err(MeterError::Tampered));
+
+        // The first launch, cut short after writing the copy, still counts
+        fs::write(journal.path().join(format!("{}.meter", header.build_id_hex())), &first).unwrap();
+        assert_eq!(counter.launches(), Ok(1));
+    }
+
+    #[test]
+    fn test_instance_slots() {
+        let dir = TempDir::new().unwrap();
+        let header = Header::new();
+
+        let first = acquire_slot(dir.path(), &header, 2).unwrap();
+        let second = acquire_slot(dir.path(), &header, 2).unwrap();
+        assert_ne!(first.index, second.index);
+        assert!(matches!(
+            acquire_slot(dir.path(), &header, 2),
+            Err(MeterError::NoFreeInstance { max: 2 })
+        ));
+
+        drop(first);
+        assert_eq!(acquire_slot(dir.path(), &header, 2).unwrap().index, 0);
+    }
+}
@@ -0,0 +1,92 @@
+//! Minimal PE reader: enough of the COFF header to identify a Windows image.
+
+use std::error::Error;
+
+pub const MZ_MAGIC: &[u8] = b"MZ";
+const PE_SIGNATURE: &[u8] = b"PE\0\0";
+
+const IMAGE_FILE_DLL: u16 = 0x2000;
+const OPTIONAL_HDR32_MAGIC: u16 = 0x10b;
+const OPTIONAL_HDR64_MAGIC: u16 = 0x20b;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pe {
+    pub machine: u16,
+    pub is_64: bool,
+    pub is_dll: bool,
+}
+
+impl Pe {
+    pub fn parse(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if !data.starts_with(MZ_MAGIC) {
+            return Err("Not a PE file".into());
+        }
+        let u16_at = |at: usize| -> Result<u16, Box<dyn Error>> {
+            let bytes = data.get(at..at + 2).ok_or("Truncated PE file")?;
+            Ok(u16::from_le_bytes(bytes.try_into()?))
+        };
+        let pe_offset = data.get(0x3c..0x40).ok_or("Truncated PE file")?;
+        let pe_offset = u32::from_le_bytes(pe_offset.try_into()?) as usize;
+        if data.get(pe_offset..pe_offset + 4) != Some(PE_SIGNATURE) {
+            return Err("Missing PE signature".into());
+        }
+
+        // COFF header follows the signature; the optional header follows it
+        let coff = pe_offset + 4;
+        let machine = u16_at(coff)?;
+        let characteristics = u16_at(coff + 18)?;
+        let is_64 = match u16_at(coff + 20)? {
+            OPTIONAL_HDR32_MAGIC => false,
+            OPTIONAL_HDR64_MAGIC => true,
+            other => return Err(format!("Unknown PE optional header magic 0x{:x}", other).into()),
+        };
+
+        Ok(Pe { machine, is_64, is_dll: characteristics & IMAGE_FILE_DLL != 0 })
+    }
+
+    /// Architecture name as used in Rust target triples.
+    pub fn arch(&self) -> Option<&'static str> {
+        match self.machine {
+            0x014c => Some("i686"),
+            0x8664 => Some("x86_64"),
+            0xaa64 => Some("aarch64"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(machine: u16, characteristics: u16, magic: u16) -> Vec<u8> {
+        let mut data = vec![0u8; 0x80 + 24 + 2];
+        data[..2].copy_from_slice(MZ_MAGIC);
+        data[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        data[0x80..0x84].copy_from_slice(PE_SIGNATURE);
+        data[0x84..0x86].copy_from_slice(&machine.to_le_bytes());
+        data[0x96..0x98].copy_from_slice(&characteristics.to_le_bytes());
+        data[0x98..0x9a].copy_from_slice(&magic.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let pe = Pe::parse(&image(0x8664, 0x0022, OPTIONAL_HDR64_MAGIC)).unwrap();
+        assert_eq!(pe.arch(), Some("x86_64"));
+        assert!(pe.is_64 && !pe.is_dll);
+
+        let dll = Pe::parse(&image(0x014c, 0x2102, OPTIONAL_HDR32_MAGIC)).unwrap();
+        assert_eq!(dll.arch(), Some("i686"));
+        assert!(!dll.is_64 && dll.is_dll);
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        assert!(Pe::parse(b"MZ").is_err());
+        assert!(Pe::parse(b"\x7fELF").is_err());
+        let mut bad = image(0x8664, 0, OPTIONAL_HDR64_MAGIC);
+        bad[0x80] = b'X';
+        assert!(Pe::parse(&bad).is_err());
+    }
+}
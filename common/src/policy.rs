@@ -0,0 +1,265 @@
+//! Launch policy: who may start a secured binary, where and from what.
+//!
+//! Rules are evaluated against [`Facts`] about the running process, which
+//! the stub gathers from the system and tests construct directly.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Declarative launch restrictions. Each non-empty list is a rule that
+/// passes when any of its entries matches.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy {
+    /// User names or numeric UIDs allowed to run the binary.
+    pub users: Vec<String>,
+    /// Group names or numeric GIDs; the process must be in one of them.
+    pub groups: Vec<String>,
+    /// Host names the binary may run on (case-insensitive).
+    pub hostnames: Vec<String>,
+    /// Directories the binary must be installed under.
+    pub install_paths: Vec<String>,
+    /// Parent process names, or absolute paths of parent executables.
+    pub parent_processes: Vec<String>,
+}
+
+/// What the stub knows about the process being launched.
+#[derive(Debug, Clone, Default)]
+pub struct Facts {
+    pub uid: Option<u32>,
+    pub user_name: Option<String>,
+    pub gids: Vec<u32>,
+    pub group_names: Vec<String>,
+    pub hostname: Option<String>,
+    pub exe_path: Option<PathBuf>,
+    pub parent_name: Option<String>,
+    pub parent_exe: Option<PathBuf>,
+}
+
+/// A rule that did not pass, named as in the build options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleFailure {
+    pub rule: &'static str,
+    pub detail: String,
+}
+
+impl fmt::Display for RuleFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "launch policy rule '{}' failed: {}", self.rule, self.detail)
+    }
+}
+
+impl Policy {
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+            && self.groups.is_empty()
+            && self.hostnames.is_empty()
+            && self.install_paths.is_empty()
+            && self.parent_processes.is_empty()
+    }
+
+    /// Evaluates every rule and returns all failures; empty means allowed.
+    pub fn evaluate(&self, facts: &Facts) -> Vec<RuleFailure> {
+        let mut failures = Vec::new();
+
+        if !self.users.is_empty() {
+            let allowed = self.users.iter().any(|u| match u.parse::<u32>() {
+                Ok(uid) => facts.uid == Some(uid),
+                Err(_) => facts.user_name.as_deref() == Some(u.as_str()),
+            });
+            if !allowed {
+                failures.push(RuleFailure {
+                    rule: "user",
+                    detail: format!("running as {}", describe(&facts.user_name, &facts.uid)),
+                });
+            }
+        }
+
+        if !self.groups.is_empty() {
+            let allowed = self.groups.iter().any(|g| match g.parse::<u32>() {
+                Ok(gid) => facts.gids.contains(&gid),
+                Err(_) => facts.group_names.iter().any(|n| n == g),
+            });
+            if !allowed {
+                failures.push(RuleFailure {
+                    rule: "group",
+                    detail: "process is not in an allowed group".to_string(),
+                });
+            }
+        }
+
+        if !self.hostnames.is_empty() {
+            let allowed = facts.hostname.as_ref().is_some_and(|h| {
+                self.hostnames.iter().any(|allowed| allowed.eq_ignore_ascii_case(h))
+            });
+            if !allowed {
+                failures.push(RuleFailure {
+                    rule: "hostname",
+                    detail: format!("host is {}", facts.hostname.as_deref().unwrap_or("unknown")),
+                });
+            }
+        }
+
+        if !self.install_paths.is_empty() {
+            let allowed = facts.exe_path.as_ref().is_some_and(|exe| {
+                self.install_paths.iter().any(|dir| exe.starts_with(Path::new(dir)))
+            });
+            if !allowed {
+                failures.push(RuleFailure {
+                    rule: "install-path",
+                    detail: format!("installed at {}", display_path(&facts.exe_path)),
+                });
+            }
+        }
+
+        if !self.parent_processes.is_empty() {
+            let allowed = self.parent_processes.iter().any(|p| {
+                if p.contains('/') || p.contains('\\') {
+                    facts.parent_exe.as_deref() == Some(Path::new(p))
+                } else {
+                    facts.parent_name.as_deref() == Some(p.as_str())
+                }
+            });
+            if !allowed {
+                failures.push(RuleFailure {
+                    rule: "parent-process",
+                    detail: format!(
+                        "started by {}",
+                        facts.parent_name.as_deref().unwrap_or("an unknown process")
+                    ),
+                });
+            }
+        }
+
+        failures
+    }
+}
+
+fn describe(name: &Option<String>, id: &Option<u32>) -> String {
+    match (name, id) {
+        (Some(name), Some(id)) => format!("{} ({})", name, id),
+        (Some(name), None) => name.clone(),
+        (None, Some(id)) => id.to_string(),
+        (None, None) => "unknown".to_string(),
+    }
+}
+
+fn display_path(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map_or_else(|| "unknown".to_string(), |p| p.display().to_string())
+}
+
+impl Facts {
+    /// Collects facts about the current process. `exe_path` is the stub's
+    /// own path; it is canonicalized so symlinks cannot fake the location.
+    pub fn gather(exe_path: &Path) -> Self {
+        let mut facts = Facts {
+            hostname: sysinfo::System::host_name(),
+            exe_path: std::fs::canonicalize(exe_path).ok(),
+            ..Default::default()
+        };
+
+        #[cfg(unix)]
+        gather_unix(&mut facts);
+
+        #[cfg(windows)]
+        {
+            facts.user_name = std::env::var("USERNAME").ok();
+        }
+
+        facts
+    }
+}
+
+#[cfg(unix)]
+fn gather_unix(facts: &mut Facts) {
+    use std::ffi::CStr;
+
+    let uid = unsafe { libc::getuid() };
+    facts.uid = Some(uid);
+    let pw = unsafe { libc::getpwuid(uid) };
+    if !pw.is_null() {
+        facts.user_name = Some(unsafe { CStr::from_ptr((*pw).pw_name) }.to_string_lossy().into_owned());
+    }
+
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    let mut gids = vec![0 as libc::gid_t; count.max(0) as usize];
+    let count = unsafe { libc::getgroups(gids.len() as libc::c_int, gids.as_mut_ptr()) };
+    gids.truncate(count.max(0) as usize);
+    let egid = unsafe { libc::getegid() };
+    if !gids.contains(&egid) {
+        gids.push(egid);
+    }
+    for &gid in &gids {
+        let gr = unsafe { libc::getgrgid(gid) };
+        if !gr.is_null() {
+            let name = unsafe { CStr::from_ptr((*gr).gr_name) };
+            facts.group_names.push(name.to_string_lossy().into_owned());
+        }
+    }
+    facts.gids = gids;
+
+    let ppid = unsafe { libc::getppid() };
+    facts.parent_name = std::fs::read_to_string(format!("/proc/{}/comm", ppid))
+        .ok()
+        .map(|s| s.trim_end().to_string());
+    facts.parent_exe = std::fs::read_link(format!("/proc/{}/exe", ppid)).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts() -> Facts {
+        Facts {
+            uid: Some(1000),
+            user_name: Some("alice".into()),
+            gids: vec![1000, 27],
+            group_names: vec!["alice".into(), "sudo".into()],
+            hostname: Some("Build-01".into()),
+            exe_path: Some(PathBuf::from("/opt/vendor/bin/tool")),
+            parent_name: Some("bash".into()),
+            parent_exe: Some(PathBuf::from("/usr/bin/bash")),
+        }
+    }
+
+    #[test]
+    fn test_empty_policy_allows_everything() {
+        assert!(Policy::default().is_empty());
+        assert!(Policy::default().evaluate(&Facts::default()).is_empty());
+    }
+
+    #[test]
+    fn test_matching_policy_passes() {
+        let policy = Policy {
+            users: vec!["root".into(), "1000".into()],
+            groups: vec!["sudo".into()],
+            hostnames: vec!["build-01".into()],
+            install_paths: vec!["/opt/vendor/bin".into()],
+            parent_processes: vec!["/usr/bin/bash".into()],
+        };
+        assert_eq!(policy.evaluate(&facts()), vec![]);
+    }
+
+    #[test]
+    fn test_reports_each_failed_rule() {
+        let policy = Policy {
+            users: vec!["root".into()],
+            groups: vec!["0".into()],
+            hostnames: vec!["prod-01".into()],
+            install_paths: vec!["/opt/vendor/binaries".into()],
+            parent_processes: vec!["systemd".into()],
+        };
+        let rules: Vec<_> = policy.evaluate(&facts()).iter().map(|f| f.rule).collect();
+        assert_eq!(rules, ["user", "group", "hostname", "install-path", "parent-process"]);
+    }
+
+    #[test]
+    fn test_unknown_facts_fail_closed() {
+        let policy = Policy {
+            hostnames: vec!["build-01".into()],
+            install_paths: vec!["/".into()],
+            ..Default::default()
+        };
+        assert_eq!(policy.evaluate(&Facts::default()).len(), 2);
+    }
+}
@@ -0,0 +1,38 @@
+//! Scripts run by the interpreter named in their `#!` line.
+//!
+//! The stub runs a script the way the kernel would: the interpreter, its
+//! optional argument, the path of the script, then the arguments.
+
+/// The interpreter line of a script without the `#!`, or `None` if `data`
+/// does not start with one.
+pub fn shebang(data: &[u8]) -> Option<String> {
+    let line = data.strip_prefix(b"#!")?;
+    let end = line.iter().position(|&b| b == b'\n').unwrap_or(line.len());
+    let line = std::str::from_utf8(&line[..end]).ok()?.trim();
+    (!line.is_empty()).then(|| line.to_string())
+}
+
+/// Interpreter path and optional argument of an interpreter line. Like
+/// Linux, everything after the path is a single argument.
+pub fn command(line: &str) -> (&str, Option<&str>) {
+    match line.trim().split_once([' ', '\t']) {
+        Some((path, arg)) => (path, Some(arg.trim()).filter(|a| !a.is_empty())),
+        None => (line.trim(), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shebang() {
+        assert_eq!(shebang(b"#!/bin/sh\necho hi\n").as_deref(), Some("/bin/sh"));
+        assert_eq!(shebang(b"#! /usr/bin/env python3 \r\n").as_deref(), Some("/usr/bin/env python3"));
+        assert_eq!(shebang(b"echo hi\n"), None);
+        assert_eq!(shebang(b"#!\n"), None);
+
+        assert_eq!(command("/bin/sh"), ("/bin/sh", None));
+        assert_eq!(command("/usr/bin/env python3 -u"), ("/usr/bin/env", Some("python3 -u")));
+    }
+}
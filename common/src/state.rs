@@ -0,0 +1,57 @@
+//! Location of the stub's persisted local state.
+//!
+//! On Unix the directories are found through the user's passwd entry, not
+//! `$HOME` or the XDG variables, so that launching a build with another
+//! environment does not give it fresh launch counts and rollback evidence.
+
+use std::path::PathBuf;
+
+/// Returns the per-user directory where stubs keep their state.
+///
+/// Unix: `~/.local/state/sbb`.
+/// Windows: `%LOCALAPPDATA%\sbb`.
+pub fn state_dir() -> Option<PathBuf> {
+    #[cfg(windows)]
+    {
+        std::env::var_os("LOCALAPPDATA").map(|d| PathBuf::from(d).join("sbb"))
+    }
+
+    #[cfg(not(windows))]
+    {
+        home_dir().map(|h| h.join(".local/state/sbb"))
+    }
+}
+
+/// Returns the second directory, where stubs keep a copy of their launch
+/// counters so that deleting the state directory does not reset them. It is
+/// never inside the state directory.
+///
+/// Unix: `~/.local/share/sbb`.
+/// Windows: `%APPDATA%\sbb`.
+pub fn journal_dir() -> Option<PathBuf> {
+    #[cfg(windows)]
+    {
+        std::env::var_os("APPDATA").map(|d| PathBuf::from(d).join("sbb"))
+    }
+
+    #[cfg(not(windows))]
+    {
+        home_dir().map(|h| h.join(".local/share/sbb"))
+    }
+}
+
+// The home directory in the passwd entry of the real user
+#[cfg(not(windows))]
+fn home_dir() -> Option<PathBuf> {
+    use std::ffi::{CStr, OsStr};
+    use std::os::unix::ffi::OsStrExt;
+
+    // The stub is single-threaded when it reads its state, so the static
+    // buffer of getpwuid is not shared
+    let entry = unsafe { libc::getpwuid(libc::getuid()) };
+    if entry.is_null() || unsafe { (*entry).pw_dir.is_null() } {
+        return None;
+    }
+    let dir = unsafe { CStr::from_ptr((*entry).pw_dir) };
+    Some(PathBuf::from(OsStr::from_bytes(dir.to_bytes()))).filter(|d| d.is_absolute())
+}
@@ -0,0 +1,343 @@
+//! Validity windows (not-before / not-after) and clock rollback detection.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::header::Header;
+
+/// How far the clock may lag behind recorded evidence before it is treated
+/// as rolled back. Covers NTP corrections and file systems with coarse mtimes.
+pub const ROLLBACK_TOLERANCE_SECS: u64 = 60 * 60;
+
+/// Source of the current time, injectable for tests.
+pub trait Clock {
+    /// Current Unix time in seconds.
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ValidityError {
+    NotYetValid { not_before: u64, now: u64 },
+    Expired { not_after: u64, now: u64 },
+    ClockRollback { now: u64, last_seen: u64 },
+}
+
+impl fmt::Display for ValidityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidityError::NotYetValid { not_before, .. } => {
+                write!(f, "This build is not valid before {}", format_timestamp(*not_before))
+            }
+            ValidityError::Expired { not_after, .. } => {
+                write!(f, "This build expired on {}", format_timestamp(*not_after))
+            }
+            ValidityError::ClockRollback { .. } => {
+                write!(f, "System clock appears to have been set back")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidityError {}
+
+/// Checks `now` against the header's window.
+///
+/// `evidence` holds timestamps known to be in the past (last-seen time,
+/// file modification times). If any of them lies clearly after `now`, the
+/// clock has been set back and the check fails.
+pub fn check_window(header: &Header, now: u64, evidence: &[u64]) -> Result<(), ValidityError> {
+    if header.not_before.is_none() && header.not_after.is_none() {
+        return Ok(());
+    }
+
+    if let Some(&last_seen) = evidence.iter().max()
+        && last_seen > now.saturating_add(ROLLBACK_TOLERANCE_SECS)
+    {
+        return Err(ValidityError::ClockRollback { now, last_seen });
+    }
+
+    if let Some(not_before) = header.not_before
+        && now < not_before
+    {
+        return Err(ValidityError::NotYetValid { not_before, now });
+    }
+    if let Some(not_after) = header.not_after
+        && now > not_after
+    {
+        return Err(ValidityError::Expired { not_after, now });
+    }
+    Ok(())
+}
+
+/// Persisted "latest time this build was seen running".
+pub struct LastSeen {
+    path: PathBuf,
+}
+
+impl LastSeen {
+    pub fn new(state_dir: &Path, header: &Header) -> Self {
+        LastSeen {
+            path: state_dir.join(format!("{}.seen", header.build_id_hex())),
+        }
+    }
+
+    pub fn load(&self) -> Option<u64> {
+        fs::read_to_string(&self.path).ok()?.trim().parse().ok()
+    }
+
+    /// Modification time of the state file itself.
+    pub fn modified(&self) -> Option<u64> {
+        file_mtime(&self.path)
+    }
+
+    pub fn store(&self, time: u64) -> std::io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, time.to_string())
+    }
+}
+
+/// Full check used by the stub: gathers the persisted evidence, validates
+/// the window and records the current time on success.
+pub fn enforce(
+    header: &Header,
+    clock: &dyn Clock,
+    last_seen: &LastSeen,
+    extra_evidence: &[u64],
+) -> Result<(), ValidityError> {
+    if header.not_before.is_none() && header.not_after.is_none() {
+        return Ok(());
+    }
+
+    let now = clock.now();
+    let stored = last_seen.load();
+    let mut evidence: Vec<u64> = extra_evidence.to_vec();
+    evidence.extend(stored);
+    evidence.extend(last_seen.modified());
+
+    check_window(header, now, &evidence)?;
+
+    // Never move the recorded time backwards.
+    let _ = last_seen.store(stored.map_or(now, |s| s.max(now)));
+    Ok(())
+}
+
+/// Modification time of a file as Unix seconds.
+pub fn file_mtime(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    modified.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
+
+/// Parses a point in time given as Unix seconds, `YYYY-MM-DD`, or
+/// `YYYY-MM-DDTHH:MM:SS[Z]` (always UTC).
+pub fn parse_timestamp(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(secs);
+    }
+
+    let invalid = || format!("Invalid time '{}': expected YYYY-MM-DD, YYYY-MM-DDTHH:MM:SSZ or Unix seconds", s);
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((d, t)) => (d, Some(t.trim_end_matches('Z'))),
+        None => (s, None),
+    };
+
+    let date: Vec<&str> = date.split('-').collect();
+    if date.len() != 3 {
+        return Err(invalid());
+    }
+    let year: i64 = date[0].parse().map_err(|_| invalid())?;
+    let month: u32 = date[1].parse().map_err(|_| invalid())?;
+    let day: u32 = date[2].parse().map_err(|_| invalid())?;
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+
+    let mut secs_of_day = 0;
+    if let Some(time) = time {
+        let parts: Vec<&str> = time.split(':').collect();
+        if parts.len() != 3 {
+            return Err(invalid());
+        }
+        let h: u64 = parts[0].parse().map_err(|_| invalid())?;
+        let m: u64 = parts[1].parse().map_err(|_| invalid())?;
+        let sec: u64 = parts[2].parse().map_err(|_| invalid())?;
+        if h > 23 || m > 59 || sec > 59 {
+            return Err(invalid());
+        }
+        secs_of_day = h * 3600 + m * 60 + sec;
+    }
+
+    let days = days_from_civil(year, month, day);
+    if days < 0 {
+        return Err(invalid());
+    }
+    Ok(days as u64 * 86_400 + secs_of_day)
+}
+
+/// Formats Unix seconds as `YYYY-MM-DDTHH:MM:SSZ`.
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    let (y, m, d) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        y,
+        m,
+        d,
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    )
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Howard Hinnant's days-from-civil algorithms.
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let m = m as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+    let z = z + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    struct FixedClock(u64);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> u64 {
+            self.0
+        }
+    }
+
+    const DAY: u64 = 86_400;
+
+    fn windowed(not_before: u64, not_after: u64) -> Header {
+        let mut header = Header::new();
+        header.not_before = Some(not_before);
+        header.not_after = Some(not_after);
+        header
+    }
+
+    #[test]
+    fn test_window_bounds() {
+        let header = windowed(1000 * DAY, 1030 * DAY);
+        assert_eq!(
+            check_window(&header, 999 * DAY, &[]),
+            Err(ValidityError::NotYetValid { not_before: 1000 * DAY, now: 999 * DAY })
+        );
+        assert!(check_window(&header, 1000 * DAY, &[]).is_ok());
+        assert!(check_window(&header, 1030 * DAY, &[]).is_ok());
+        assert_eq!(
+            check_window(&header, 1031 * DAY, &[]),
+            Err(ValidityError::Expired { not_after: 1030 * DAY, now: 1031 * DAY })
+        );
+    }
+
+    #[test]
+    fn test_no_window_ignores_evidence() {
+        let header = Header::new();
+        assert!(check_window(&header, 0, &[u64::MAX]).is_ok());
+    }
+
+    #[test]
+    fn test_rollback_against_evidence() {
+        let header = windowed(1000 * DAY, 1030 * DAY);
+        let now = 1010 * DAY;
+        assert!(check_window(&header, now, &[now + ROLLBACK_TOLERANCE_SECS]).is_ok());
+        assert!(matches!(
+            check_window(&header, now, &[now - DAY, now + 2 * DAY]),
+            Err(ValidityError::ClockRollback { .. })
+        ));
+    }
+
+    // The state file gets a real mtime, so injected times stay after it.
+    #[test]
+    fn test_enforce_persists_last_seen() {
+        let dir = TempDir::new().unwrap();
+        let base = SystemClock.now();
+        let header = windowed(base - 10 * DAY, base + 30 * DAY);
+        let store = LastSeen::new(dir.path(), &header);
+
+        // A run late in the window records the time ...
+        enforce(&header, &FixedClock(base + 25 * DAY), &store, &[]).unwrap();
+        assert_eq!(store.load(), Some(base + 25 * DAY));
+
+        // ... so setting the clock back into the window is caught.
+        assert!(matches!(
+            enforce(&header, &FixedClock(base + 5 * DAY), &store, &[]),
+            Err(ValidityError::ClockRollback { .. })
+        ));
+
+        // Running forward keeps working and never lowers the stored time.
+        enforce(&header, &FixedClock(base + 26 * DAY), &store, &[]).unwrap();
+        assert_eq!(store.load(), Some(base + 26 * DAY));
+    }
+
+    #[test]
+    fn test_enforce_uses_file_mtimes() {
+        let dir = TempDir::new().unwrap();
+        let base = SystemClock.now();
+        let header = windowed(0, u64::MAX);
+        let store = LastSeen::new(dir.path(), &header);
+        let exe_mtime = base + 100 * DAY;
+
+        assert!(matches!(
+            enforce(&header, &FixedClock(base), &store, &[exe_mtime]),
+            Err(ValidityError::ClockRollback { .. })
+        ));
+        assert_eq!(store.load(), None);
+    }
+
+    #[test]
+    fn test_parse_and_format_timestamp() {
+        assert_eq!(parse_timestamp("0"), Ok(0));
+        assert_eq!(parse_timestamp("1970-01-02"), Ok(DAY));
+        assert_eq!(parse_timestamp("2024-02-29T12:30:15Z"), Ok(1_709_209_815));
+        assert_eq!(format_timestamp(1_709_209_815), "2024-02-29T12:30:15Z");
+        assert!(parse_timestamp("2023-02-29").is_err());
+        assert!(parse_timestamp("2024-13-01").is_err());
+        assert!(parse_timestamp("tomorrow").is_err());
+    }
+}
@@ -1,12 +1,15 @@
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
-use common::fingerprint::generate_fingerprint;
+use common::fingerprint::{collect_components, fingerprint_from, ComponentHashes, KeyFile};
 fn main() {
-    // Assuming the function is defined as: common::generate_fingerprint() -> String
-    let fingerprint = generate_fingerprint();
+    let components = collect_components();
+    let key = KeyFile {
+        fingerprint: fingerprint_from(&components),
+        components: Some(ComponentHashes::from_components(&components)),
+    };
 
     let path = Path::new("key.txt");
-    let mut file = File::create(&path).expect("Unable to create file");
-    file.write_all(fingerprint.as_bytes()).expect("Unable to write data");
-}
\ No newline at end of file
+    let mut file = File::create(path).expect("Unable to create file");
+    file.write_all(key.to_file_string().as_bytes()).expect("Unable to write data");
+}
@@ -0,0 +1,64 @@
+//! Embeds prebuilt stubs into `sbb`.
+//!
+//! Set `SBB_STUB_DIR` to a stub registry: a directory with one subdirectory
+//! per stub, each holding a `stub` or `stub.exe` and optionally the
+//! `stub.json` sidecar written by `sbb stubs add`:
+//!
+//! ```text
+//! stubs/x86_64-unknown-linux-gnu/stub
+//! stubs/x86_64-unknown-linux-gnu/stub.json
+//! stubs/x86_64-pc-windows-gnu/stub.exe
+//! ```
+//!
+//! Without it, `sbb` has no bundled stubs and falls back to the stub built
+//! next to it, which is enough for development. A release build without
+//! stubs fails unless `SBB_NO_BUNDLED_STUBS=1` says that is intended.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=SBB_STUB_DIR");
+    println!("cargo:rerun-if-env-changed=SBB_NO_BUNDLED_STUBS");
+    println!("cargo:rustc-env=SBB_HOST_TARGET={}", env::var("TARGET").unwrap());
+
+    let mut stubs = Vec::new();
+    if let Some(dir) = env::var_os("SBB_STUB_DIR") {
+        let dir = PathBuf::from(dir);
+        println!("cargo:rerun-if-changed={}", dir.display());
+        let entries = fs::read_dir(&dir).unwrap_or_else(|e| panic!("Cannot read SBB_STUB_DIR {}: {}", dir.display(), e));
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let sidecar = entry.path().join("stub.json");
+            println!("cargo:rerun-if-changed={}", sidecar.display());
+            let sidecar = fs::read_to_string(&sidecar).unwrap_or_default();
+            for file in ["stub", "stub.exe"] {
+                let path = entry.path().join(file);
+                if path.is_file() {
+                    println!("cargo:rerun-if-changed={}", path.display());
+                    stubs.push((name.clone(), fs::canonicalize(&path).unwrap(), sidecar.clone()));
+                }
+            }
+        }
+        if stubs.is_empty() {
+            panic!("SBB_STUB_DIR {} contains no <name>/stub files", dir.display());
+        }
+        stubs.sort();
+    } else if env::var("PROFILE").as_deref() == Ok("release") {
+        if env::var("SBB_NO_BUNDLED_STUBS").as_deref() != Ok("1") {
+            panic!("SBB_STUB_DIR is not set; set it to a stub registry, or SBB_NO_BUNDLED_STUBS=1 to build without stubs");
+        }
+        println!("cargo:warning=SBB_NO_BUNDLED_STUBS=1; this sbb will not contain any stubs");
+    }
+
+    // (directory name, stub image, sidecar JSON or "")
+    let mut code = String::from("pub static BUNDLED: &[(&str, &[u8], &str)] = &[\n");
+    for (name, path, sidecar) in &stubs {
+        code += &format!("    ({:?}, include_bytes!({:?}), {:?}),\n", name, path, sidecar);
+    }
+    code += "];\n";
+
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("bundled_stubs.rs");
+    fs::write(out, code).unwrap();
+}
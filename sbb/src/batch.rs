@@ -0,0 +1,300 @@
+//! `sbb build --keys`: one secured binary per machine of an inventory.
+//!
+//! The inventory is either a directory of key files, named after the
+//! machine's host name, or a CSV file with a header row containing at least
+//! `hostname` and `key_file` columns (paths relative to the CSV file; quoted
+//! fields are not supported). Extra CSV columns can be used in the output
+//! name template.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use common::fingerprint::{self, KeyFile};
+use common::log_error;
+use serde_json::json;
+
+use sbb::BuildReport;
+use sbb::builder::read_key_file;
+use sbb::config::BuildConfig;
+use sbb::error::usage;
+
+use crate::ReportFormat;
+
+/// Default output name template.
+pub const DEFAULT_TEMPLATE: &str = "{name}-{hostname}.secured";
+
+pub struct Machine {
+    pub hostname: String,
+    pub key: KeyFile,
+    /// Values for the name template, including `hostname`.
+    pub vars: Vec<(String, String)>,
+}
+
+struct Outcome {
+    output: PathBuf,
+    result: Result<BuildReport, String>,
+}
+
+pub fn secure_batch(settings: &BuildConfig, keys: &str, format: ReportFormat) -> Result<(), Box<dyn Error>> {
+    let machines = load_inventory(Path::new(keys))?;
+    if machines.is_empty() {
+        return Err(usage(format!("No machines found in {}", keys)));
+    }
+
+    let builder = settings.builder()?;
+    let input = settings.input.as_deref().unwrap_or_default();
+    let name = Path::new(input)
+        .file_stem()
+        .map_or_else(|| input.to_string(), |s| s.to_string_lossy().into_owned());
+    let template = settings.name_template.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+    let out_dir = Path::new(settings.output_dir.as_deref().unwrap_or("."));
+    let mut outputs = Vec::new();
+    for machine in &machines {
+        let file_name = render(template, &name, machine)?;
+        let output = out_dir.join(&file_name);
+        if outputs.contains(&output) {
+            return Err(usage(format!("Template {} gives the same output {} for several machines", template, file_name)));
+        }
+        outputs.push(output);
+    }
+    fs::create_dir_all(out_dir)?;
+
+    let plan = builder.plan()?;
+    let jobs = settings
+        .jobs
+        .map(|n| n as usize)
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+        .min(machines.len());
+
+    // Workers take the next machine until none are left
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Outcome>>> = Mutex::new((0..machines.len()).map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(machine) = machines.get(i) else { break };
+                    let output = outputs[i].clone();
+                    let result = plan
+                        .build_file(Some(&machine.key), &output)
+                        .map_err(|e| e.to_string());
+                    if let Err(e) = &result {
+                        log_error!("Build for {} failed: {}", machine.hostname, e);
+                    }
+                    results.lock().unwrap()[i] = Some(Outcome { output, result });
+                }
+            });
+        }
+    });
+    let outcomes: Vec<Outcome> = results.into_inner().unwrap().into_iter().flatten().collect();
+
+    match format {
+        ReportFormat::Text => print_summary(&machines, &outcomes),
+        ReportFormat::Json => println!("{:#}", json_report(&machines, &outcomes)),
+    }
+    if let Some(path) = &settings.summary {
+        fs::write(path, summary_csv(&machines, &outcomes))?;
+    }
+
+    let failed = outcomes.iter().filter(|o| o.result.is_err()).count();
+    if failed > 0 {
+        return Err(format!("{} of {} builds failed", failed, machines.len()).into());
+    }
+    Ok(())
+}
+
+/// Reads the machines of a key directory or CSV inventory.
+pub fn load_inventory(path: &Path) -> Result<Vec<Machine>, Box<dyn Error>> {
+    if path.is_dir() {
+        let mut machines = Vec::new();
+        for entry in fs::read_dir(path)?.flatten() {
+            let file = entry.path();
+            let is_key = matches!(file.extension().and_then(|e| e.to_str()), Some("txt" | "key"));
+            if !file.is_file() || !is_key {
+                continue;
+            }
+            let hostname = file.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            machines.push(machine(hostname, &file, Vec::new())?);
+        }
+        machines.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+        Ok(machines)
+    } else {
+        let text = fs::read_to_string(path).map_err(|e| usage(format!("Cannot read {}: {}", path.display(), e)))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        parse_csv(&text, base)
+    }
+}
+
+fn parse_csv(text: &str, base: &Path) -> Result<Vec<Machine>, Box<dyn Error>> {
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#'));
+    let columns: Vec<String> = lines
+        .next()
+        .ok_or_else(|| usage("Inventory is empty"))?
+        .split(',')
+        .map(|c| c.trim().to_string())
+        .collect();
+    let column = |name: &str| {
+        columns
+            .iter()
+            .position(|c| c == name)
+            .ok_or_else(|| usage(format!("Inventory has no '{}' column", name)))
+    };
+    let (host_col, key_col) = (column("hostname")?, column("key_file")?);
+
+    let mut machines = Vec::new();
+    for (n, line) in lines.enumerate() {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if fields.len() != columns.len() {
+            return Err(usage(format!("Inventory row {} has {} fields, expected {}", n + 1, fields.len(), columns.len())));
+        }
+        let vars = columns.iter().cloned().zip(fields.iter().map(|f| f.to_string())).collect();
+        machines.push(machine(fields[host_col].to_string(), &base.join(fields[key_col]), vars)?);
+    }
+    Ok(machines)
+}
+
+fn machine(hostname: String, key_file: &Path, mut vars: Vec<(String, String)>) -> Result<Machine, Box<dyn Error>> {
+    if hostname.is_empty() || hostname.contains(['/', '\\']) || hostname.starts_with('.') {
+        return Err(usage(format!("Invalid host name '{}' in inventory", hostname)));
+    }
+    let key = read_key_file(key_file)?;
+    if !vars.iter().any(|(k, _)| k == "hostname") {
+        vars.push(("hostname".into(), hostname.clone()));
+    }
+    vars.push(("key_id".into(), fingerprint::key_id(&key.fingerprint)));
+    Ok(Machine { hostname, key, vars })
+}
+
+/// Fills `{placeholder}`s in `template` with `name` (the input's file stem)
+/// and the machine's values.
+fn render(template: &str, name: &str, machine: &Machine) -> Result<String, Box<dyn Error>> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out += &rest[..start];
+        let end = rest[start..].find('}').ok_or_else(|| usage("Unclosed '{' in name template"))? + start;
+        let key = &rest[start + 1..end];
+        let value = if key == "name" {
+            name
+        } else {
+            machine
+                .vars
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+                .ok_or_else(|| usage(format!("Unknown placeholder {{{}}} in name template", key)))?
+        };
+        out += value;
+        rest = &rest[end + 1..];
+    }
+    out += rest;
+    if out.is_empty() || out.contains(['/', '\\']) {
+        return Err(usage(format!("Name template gives an invalid file name '{}'", out)));
+    }
+    Ok(out)
+}
+
+fn print_summary(machines: &[Machine], outcomes: &[Outcome]) {
+    for (machine, outcome) in machines.iter().zip(outcomes) {
+        let key_id = fingerprint::key_id(&machine.key.fingerprint);
+        match &outcome.result {
+            Ok(report) => println!(
+                "✅ {:<24} {}  {}  {}",
+                machine.hostname,
+                key_id,
+                report.output_sha256,
+                outcome.output.display()
+            ),
+            Err(e) => println!("❌ {:<24} {}  {}", machine.hostname, key_id, e),
+        }
+    }
+    let built = outcomes.iter().filter(|o| o.result.is_ok()).count();
+    println!("{} of {} secured binaries written", built, machines.len());
+}
+
+fn json_report(machines: &[Machine], outcomes: &[Outcome]) -> serde_json::Value {
+    let builds: Vec<_> = machines
+        .iter()
+        .zip(outcomes)
+        .map(|(machine, outcome)| match &outcome.result {
+            Ok(report) => json!({ "hostname": machine.hostname, "report": report.to_json() }),
+            Err(e) => json!({
+                "hostname": machine.hostname,
+                "key_id": fingerprint::key_id(&machine.key.fingerprint),
+                "output": outcome.output,
+                "error": e,
+            }),
+        })
+        .collect();
+    json!(builds)
+}
+
+fn summary_csv(machines: &[Machine], outcomes: &[Outcome]) -> String {
+    let mut csv = String::from("hostname,key_id,output,sha256,error\n");
+    for (machine, outcome) in machines.iter().zip(outcomes) {
+        let (sha256, error) = match &outcome.result {
+            Ok(report) => (report.output_sha256.as_str(), String::new()),
+            Err(e) => ("", e.replace([',', '\n'], " ")),
+        };
+        csv += &format!(
+            "{},{},{},{},{}\n",
+            machine.hostname,
+            fingerprint::key_id(&machine.key.fingerprint),
+            outcome.output.display(),
+            sha256,
+            error
+        );
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    fn inventory(csv: &str) -> (tempfile::TempDir, Vec<Machine>) {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::write(dir.path().join("web-01.key"), KEY).unwrap();
+        fs::write(dir.path().join("machines.csv"), csv).unwrap();
+        let machines = load_inventory(&dir.path().join("machines.csv")).unwrap();
+        (dir, machines)
+    }
+
+    #[test]
+    fn test_csv_inventory() {
+        let (_dir, machines) = inventory("hostname,key_file,site\nweb-01,web-01.key,ams\n");
+        assert_eq!(machines.len(), 1);
+        assert_eq!(machines[0].key.fingerprint, KEY);
+        assert_eq!(render(DEFAULT_TEMPLATE, "app", &machines[0]).unwrap(), "app-web-01.secured");
+        assert_eq!(render("{site}/{name}", "app", &machines[0]).err().unwrap().to_string(),
+            "Name template gives an invalid file name 'ams/app'");
+        assert_eq!(
+            render("{name}-{site}-{key_id}", "app", &machines[0]).unwrap(),
+            format!("app-ams-{}", fingerprint::key_id(KEY))
+        );
+        assert!(render("{nope}", "app", &machines[0]).is_err());
+    }
+
+    #[test]
+    fn test_directory_inventory() {
+        let (dir, _) = inventory("hostname,key_file\n");
+        fs::write(dir.path().join("README"), "not a key").unwrap();
+        let machines = load_inventory(dir.path()).unwrap();
+        assert_eq!(machines.iter().map(|m| m.hostname.as_str()).collect::<Vec<_>>(), ["web-01"]);
+    }
+
+    #[test]
+    fn test_rejects_bad_rows() {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::write(dir.path().join("a.key"), KEY).unwrap();
+        for csv in ["hostname\nweb\n", "hostname,key_file\nweb\n", "hostname,key_file\n../up,a.key\n"] {
+            assert!(parse_csv(csv, dir.path()).is_err(), "{}", csv);
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! `cargo sbb`: builds a crate's binaries in release mode and secures them.
+//!
+//! Settings come from `[package.metadata.sbb]` in the crate's Cargo.toml.
+//! It takes the keys of the `[build]` table of `sbb.toml`, `profile.<name>`
+//! tables, and two of its own:
+//!
+//! ```toml
+//! [package.metadata.sbb]
+//! targets = ["x86_64-unknown-linux-gnu", "aarch64-unknown-linux-gnu"]
+//! bins = ["server"]
+//! registry = "stubs"
+//! key = "keys/build-host.txt"
+//!
+//! [package.metadata.sbb.profile.eval]
+//! not-after = "2026-12-31"
+//! ```
+//!
+//! Each binary is written to `target/<triple>/secured/`.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use clap::Parser;
+use sbb::builder::read_key_file;
+use sbb::config::{BuildConfig, ConfigFile};
+use sbb::error::{self, usage};
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(clap::Parser)]
+#[command(bin_name = "cargo")]
+enum CargoCli {
+    /// Build the crate's binaries in release mode and secure them
+    Sbb(SbbArgs),
+}
+
+#[derive(clap::Args)]
+struct SbbArgs {
+    /// Path to Cargo.toml [default: the one cargo finds from here]
+    #[arg(long, value_name = "PATH")]
+    manifest_path: Option<String>,
+
+    /// Package to build, in a workspace with several
+    #[arg(long, short, value_name = "NAME")]
+    package: Option<String>,
+
+    /// Target triple to build for; repeatable [default: targets from the
+    /// metadata, or the host]
+    #[arg(long, value_name = "TRIPLE")]
+    target: Vec<String>,
+
+    /// Binary to build and secure; repeatable [default: bins from the
+    /// metadata, or all of the package's binaries]
+    #[arg(long, value_name = "NAME")]
+    bin: Vec<String>,
+
+    /// Apply this profile of the metadata on top of its top-level settings
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Bind the binaries to this key file instead of the metadata's key
+    #[arg(long, value_name = "KEY_FILE")]
+    key: Option<String>,
+}
+
+/// `[package.metadata.sbb]`.
+#[derive(Default)]
+struct Metadata {
+    targets: Vec<String>,
+    bins: Vec<String>,
+    profile: BTreeMap<String, BuildConfig>,
+    build: BuildConfig,
+}
+
+impl Metadata {
+    // The remaining keys are the build settings, so unknown ones are errors
+    fn from_value(value: &Value) -> Result<Self, Box<dyn Error>> {
+        let mut table = value.as_object().ok_or("expected a table")?.clone();
+        let mut take = |key: &str| table.remove(key).unwrap_or(Value::Null);
+        let list = |value: Value| -> Result<Vec<String>, serde_json::Error> {
+            match value {
+                Value::Null => Ok(Vec::new()),
+                value => serde_json::from_value(value),
+            }
+        };
+        let targets = list(take("targets"))?;
+        let bins = list(take("bins"))?;
+        let profile = match take("profile") {
+            Value::Null => BTreeMap::new(),
+            value => serde_json::from_value(value)?,
+        };
+        let build = BuildConfig::deserialize(Value::Object(table))?;
+        Ok(Metadata { targets, bins, profile, build })
+    }
+}
+
+struct Package {
+    name: String,
+    manifest_path: PathBuf,
+    bins: Vec<String>,
+    metadata: Metadata,
+}
+
+fn main() {
+    common::log::init("sbb");
+    let CargoCli::Sbb(args) = CargoCli::parse();
+    if let Err(e) = run(&args) {
+        eprintln!("❌ Error: {}", e);
+        std::process::exit(error::exit_code(e.as_ref()));
+    }
+}
+
+fn run(args: &SbbArgs) -> Result<(), Box<dyn Error>> {
+    let (package, target_dir) = cargo_metadata(args)?;
+    let dir = package.manifest_path.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let config = ConfigFile { build: package.metadata.build, profile: package.metadata.profile, base: dir };
+    let mut settings = config.settings(args.profile.as_deref())?;
+    if let Some(key) = &args.key {
+        settings.key = Some(key.clone());
+    }
+    for (field, set) in [
+        ("input", settings.input.is_some()),
+        ("output", settings.output.is_some()),
+        ("keys", settings.keys.is_some()),
+        ("output-dir", settings.output_dir.is_some()),
+        ("name-template", settings.name_template.is_some()),
+        ("summary", settings.summary.is_some()),
+        ("target", settings.target.is_some()),
+    ] {
+        if set {
+            return Err(usage(format!("'{}' is not used by cargo sbb; set targets and bins instead", field)));
+        }
+    }
+    settings.check()?;
+    let key = settings.key.as_deref().map(read_key_file).transpose()?;
+
+    let targets = match (&args.target, &package.metadata.targets) {
+        (cli, _) if !cli.is_empty() => cli.clone(),
+        (_, targets) if !targets.is_empty() => targets.clone(),
+        _ => vec![host_target()?],
+    };
+    let bins = match (&args.bin, &package.metadata.bins) {
+        (cli, _) if !cli.is_empty() => cli.clone(),
+        (_, bins) if !bins.is_empty() => bins.clone(),
+        _ => package.bins.clone(),
+    };
+    if bins.is_empty() {
+        return Err(usage(format!("Package {} has no binaries", package.name)));
+    }
+    if let Some(bin) = bins.iter().find(|b| !package.bins.contains(b)) {
+        return Err(usage(format!("Package {} has no binary named {}", package.name, bin)));
+    }
+
+    for target in &targets {
+        cargo_build(&package.manifest_path, target, &bins)?;
+        let secured_dir = target_dir.join(target).join("secured");
+        std::fs::create_dir_all(&secured_dir)?;
+
+        for bin in &bins {
+            let file_name = if target.contains("windows") { format!("{}.exe", bin) } else { bin.clone() };
+            let mut build = settings.clone();
+            build.input = Some(target_dir.join(target).join("release").join(&file_name).to_string_lossy().into_owned());
+            build.target = Some(target.clone());
+
+            let mut builder = build.builder()?;
+            if let Some(key) = &key {
+                builder = builder.key(key.clone());
+            }
+            let output = secured_dir.join(&file_name);
+            let report = builder.build_file(&output)?;
+            println!("✅ {:<32} {}  {}", target, report.output_sha256, output.display());
+        }
+    }
+    Ok(())
+}
+
+fn cargo() -> Command {
+    Command::new(std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into()))
+}
+
+// The package to secure and the target directory, from `cargo metadata`
+fn cargo_metadata(args: &SbbArgs) -> Result<(Package, PathBuf), Box<dyn Error>> {
+    let mut command = cargo();
+    command.args(["metadata", "--format-version", "1", "--no-deps"]);
+    if let Some(path) = &args.manifest_path {
+        command.args(["--manifest-path", path]);
+    }
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(format!("cargo metadata failed: {}", String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    let metadata: Value = serde_json::from_slice(&output.stdout)?;
+    let target_dir = PathBuf::from(metadata["target_directory"].as_str().ok_or("cargo metadata has no target_directory")?);
+
+    let packages = metadata["packages"].as_array().ok_or("cargo metadata has no packages")?;
+    let package = match &args.package {
+        Some(name) => packages
+            .iter()
+            .find(|p| p["name"] == name.as_str())
+            .ok_or_else(|| usage(format!("No package named {}", name)))?,
+        None => {
+            let with_sbb: Vec<&Value> = packages.iter().filter(|p| !p["metadata"]["sbb"].is_null()).collect();
+            match (packages.as_slice(), with_sbb.as_slice()) {
+                ([only], _) => only,
+                (_, [only]) => *only,
+                _ => return Err(usage("The workspace has several packages; pass --package")),
+            }
+        }
+    };
+
+    let name = package["name"].as_str().unwrap_or_default().to_string();
+    let metadata = match &package["metadata"]["sbb"] {
+        Value::Null => Metadata::default(),
+        value => Metadata::from_value(value)
+            .map_err(|e| usage(format!("Invalid [package.metadata.sbb] of {}: {}", name, e)))?,
+    };
+    let bins = package["targets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|t| t["kind"].as_array().is_some_and(|k| k.iter().any(|k| k == "bin")))
+        .filter_map(|t| t["name"].as_str().map(String::from))
+        .collect();
+    let manifest_path = PathBuf::from(package["manifest_path"].as_str().ok_or("cargo metadata has no manifest_path")?);
+    Ok((Package { name, manifest_path, bins, metadata }, target_dir))
+}
+
+fn cargo_build(manifest_path: &Path, target: &str, bins: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut command = cargo();
+    command.args(["build", "--release", "--target", target]).arg("--manifest-path").arg(manifest_path);
+    for bin in bins {
+        command.args(["--bin", bin]);
+    }
+    let status = command.status()?;
+    if !status.success() {
+        return Err(format!("cargo build for {} failed", target).into());
+    }
+    Ok(())
+}
+
+// The triple rustc builds for by default
+fn host_target() -> Result<String, Box<dyn Error>> {
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let output = Command::new(rustc).arg("-vV").output()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(String::from)
+        .ok_or_else(|| "Cannot determine the host target from rustc -vV".into())
+}
@@ -1,79 +1,834 @@
-use common::{fingerprint, crypto};
+//! Building secured binaries from Rust code.
+//!
+//! ```no_run
+//! use sbb::SecureBuilder;
+//! use sbb::builder::read_key_file;
+//!
+//! let report = SecureBuilder::new("target/release/app")
+//!     .key(read_key_file("host.key")?)
+//!     .target("x86_64-unknown-linux-gnu")
+//!     .max_launches(100)
+//!     .build_file("app.secured")?;
+//! println!("{} ({})", report.output_sha256, report.key_id);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::error::Error;
 use std::fs;
-use std::io::{self, BufRead};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use common::antidebug::{Action, Check};
+use common::bundle;
+use common::compress::{self, Compression};
+use common::crypto::{self, Cipher, Seal};
+use common::fingerprint::{self, KeyFile};
+use common::header::Header;
+use common::policy::Policy;
+use common::validity::{self, Clock, SystemClock};
+use common::{elf, log_debug, log_info, log_warn};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::container::Container;
 use crate::embed;
-use crate::Args;
-
-
-
-pub fn secure_binary(args: &Args) -> Result<String, Box<dyn std::error::Error>> {
-    println!("[*] Starting secure build for: {}", args.input);
-
-    // Generate output path if not specified
-    let output_path = format!("{}.secured", args.input);
-
-    // Get fingerprint - either from key file or generate random bytes
-    let fp = if args.encrypt {
-        // Read from key file
-        let key_path = args.key.as_ref().ok_or("Key file required when --encrypt is used")?;
-        let file = fs::File::open(key_path)?;
-        let mut lines = io::BufReader::new(file).lines();
-        let fp = lines.next().ok_or("Key file is empty")??;
-        if lines.next().is_some() {
-            return Err("Key file should contain only one line".into());
-        }
-        println!("[+] Using fingerprint from key file: {}", fp);
-        fp
-    } else {
-        // Generate random bytes instead of machine fingerprint
-        fingerprint::generate_random_key()
-    };
-
-    // Read binary
-    let bin_data = fs::read(&args.input)?;
-    println!("[+] Read {} bytes from input binary", bin_data.len());
-
-    // Encrypt binary with fingerprint/key
-    println!("[*] Encrypting binary...");
-    let encrypted = crypto::encrypt_binary(&fp, &bin_data)
-        .ok_or("Encryption failed")?;
-    println!("[+] Encrypted size: {} bytes", encrypted.len());
-    
-    println!("[*] Embedding into stub for target platform...");
-    let stub_path = get_stub_path(args);
-    println!("[*] Using stub from: {}", stub_path);
-    
-    // Embed multiple payloads (in this case, just one encrypted binary)
-    let payloads = if args.encrypt {
-        vec![encrypted]
-    } else {
-        vec![fp.as_bytes().to_vec(), encrypted]
-    };
-    
-    let output_bin = embed::embed_multiple_into_stub(&payloads ,&stub_path)?;
-    
-    // Save final binary
-    fs::write(&output_path, output_bin)?;
-    println!("✅ Secured binary written to {}", output_path);
-
-    // Set executable permissions on Unix
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let mut perms = fs::metadata(&output_path)?.permissions();
-        perms.set_mode(0o755);  // rwxr-xr-x
-        fs::set_permissions(&output_path, perms)?;
-    }
-
-    Ok(output_path)
+use crate::error::usage;
+use crate::input::{Format, InputInfo};
+use crate::libraries;
+use crate::scripts::{self, ScriptDir};
+use crate::stubs::{self, Stub};
+
+/// Options of a build. Setters consume and return the builder; nothing is
+/// read or checked until [`plan`](Self::plan) or one of the `build_*`
+/// methods is called.
+#[derive(Debug, Clone)]
+pub struct SecureBuilder {
+    input: PathBuf,
+    key: Option<KeyFile>,
+    target: Option<String>,
+    stub: Option<String>,
+    registry: Option<String>,
+    cipher: Option<Cipher>,
+    compression: Option<compress::Setting>,
+    bundle_libs: bool,
+    bundle_lib: Vec<PathBuf>,
+    entry: Option<String>,
+    record_components: bool,
+    epoch: Option<u64>,
+    force: bool,
+    header: Header,
+}
+
+/// What a build produced, for logs and release records. It never contains
+/// the key, only its id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildReport {
+    pub input: PathBuf,
+    /// Set by the `build_file` methods.
+    pub output: Option<PathBuf>,
+    pub input_sha256: String,
+    pub input_size: u64,
+    pub output_sha256: String,
+    pub output_size: u64,
+    pub stub_target: String,
+    /// Where the stub came from: `bundled:<name>` or a file path.
+    pub stub_source: String,
+    pub stub_sha256: String,
+    pub cipher: Cipher,
+    pub compression: Option<compress::Setting>,
+    /// Size of the program as encrypted, after compression.
+    pub payload_size: u64,
+    /// Shared libraries encrypted along with the program.
+    pub libraries: Vec<BundledFile>,
+    /// For a script directory, the files encrypted along with the entry
+    /// script.
+    pub script_files: Vec<BundledFile>,
+    /// Salt of the key derivation, for ciphers that use one.
+    pub kdf_salt: Option<[u8; 16]>,
+    pub key_id: String,
+    /// Whether the key is embedded in the output rather than bound to a machine.
+    pub embedded_key: bool,
+    pub build_id: String,
+    /// Build epoch of a reproducible build.
+    pub epoch: Option<u64>,
+    /// The container header, with the options the stub enforces.
+    pub header: Header,
+    pub timings: Timings,
+}
+
+/// A file encrypted along with the program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundledFile {
+    /// Name the loader looks for, or the path inside a script directory.
+    pub name: String,
+    pub path: PathBuf,
+    pub sha256: String,
+    pub size: u64,
+}
+
+impl BundledFile {
+    fn new(name: &str, path: &Path, data: &[u8]) -> Self {
+        BundledFile {
+            name: name.to_string(),
+            path: path.to_path_buf(),
+            sha256: hex::encode(Sha256::digest(data)),
+            size: data.len() as u64,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({ "name": self.name, "path": self.path, "sha256": self.sha256, "size": self.size })
+    }
+}
+
+/// Time spent in each step of a build.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timings {
+    /// Reading the input and selecting the stub; shared by the builds of a plan.
+    pub plan: Duration,
+    pub encrypt: Duration,
+    pub write: Duration,
+}
+
+impl Timings {
+    pub fn total(&self) -> Duration {
+        self.plan + self.encrypt + self.write
+    }
+}
+
+impl BuildReport {
+    /// The report as JSON, for CI artifacts and license systems.
+    pub fn to_json(&self) -> Value {
+        let header = &self.header;
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        json!({
+            "input": {
+                "path": self.input,
+                "sha256": self.input_sha256,
+                "size": self.input_size,
+            },
+            "output": {
+                "path": self.output,
+                "sha256": self.output_sha256,
+                "size": self.output_size,
+            },
+            "stub": {
+                "target": self.stub_target,
+                "source": self.stub_source,
+                "sha256": self.stub_sha256,
+            },
+            "compression": self.compression.map(|c| json!({
+                "codec": c.codec.name(),
+                "level": c.level,
+                "size": self.payload_size,
+            })),
+            "libraries": self.libraries.iter().map(BundledFile::to_json).collect::<Vec<_>>(),
+            "script": header.interpreter.as_ref().map(|interpreter| json!({
+                "interpreter": interpreter,
+                "entry": header.entry,
+                "files": self.script_files.iter().map(BundledFile::to_json).collect::<Vec<_>>(),
+            })),
+            "cipher": {
+                "name": self.cipher.name(),
+                "kdf": self.cipher.kdf(),
+                "kdf_salt": self.kdf_salt.map(hex::encode),
+            },
+            "key": {
+                "id": self.key_id,
+                "embedded": self.embedded_key,
+            },
+            "build": {
+                "id": self.build_id,
+                "date": header.build_date.map(validity::format_timestamp),
+                "product": header.product,
+                "version": header.version,
+                "reproducible_epoch": self.epoch,
+            },
+            "policy": header.restrictions(),
+            "timings_ms": {
+                "plan": ms(self.timings.plan),
+                "encrypt": ms(self.timings.encrypt),
+                "write": ms(self.timings.write),
+                "total": ms(self.timings.total()),
+            },
+        })
+    }
+}
+
+impl SecureBuilder {
+    pub fn new(input: impl AsRef<Path>) -> Self {
+        SecureBuilder {
+            input: input.as_ref().to_path_buf(),
+            key: None,
+            target: None,
+            stub: None,
+            registry: None,
+            cipher: None,
+            compression: None,
+            bundle_libs: false,
+            bundle_lib: Vec::new(),
+            entry: None,
+            record_components: false,
+            epoch: None,
+            force: false,
+            header: Header::default(),
+        }
+    }
+
+    /// Binds the output to the machine of this key; without a key, a random
+    /// key is embedded in the output.
+    pub fn key(mut self, key: KeyFile) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Target triple of the stub [default: detected from the input].
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// Stub executable to use instead of looking one up by target.
+    pub fn stub(mut self, path: impl AsRef<Path>) -> Self {
+        self.stub = Some(path.as_ref().to_string_lossy().into_owned());
+        self
+    }
+
+    /// Stub registry to search before the bundled stubs.
+    pub fn registry(mut self, dir: impl AsRef<Path>) -> Self {
+        self.registry = Some(dir.as_ref().to_string_lossy().into_owned());
+        self
+    }
+
+    /// Cipher suite [default: AES-256-GCM, or AES-256-GCM-SIV when reproducible].
+    pub fn cipher(mut self, cipher: Cipher) -> Self {
+        self.cipher = Some(cipher);
+        self
+    }
+
+    /// Compresses the program before encryption.
+    pub fn compress(mut self, setting: compress::Setting) -> Self {
+        self.compression = Some(setting);
+        self
+    }
+
+    /// Encrypts the shared libraries the program needs along with it,
+    /// except the C library and the loader.
+    pub fn bundle_libs(mut self, bundle: bool) -> Self {
+        self.bundle_libs = bundle;
+        self
+    }
+
+    /// Encrypts this shared library along with the program, under its
+    /// SONAME.
+    pub fn bundle_lib(mut self, path: impl AsRef<Path>) -> Self {
+        self.bundle_lib.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Script to run when the input is a directory, relative to it. The
+    /// other files of the directory are encrypted along with it.
+    pub fn entry(mut self, path: impl Into<String>) -> Self {
+        self.entry = Some(path.into());
+        self
+    }
+
+    /// Records the key file's component hashes in the header, so that
+    /// `SBB_DIAGNOSE` can tell which part of the machine changed. This
+    /// weakens the key binding: anyone with the binary can guess the CPU,
+    /// hostname and MAC one at a time, although each guess is slow.
+    pub fn record_components(mut self, record: bool) -> Self {
+        self.record_components = record;
+        self
+    }
+
+    /// Makes the output depend only on the input, key, options, stub and
+    /// this epoch, which is recorded as the build date.
+    pub fn reproducible(mut self, epoch: u64) -> Self {
+        self.epoch = Some(epoch);
+        self
+    }
+
+    /// Wraps inputs that are not executables for the target, or already
+    /// secured, with a warning instead of an error.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Launch restrictions evaluated by the stub before decryption.
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.header.policy = policy;
+        self
+    }
+
+    /// Refuse to run before this Unix time.
+    pub fn not_before(mut self, time: u64) -> Self {
+        self.header.not_before = Some(time);
+        self
+    }
+
+    /// Refuse to run after this Unix time.
+    pub fn not_after(mut self, time: u64) -> Self {
+        self.header.not_after = Some(time);
+        self
+    }
+
+    pub fn max_launches(mut self, n: u32) -> Self {
+        self.header.max_launches = Some(n);
+        self
+    }
+
+    pub fn max_instances(mut self, n: u32) -> Self {
+        self.header.max_instances = Some(n);
+        self
+    }
+
+    /// Runs `check` before decryption; a later action for the same check
+    /// replaces an earlier one.
+    pub fn anti_debug(mut self, check: Check, action: Action) -> Self {
+        self.header.anti_debug.retain(|(c, _)| *c != check);
+        self.header.anti_debug.push((check, action));
+        self
+    }
+
+    pub fn product(mut self, name: impl Into<String>) -> Self {
+        self.header.product = Some(name.into());
+        self
+    }
+
+    pub fn version(mut self, version: impl Into<String>) -> Self {
+        self.header.version = Some(version.into());
+        self
+    }
+
+    /// Sole argument that makes the secured binary print its manifest.
+    pub fn manifest_arg(mut self, arg: impl Into<String>) -> Self {
+        self.header.manifest_arg = Some(arg.into());
+        self
+    }
+
+    /// Checks the options without reading any file.
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        self.header_template().map(|_| ())
+    }
+
+    /// Reads the input and picks the stub, for one or more builds.
+    pub fn plan(&self) -> Result<BuildPlan, Box<dyn Error>> {
+        let start = Instant::now();
+        let mut header = self.header_template()?;
+        log_info!("Starting secure build for: {}", self.input.display());
+        let (input, script_dir) = self.read_input()?;
+        log_debug!("Read {} bytes from input binary", input.len());
+        let info = self.check_input(&input)?;
+        let (input_sha256, input_size) = (Sha256::digest(&input).into(), input.len() as u64);
+        if let Some(info) = info.as_ref().filter(|i| i.format == Format::Script) {
+            header.interpreter = info.interpreter.clone();
+        }
+        if let Some(dir) = &script_dir {
+            if header.interpreter.is_none() {
+                return Err(usage(format!("Entry {} is not a #! script", dir.entry)));
+            }
+            log_info!("Bundling {} files next to {}", dir.files.len(), dir.entry);
+            header.entry = Some(dir.entry.clone());
+        }
+
+        let libraries = match self.bundle_libs || !self.bundle_lib.is_empty() {
+            true => libraries::resolve(&input, &self.input, &self.bundle_lib, self.bundle_libs)?,
+            false => Vec::new(),
+        };
+        let plaintext = if let Some(dir) = &script_dir {
+            let files: Vec<(String, Vec<u8>)> = dir.files.iter().map(|f| (f.name.clone(), f.data.clone())).collect();
+            bundle::pack(&input, &files)
+        } else if !libraries.is_empty() {
+            log_info!("Bundling {} shared libraries", libraries.len());
+            let files: Vec<(String, Vec<u8>)> = libraries.iter().map(|l| (l.name.clone(), l.data.clone())).collect();
+            bundle::pack(&input, &files)
+        } else {
+            input
+        };
+        header.libraries = libraries.len() as u32;
+        header.compression =
+            self.compression.map(|c| Compression { codec: c.codec, original_size: plaintext.len() as u64 });
+
+        let stub = self.select_stub(info.as_ref(), &header)?;
+        log_info!("Using {} stub from {} (sha256 {})", stub.target, stub.source, stub.sha256_hex());
+        header.target = Some(stub.target.clone());
+        if stub.capabilities.as_ref().is_none_or(|c| c.has_feature("integrity")) {
+            header.stub_measurement = measure_stub(&stub)?;
+        }
+        // Stubs from before the record existed would reject the header
+        if stub.capabilities.as_ref().is_some_and(|c| c.has_feature("input-hash")) {
+            header.input_sha256 = Some(input_sha256);
+        }
+
+        let payload = match self.compression {
+            Some(setting) => {
+                let compressed = compress::compress(setting, &plaintext)?;
+                log_info!(
+                    "Compressed {} bytes to {} with {} level {}",
+                    plaintext.len(),
+                    compressed.len(),
+                    setting.codec.name(),
+                    setting.level
+                );
+                compressed
+            }
+            None => plaintext,
+        };
+        Ok(BuildPlan {
+            input_path: self.input.clone(),
+            input_sha256,
+            input_size,
+            payload,
+            compression: self.compression,
+            libraries: libraries.iter().map(|l| BundledFile::new(&l.name, &l.path, &l.data)).collect(),
+            script_files: script_dir
+                .iter()
+                .flat_map(|dir| &dir.files)
+                .map(|f| BundledFile::new(&f.name, &f.path, &f.data))
+                .collect(),
+            header,
+            stub,
+            epoch: self.epoch,
+            record_components: self.record_components,
+            plan_time: start.elapsed(),
+        })
+    }
+
+    /// Builds with the key set by [`key`](Self::key) and writes the secured
+    /// binary to `writer`.
+    pub fn build_to(&self, writer: &mut impl Write) -> Result<BuildReport, Box<dyn Error>> {
+        self.plan()?.build_to(self.key.as_ref(), writer)
+    }
+
+    /// Like [`build_to`](Self::build_to), writing an executable file.
+    pub fn build_file(&self, path: impl AsRef<Path>) -> Result<BuildReport, Box<dyn Error>> {
+        if same_file(&self.input, path.as_ref()) {
+            return Err(usage("The output must not overwrite the input file"));
+        }
+        self.plan()?.build_file(self.key.as_ref(), path)
+    }
+
+    // The header options shared by every build of this input
+    fn header_template(&self) -> Result<Header, Box<dyn Error>> {
+        let mut header = self.header.clone();
+        if let (Some(nb), Some(na)) = (header.not_before, header.not_after)
+            && nb >= na
+        {
+            return Err(usage("--not-before must be earlier than --not-after"));
+        }
+        if header.policy.install_paths.iter().any(|p| !Path::new(p).is_absolute()) {
+            return Err(usage("--install-path must be an absolute directory"));
+        }
+        if let Some(arg) = &header.manifest_arg {
+            // The program never sees this argument alone, so it must not be one it accepts
+            if arg.is_empty() || arg.contains('\0') {
+                return Err(usage("--manifest-arg must be a non-empty string"));
+            }
+        }
+
+        // Reproducible builds need a misuse-resistant cipher, since their
+        // nonce is derived rather than random
+        header.cipher = match (self.cipher, self.epoch) {
+            (None, Some(_)) => Cipher::Aes256GcmSiv,
+            (Some(cipher), Some(_)) if cipher != Cipher::Aes256GcmSiv => {
+                return Err(usage(format!("--reproducible needs the {} cipher", Cipher::Aes256GcmSiv.name())));
+            }
+            (cipher, _) => cipher.unwrap_or_default(),
+        };
+        header.build_date = Some(self.epoch.unwrap_or_else(|| SystemClock.now()));
+        Ok(header)
+    }
+
+    // The program, or the entry script and the other files of a directory
+    fn read_input(&self) -> Result<(Vec<u8>, Option<ScriptDir>), Box<dyn Error>> {
+        if self.input.is_dir() {
+            let entry = self.entry.as_deref().ok_or_else(|| {
+                usage(format!("{} is a directory; give the script to run with --entry", self.input.display()))
+            })?;
+            let (entry, dir) = scripts::read_dir(&self.input, entry)?;
+            return Ok((entry, Some(dir)));
+        }
+        if self.entry.is_some() {
+            return Err(usage("--entry needs a directory as input"));
+        }
+        if !self.input.is_file() {
+            return Err(usage(format!("Input file not found: {}", self.input.display())));
+        }
+        Ok((fs::read(&self.input)?, None))
+    }
+
+    // Refuses inputs the stub cannot run: unknown formats, shared libraries and
+    // binaries that are already secured. --force downgrades these to warnings.
+    fn check_input(&self, data: &[u8]) -> Result<Option<InputInfo>, Box<dyn Error>> {
+        let refuse = |problem: String| -> Result<(), Box<dyn Error>> {
+            if self.force {
+                log_warn!("{}; continuing because of --force", problem);
+                Ok(())
+            } else {
+                Err(usage(format!("{} (use --force to wrap it anyway)", problem)))
+            }
+        };
+
+        if Container::parse(data).is_ok() {
+            refuse(format!("{} is already a secured binary", self.input.display()))?;
+        }
+        let info = match InputInfo::detect(data) {
+            Ok(info) => info,
+            Err(e) => {
+                refuse(e)?;
+                return Ok(None);
+            }
+        };
+        log_info!("Input: {}", info);
+        if info.library {
+            refuse(format!("{} is a shared library, not an executable", self.input.display()))?;
+        }
+        Ok(Some(info))
+    }
+
+    // Picks the stub: --target if given, otherwise the best target for the input
+    fn select_stub(&self, info: Option<&InputInfo>, header: &Header) -> Result<Stub, Box<dyn Error>> {
+        let targets: Vec<Option<String>> = match (&self.target, info) {
+            (Some(target), _) => vec![Some(target.clone())],
+            (None, _) if self.stub.is_some() => vec![None],
+            (None, Some(info)) if info.arch.is_some() => {
+                info.preferred_targets(stubs::HOST_TARGET).into_iter().map(Some).collect()
+            }
+            (None, _) => vec![Some(stubs::HOST_TARGET.to_string())],
+        };
+
+        let mut first_error = None;
+        let mut stub = None;
+        for target in &targets {
+            match stubs::resolve(&stubs::Request {
+                target: target.as_deref(),
+                stub: self.stub.as_deref(),
+                registry: self.registry.as_deref(),
+                header,
+                cipher: header.cipher.name(),
+            }) {
+                Ok(found) => {
+                    stub = Some(found);
+                    break;
+                }
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        let stub = match stub {
+            Some(stub) => stub,
+            None => return Err(first_error.unwrap_or_else(|| usage("No stub available"))),
+        };
+
+        // A stub without a capability record does not say what it targets
+        if let Some(info) = info
+            && stub.capabilities.is_some()
+            && let Some(problem) = info.mismatch(&stub.target)
+        {
+            if !self.force {
+                return Err(usage(format!("{} (use --force to wrap it anyway)", problem)));
+            }
+            log_warn!("{}; continuing because of --force", problem);
+        }
+        Ok(stub)
+    }
+}
+
+/// Everything that is shared by the builds of one input: the program, the
+/// stub and the header options. Each build binds it to one key.
+pub struct BuildPlan {
+    input_path: PathBuf,
+    input_sha256: [u8; 32],
+    input_size: u64,
+    /// The program as it is encrypted, with any bundled libraries and
+    /// compressed if asked to.
+    payload: Vec<u8>,
+    compression: Option<compress::Setting>,
+    libraries: Vec<BundledFile>,
+    script_files: Vec<BundledFile>,
+    header: Header,
+    stub: Stub,
+    /// Build epoch of a reproducible build.
+    epoch: Option<u64>,
+    record_components: bool,
+    plan_time: Duration,
+}
+
+impl BuildPlan {
+    /// Writes a secured binary bound to `key`, or with a random embedded key
+    /// if there is none.
+    ///
+    /// A reproducible build derives everything that is otherwise random
+    /// (embedded key, build id, salt and nonce) from the SHA-256 of the
+    /// input, the key id and the build epoch.
+    pub fn build_to(&self, key: Option<&KeyFile>, writer: &mut impl Write) -> Result<BuildReport, Box<dyn Error>> {
+        if let Some(epoch) = self.epoch {
+            log_info!("Reproducible build with epoch {}", validity::format_timestamp(epoch));
+        }
+        if let Some(na) = self.header.not_after {
+            log_info!("Valid until {}", validity::format_timestamp(na));
+        }
+
+        // Get fingerprint - either from key file or generate random bytes
+        let (fp, components) = match key {
+            Some(key) => {
+                log_info!("Using key {} from key file", fingerprint::key_id(&key.fingerprint));
+                if !self.record_components {
+                    (key.fingerprint.clone(), None)
+                } else if key.components.is_none() {
+                    log_warn!("Key file has no component hashes to record; create it again with sbb keygen");
+                    (key.fingerprint.clone(), None)
+                } else {
+                    log_warn!("Recording component hashes; each component can be guessed from the binary");
+                    (key.fingerprint.clone(), key.components.clone())
+                }
+            }
+            None => match self.epoch {
+                Some(_) => (hex::encode(self.derive("sbb-embedded-key", "")), None),
+                None => (fingerprint::generate_random_key(), None),
+            },
+        };
+
+        // Every output is a separate build with its own local state
+        let mut header = self.header.clone();
+        let key_id = fingerprint::key_id(&fp);
+        let seal = match self.epoch {
+            Some(_) => {
+                header.build_id = self.derive("sbb-build-id", &key_id)[..16].try_into().unwrap();
+                Seal::from_seed(&self.derive("sbb-seal", &key_id))
+            }
+            None => {
+                header.build_id = Header::new().build_id;
+                Seal::random()
+            }
+        };
+        header.components = components;
+        let header_bytes = header.to_bytes();
+
+        // Encrypt binary with fingerprint/key
+        let start = Instant::now();
+        log_debug!("Encrypting binary with {}", header.cipher.name());
+        let encrypted = crypto::encrypt(header.cipher, &fp, &self.payload, &header_bytes, &seal)
+            .ok_or("Encryption failed")?;
+        log_debug!("Encrypted size: {} bytes", encrypted.len());
+
+        // Embed multiple payloads: [fingerprint], header, encrypted binary
+        let payloads = if key.is_some() {
+            vec![header_bytes, encrypted]
+        } else {
+            vec![fp.as_bytes().to_vec(), header_bytes, encrypted]
+        };
+        let output = embed::embed_multiple_into_stub(&payloads, &self.stub.bytes);
+        let encrypt_time = start.elapsed();
+        let start = Instant::now();
+        writer.write_all(&output)?;
+        writer.flush()?;
+
+        Ok(BuildReport {
+            input: self.input_path.clone(),
+            output: None,
+            input_sha256: hex::encode(self.input_sha256),
+            input_size: self.input_size,
+            output_sha256: hex::encode(Sha256::digest(&output)),
+            output_size: output.len() as u64,
+            stub_target: self.stub.target.clone(),
+            stub_source: self.stub.source.clone(),
+            stub_sha256: self.stub.sha256_hex(),
+            cipher: header.cipher,
+            compression: self.compression,
+            payload_size: self.payload.len() as u64,
+            libraries: self.libraries.clone(),
+            script_files: self.script_files.clone(),
+            kdf_salt: (header.cipher == Cipher::Aes256GcmSiv).then_some(seal.salt),
+            key_id,
+            embedded_key: key.is_none(),
+            build_id: header.build_id_hex(),
+            epoch: self.epoch,
+            header,
+            timings: Timings { plan: self.plan_time, encrypt: encrypt_time, write: start.elapsed() },
+        })
+    }
+
+    /// Like [`build_to`](Self::build_to), writing an executable file.
+    pub fn build_file(&self, key: Option<&KeyFile>, path: impl AsRef<Path>) -> Result<BuildReport, Box<dyn Error>> {
+        let path = path.as_ref();
+        let mut file = fs::File::create(path)?;
+        let mut report = self.build_to(key, &mut file)?;
+        report.output = Some(path.to_path_buf());
+        log_info!("Secured binary written to {}", path.display());
+
+        // Set executable permissions on Unix
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mut perms = fs::metadata(path)?.permissions();
+            perms.set_mode(0o755);  // rwxr-xr-x
+            fs::set_permissions(path, perms)?;
+        }
+        Ok(report)
+    }
+
+    // Deterministic per-build value for a reproducible build
+    fn derive(&self, label: &str, key_id: &str) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(label.as_bytes());
+        hasher.update([0]);
+        hasher.update(self.input_sha256);
+        hasher.update(key_id.as_bytes());
+        hasher.update(self.epoch.unwrap_or_default().to_le_bytes());
+        hasher.finalize().into()
+    }
+}
+
+/// Reads and parses a key file, reporting problems as usage errors.
+pub fn read_key_file(path: impl AsRef<Path>) -> Result<KeyFile, Box<dyn Error>> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)
+        .map_err(|e| usage(format!("Cannot read key file {}: {}", path.display(), e)))?;
+    KeyFile::parse(&contents).map_err(|e| usage(format!("Invalid key file {}: {}", path.display(), e)))
 }
 
-// Helper function for choosing the right stub path based on target platform
-pub fn get_stub_path(args: &Args) -> String {
-    let stub_path = if args.windows {
-        ".\\stub.exe"
-    } else {
-        "./stub"
-    };
-    stub_path.to_string()
-}
\ No newline at end of file
+/// Whether two paths name the same existing file.
+pub fn same_file(a: impl AsRef<Path>, b: impl AsRef<Path>) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+// Measures the stub's loadable segments so it can detect patching at run time
+fn measure_stub(stub: &Stub) -> Result<Option<[u8; 32]>, Box<dyn Error>> {
+    if !stub.bytes.starts_with(elf::ELF_MAGIC) {
+        log_warn!("Stub is not an ELF image; skipping self-integrity measurement");
+        return Ok(None);
+    }
+    let measurement = elf::measure_load_segments(&stub.bytes)?;
+    log_debug!("Stub measurement: {}", hex::encode(measurement));
+    Ok(Some(measurement))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::fingerprint::ComponentHashes;
+    use tempfile::TempDir;
+
+    const KEY: &str = "1111111111111111111111111111111111111111111111111111111111111111";
+
+    fn project() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("stub"), b"placeholder stub").unwrap();
+        fs::copy("/bin/true", dir.path().join("app")).unwrap();
+        dir
+    }
+
+    fn key() -> KeyFile {
+        KeyFile::parse(KEY).unwrap()
+    }
+
+    #[test]
+    fn test_build_report() {
+        let dir = project();
+        let mut output = Vec::new();
+        let report = SecureBuilder::new(dir.path().join("app"))
+            .stub(dir.path().join("stub"))
+            .key(key())
+            .max_launches(3)
+            .build_to(&mut output)
+            .unwrap();
+
+        assert_eq!(report.output_size, output.len() as u64);
+        assert_eq!(report.output_sha256, hex::encode(Sha256::digest(&output)));
+        assert_eq!(report.input_size, fs::metadata("/bin/true").unwrap().len());
+        assert_eq!(report.key_id, fingerprint::key_id(KEY));
+        assert_eq!(report.cipher, Cipher::Aes256Gcm);
+        assert!(!report.embedded_key);
+
+        let container = Container::parse(&output).unwrap();
+        assert_eq!(container.header.max_launches, Some(3));
+        assert_eq!(container.header.build_id_hex(), report.build_id);
+        assert_eq!(container.decrypt(KEY).unwrap(), fs::read("/bin/true").unwrap());
+    }
+
+    #[test]
+    fn test_reproducible_output() {
+        let dir = project();
+        let builder = SecureBuilder::new(dir.path().join("app")).stub(dir.path().join("stub")).reproducible(1_700_000_000);
+        let build = |builder: &SecureBuilder| {
+            let mut output = Vec::new();
+            builder.build_to(&mut output).unwrap();
+            output
+        };
+        assert_eq!(build(&builder), build(&builder));
+        assert_ne!(build(&builder), build(&builder.clone().key(key())));
+        assert!(builder.clone().cipher(Cipher::Aes256Gcm).check().is_err());
+    }
+
+    #[test]
+    fn test_records_components_only_when_asked() {
+        let dir = project();
+        let components = fingerprint::Components { hostname: Some("build-01".into()), ..Default::default() };
+        let key = KeyFile { components: Some(ComponentHashes::from_components(&components)), ..key() };
+        let header = |record: bool| {
+            let mut output = Vec::new();
+            let builder = SecureBuilder::new(dir.path().join("app")).stub(dir.path().join("stub"));
+            builder.key(key.clone()).record_components(record).build_to(&mut output).unwrap();
+            Container::parse(&output).unwrap().header
+        };
+        assert_eq!(header(false).components, None);
+        assert_eq!(header(true).components, key.components);
+    }
+
+    #[test]
+    fn test_check_options() {
+        assert!(SecureBuilder::new("app").not_before(20).not_after(10).check().is_err());
+        assert!(SecureBuilder::new("app").manifest_arg("").check().is_err());
+        let policy = Policy { install_paths: vec!["relative/dir".into()], ..Default::default() };
+        assert!(SecureBuilder::new("app").policy(policy).check().is_err());
+        assert!(SecureBuilder::new("app").not_after(10).anti_debug(Check::Tracer, Action::Refuse).check().is_ok());
+    }
+}
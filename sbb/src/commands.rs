@@ -0,0 +1,473 @@
+//! Subcommands that work on existing secured binaries and key files.
+
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use common::capabilities::Capabilities;
+use common::crypto::{self, Seal};
+use common::{bundle, elf, embed};
+use common::fingerprint::{self, COMPONENT_NAMES, ComponentHashes, KeyFile};
+use common::validity::{self, Clock, SystemClock, ValidityError};
+use common::{log_debug, log_info, log_warn};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use sbb::builder::{read_key_file, same_file};
+use sbb::config::{self, ConfigFile};
+use sbb::container::{Container, Contents};
+use sbb::error::{SbbError, usage};
+use sbb::stubs::{self, Stub};
+use crate::{ExtractArgs, InspectArgs, KeygenArgs, RekeyArgs, ReportFormat, VerifyArgs};
+
+pub fn inspect(args: &InspectArgs) -> Result<(), Box<dyn Error>> {
+    let data = read_input(&args.file)?;
+    let container = Container::parse(&data)?;
+    let header = &container.header;
+    let stub = &data[..container.stub_len];
+    let caps = Capabilities::find_in(stub);
+    let integrity = stub_integrity(&container, stub).describe();
+    let sections: Vec<_> = container
+        .sections
+        .iter()
+        .map(|s| (s.kind.name(), s.range.start, s.range.len(), hex::encode(Sha256::digest(&data[s.range.clone()]))))
+        .collect();
+    let compressed_size = container.encrypted.len() - header.cipher.overhead();
+
+    if args.format == ReportFormat::Json {
+        let report = json!({
+            "file": args.file,
+            "size": data.len(),
+            "stub": {
+                "target": caps.as_ref().map(|c| &c.target),
+                "container_versions": caps.as_ref().map(|c| &c.container_versions),
+                "features": caps.as_ref().map(|c| &c.features),
+                "sha256": sections[0].3,
+                "integrity": integrity,
+            },
+            "container_version": container.version(),
+            "input_sha256": header.input_sha256.map(hex::encode),
+            "sections": sections
+                .iter()
+                .map(|(kind, offset, length, sha256)| json!({
+                    "kind": kind,
+                    "offset": offset,
+                    "length": length,
+                    "sha256": sha256,
+                }))
+                .collect::<Vec<_>>(),
+            "compression": header.compression.map(|c| json!({
+                "codec": c.codec.name(),
+                "original_size": c.original_size,
+                "compressed_size": compressed_size,
+                "ratio": compressed_size as f64 / c.original_size.max(1) as f64,
+            })),
+            "libraries": header.libraries,
+            "script": header.interpreter.as_ref().map(|i| json!({ "interpreter": i, "entry": header.entry })),
+            "cipher": { "name": header.cipher.name(), "id": header.cipher.id(), "kdf": header.cipher.kdf() },
+            "key": {
+                "embedded": container.embedded_key.is_some(),
+                "id": container.embedded_key.as_deref().map(fingerprint::key_id),
+            },
+            "signature": "none",
+            "manifest": header.manifest(),
+            "policy": header.restrictions(),
+        });
+        println!("{:#}", report);
+        return Ok(());
+    }
+
+    let stub_id = match &caps {
+        Some(caps) => format!(
+            "{}, container versions {}",
+            caps.target,
+            caps.container_versions.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" ")
+        ),
+        None => "no capability record".to_string(),
+    };
+    println!("{} ({} bytes)", args.file, data.len());
+    println!("  stub:        {}", stub_id);
+    println!("  integrity:   {}", integrity);
+    println!("  container:   version {}", container.version());
+    println!("  key:         {}", key_mode(&container));
+    if let Some(hash) = header.input_sha256 {
+        println!("  input:       sha256 {}", hex::encode(hash));
+    }
+    if let Some(c) = &header.compression {
+        println!(
+            "  compression: {}, {} bytes to {} ({:.1}%)",
+            c.codec.name(),
+            c.original_size,
+            compressed_size,
+            compressed_size as f64 * 100.0 / c.original_size.max(1) as f64
+        );
+    }
+    if header.libraries > 0 {
+        println!("  libraries:   {} bundled", header.libraries);
+    }
+    if let Some(interpreter) = &header.interpreter {
+        match &header.entry {
+            Some(entry) => println!("  script:      {} run by {}", entry, interpreter),
+            None => println!("  script:      run by {}", interpreter),
+        }
+    }
+    println!("  cipher:      {} (id {}), key derivation {}", header.cipher.name(), header.cipher.id(), header.cipher.kdf());
+    println!("  signature:   none (the container format has no signatures)");
+    println!("  sections:");
+    for (kind, offset, length, sha256) in &sections {
+        println!("    {:<13} offset {:>10}  length {:>10}  sha256 {}", kind, offset, length, sha256);
+    }
+    println!("  manifest:    {:#}", header.manifest());
+    println!("  policy:      {:#}", header.restrictions());
+    Ok(())
+}
+
+pub fn verify(args: &VerifyArgs) -> Result<(), Box<dyn Error>> {
+    let data = read_input(&args.file)?;
+    let container = Container::parse(&data)?;
+    let header = &container.header;
+    let key = select_key(&container, args.key.as_deref())?;
+
+    // What the stub checks before decrypting, as far as it does not depend
+    // on the machine the binary will run on
+    let stub = &data[..container.stub_len];
+    if let Some(caps) = Capabilities::find_in(stub) {
+        let missing = caps.missing_for(header, header.cipher.name());
+        if !missing.is_empty() {
+            return Err(SbbError::InvalidContainer(format!("the stub lacks {}", missing.join(", "))).into());
+        }
+    }
+    match validity::check_window(header, SystemClock.now(), &[]) {
+        Err(e @ ValidityError::NotYetValid { .. }) => log_warn!("{}", e),
+        Err(e) => return Err(e.into()),
+        Ok(()) => {}
+    }
+
+    // The plaintext is only hashed, never written
+    let (payload, integrity) = open(&container, stub, &key)?;
+    let contents = checked_contents(&container, &payload)?;
+    let input_sha256 = hex::encode(Sha256::digest(&contents.program));
+    let input = match header.input_sha256 {
+        Some(_) => format!("sha256 {} matches the recorded hash", input_sha256),
+        None => format!("sha256 {} (no hash recorded by this stub)", input_sha256),
+    };
+    println!("✅ {} decrypts with key {}", args.file, fingerprint::key_id(&key));
+    println!("  input:       {}", input);
+    if let Some(entry) = &header.entry {
+        println!("  script:      {} with {} other files", entry, contents.files.len());
+    } else if !contents.files.is_empty() {
+        let names: Vec<&str> = contents.files.iter().map(|(name, _)| name.as_str()).collect();
+        println!("  libraries:   {}", names.join(", "));
+    }
+    println!("  integrity:   {}", integrity.describe());
+    println!("  signature:   none (the container format has no signatures)");
+    Ok(())
+}
+
+pub fn extract(args: &ExtractArgs) -> Result<(), Box<dyn Error>> {
+    if same_file(&args.file, &args.output) {
+        return Err(usage("--output must not overwrite the secured binary"));
+    }
+    let data = read_input(&args.file)?;
+    let container = Container::parse(&data)?;
+    let key = read_key_file(&args.key)?.fingerprint;
+    let (payload, _) = open(&container, &data[..container.stub_len], &key)?;
+    let contents = checked_contents(&container, &payload)?;
+    log_info!("Recovering build {} with key {}", container.header.build_id_hex(), fingerprint::key_id(&key));
+
+    if let Some(entry) = &container.header.entry {
+        // A script directory comes back as a directory
+        if !bundle::valid_path(entry) {
+            return Err(SbbError::InvalidContainer("the entry script has an invalid path".into()).into());
+        }
+        create_private_dir(&args.output)?;
+        write_dir_file(&args.output, entry, &contents.program)?;
+        for (name, data) in &contents.files {
+            write_dir_file(&args.output, name, data)?;
+        }
+        println!("✅ Script directory written to {} ({} and {} other files)", args.output, entry, contents.files.len());
+        return Ok(());
+    }
+
+    // Library names become file names, so they must not leave the directory
+    if contents.files.iter().any(|(name, _)| !bundle::valid_name(name)) {
+        return Err(SbbError::InvalidContainer("a bundled library has an invalid name".into()).into());
+    }
+    write_new_file(&args.output, &contents.program, 0o700)?;
+    println!("✅ Original binary written to {}", args.output);
+    if !contents.files.is_empty() {
+        // Next to the program, under the names its loader looks for
+        let dir = format!("{}.libs", args.output);
+        create_private_dir(&dir)?;
+        for (name, data) in &contents.files {
+            write_new_file(&format!("{}/{}", dir, name), data, 0o700)?;
+        }
+        println!("✅ {} bundled libraries written to {}", contents.files.len(), dir);
+    }
+    Ok(())
+}
+
+pub fn rekey(args: &RekeyArgs) -> Result<(), Box<dyn Error>> {
+    if same_file(&args.file, &args.output) {
+        return Err(usage("--output must not overwrite the secured binary"));
+    }
+    let data = read_input(&args.file)?;
+    let container = Container::parse(&data)?;
+    let old_key = select_key(&container, args.old_key.as_deref())?;
+    let new_key = read_key_file(&args.new_key)?;
+    let (payload, _) = open(&container, &data[..container.stub_len], &old_key)?;
+    checked_contents(&container, &payload)?;
+
+    // Everything except the key binding stays as built: stub, manifest,
+    // policy, compression and the recorded input hash. There is no data key
+    // to rewrap, so the payload is encrypted again under the new key. The
+    // old key's component hashes go, and the new key's are only recorded on
+    // request, since they let each component be guessed from the binary.
+    let mut header = container.header.clone();
+    header.components = match args.record_components {
+        true => new_key.components,
+        false => None,
+    };
+    let header_bytes = header.to_bytes();
+    let encrypted = crypto::encrypt(header.cipher, &new_key.fingerprint, &payload, &header_bytes, &Seal::random())
+        .ok_or("Encryption failed")?;
+    log_info!(
+        "Rebinding build {} from key {} to key {}",
+        header.build_id_hex(),
+        fingerprint::key_id(&old_key),
+        fingerprint::key_id(&new_key.fingerprint)
+    );
+
+    let output = embed::append_payloads(&data[..container.stub_len], &[header_bytes, encrypted]);
+    write_file(&args.output, &output, 0o755)?;
+    println!("✅ Rekeyed binary written to {}", args.output);
+    Ok(())
+}
+
+pub fn keygen(args: &KeygenArgs) -> Result<(), Box<dyn Error>> {
+    let components = fingerprint::collect_components();
+    let key = KeyFile {
+        fingerprint: fingerprint::fingerprint_from(&components),
+        components: Some(ComponentHashes::from_components(&components)),
+    };
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&args.output)
+        .map_err(|e| usage(format!("Cannot create {}: {}", args.output, e)))?;
+    file.write_all(key.to_file_string().as_bytes())?;
+    println!("✅ Key {} written to {}", fingerprint::key_id(&key.fingerprint), args.output);
+    Ok(())
+}
+
+pub fn fingerprint() -> Result<(), Box<dyn Error>> {
+    let components = fingerprint::collect_components();
+    println!("key id:      {}", fingerprint::key_id(&fingerprint::fingerprint_from(&components)));
+    for name in COMPONENT_NAMES {
+        println!("{:<12} {}", format!("{}:", name), components.get(name).unwrap_or("unavailable"));
+    }
+    Ok(())
+}
+
+pub fn list_stubs(registry: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let mut stubs = match registry {
+        Some(dir) => stubs::registry(dir)?,
+        None => Vec::new(),
+    };
+    stubs.extend(stubs::bundled());
+    stubs.extend(stubs::local());
+
+    if stubs.is_empty() {
+        println!("No stubs available; pass --registry, rebuild sbb with SBB_STUB_DIR set, or pass --stub to build");
+    }
+    for stub in &stubs {
+        print_stub(stub);
+    }
+    Ok(())
+}
+
+pub fn add_stub(path: &str, registry: &str, name: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let stub = stubs::add_to_registry(path, registry, name)?;
+    print_stub(&stub);
+    println!("✅ Stub added to {}", registry);
+    Ok(())
+}
+
+pub fn validate_config(path: Option<&str>, profile: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let config = ConfigFile::find(path)?.ok_or_else(|| usage(format!("No {} in the current directory", config::DEFAULT_PATH)))?;
+    let problems = config.validate(profile);
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    if !problems.is_empty() {
+        return Err(usage(format!("{} problem(s) in the config file", problems.len())));
+    }
+    let checked = match profile {
+        Some(name) => format!("profile '{}'", name),
+        None => format!("[build] and {} profile(s)", config.profile.len()),
+    };
+    println!("✅ Config is valid ({})", checked);
+    Ok(())
+}
+
+fn print_stub(stub: &Stub) {
+    println!("{:<32} {}  {:>9} bytes  {}", stub.target, stub.sha256_hex(), stub.bytes.len(), stub.source);
+    match &stub.capabilities {
+        Some(caps) => println!(
+            "{:<32} container versions: {}, ciphers: {}, features: {}",
+            "",
+            caps.container_versions.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" "),
+            caps.ciphers.join(" "),
+            caps.features.join(" ")
+        ),
+        None => println!("{:<32} no capability record", ""),
+    }
+}
+
+fn read_input(path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    if !Path::new(path).is_file() {
+        return Err(usage(format!("File not found: {}", path)));
+    }
+    Ok(fs::read(path)?)
+}
+
+// The explicit key file if given, otherwise the key embedded in the binary
+fn select_key(container: &Container, key_path: Option<&str>) -> Result<String, Box<dyn Error>> {
+    match (key_path, &container.embedded_key) {
+        (Some(path), _) => Ok(read_key_file(path)?.fingerprint),
+        (None, Some(key)) => {
+            log_debug!("Using the key embedded in the binary");
+            Ok(key.clone())
+        }
+        (None, None) => Err(usage("This binary is bound to a machine; pass its key file with --key")),
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum Integrity {
+    NotMeasured,
+    NotElf,
+    Unreadable,
+    Matches,
+    Differs,
+}
+
+impl Integrity {
+    fn describe(&self) -> &'static str {
+        match self {
+            Integrity::NotMeasured => "not measured",
+            Integrity::NotElf => "measured, but the stub is not an ELF image",
+            Integrity::Unreadable => "measured, but the stub's segments cannot be read",
+            Integrity::Matches => "stub matches its build-time measurement",
+            Integrity::Differs => "stub does not match its build-time measurement",
+        }
+    }
+}
+
+// Whether the stub still matches the measurement taken at build time
+fn stub_integrity(container: &Container, stub: &[u8]) -> Integrity {
+    let Some(expected) = container.header.stub_measurement else { return Integrity::NotMeasured };
+    if !stub.starts_with(elf::ELF_MAGIC) {
+        return Integrity::NotElf;
+    }
+    match elf::measure_load_segments(stub) {
+        Ok(actual) if actual == expected => Integrity::Matches,
+        Ok(_) => Integrity::Differs,
+        Err(_) => Integrity::Unreadable,
+    }
+}
+
+// Decrypts the payload of a binary whose stub is unmodified
+fn open(container: &Container, stub: &[u8], key: &str) -> Result<(Vec<u8>, Integrity), Box<dyn Error>> {
+    let integrity = stub_integrity(container, stub);
+    if integrity == Integrity::Differs {
+        return Err(SbbError::InvalidContainer(integrity.describe().into()).into());
+    }
+    Ok((container.decrypt(key)?, integrity))
+}
+
+// The contents of a decrypted payload, with the program checked against the
+// input hash recorded at build time
+fn checked_contents(container: &Container, payload: &[u8]) -> Result<Contents, Box<dyn Error>> {
+    let contents = container.contents(payload)?;
+    if let Some(recorded) = container.header.input_sha256
+        && recorded != <[u8; 32]>::from(Sha256::digest(&contents.program))
+    {
+        return Err(SbbError::InvalidContainer("the payload does not match the recorded input hash".into()).into());
+    }
+    Ok(contents)
+}
+
+fn key_mode(container: &Container) -> String {
+    match &container.embedded_key {
+        Some(key) => format!("embedded (id {})", fingerprint::key_id(key)),
+        None => "bound to a machine key".to_string(),
+    }
+}
+
+// Creates a file that did not exist, with `mode` from the start, so the
+// plaintext is never readable by others and never replaces another file
+fn write_new_file(path: &str, data: &[u8], mode: u32) -> Result<(), Box<dyn Error>> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    let mut file = options.open(path).map_err(|e| usage(format!("Cannot create {}: {}", path, e)))?;
+    file.write_all(data)?;
+    // The umask may have cleared bits of `mode`
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+// Creates a directory that did not exist, readable only by its owner
+fn create_private_dir(path: &str) -> Result<(), Box<dyn Error>> {
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(path).map_err(|e| usage(format!("Cannot create {}: {}", path, e)))?;
+    Ok(())
+}
+
+// Writes `name`, a path inside a script directory, below `dir`, creating
+// its parent directories as needed
+fn write_dir_file(dir: &str, name: &str, data: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut path = dir.to_string();
+    let mut parts = name.split('/').peekable();
+    while let Some(part) = parts.next() {
+        path = format!("{}/{}", path, part);
+        if parts.peek().is_none() {
+            return write_new_file(&path, data, 0o700);
+        }
+        if !Path::new(&path).is_dir() {
+            create_private_dir(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_file(path: &str, data: &[u8], mode: u32) -> Result<(), Box<dyn Error>> {
+    fs::write(path, data)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    Ok(())
+}
+
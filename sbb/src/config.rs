@@ -0,0 +1,379 @@
+//! `sbb.toml`: build settings kept in the repository, with named profiles.
+//!
+//! ```toml
+//! [build]
+//! input = "target/release/app"
+//! registry = "stubs"
+//! product = "App"
+//! anti-debug = ["all=refuse"]
+//!
+//! [profile.eval]
+//! not-after = "2026-12-31"
+//! max-launches = 50
+//!
+//! [profile.production]
+//! keys = "fleet.csv"
+//! output-dir = "dist"
+//! ```
+//!
+//! `[build]` applies to every build and a profile overrides its fields.
+//! Command line options override both. Relative paths are resolved against
+//! the directory of the config file.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use common::{antidebug, compress};
+use common::crypto::Cipher;
+use common::policy::Policy;
+use common::validity;
+use serde::Deserialize;
+
+use crate::SecureBuilder;
+use crate::error::usage;
+
+/// Looked up in the current directory when `--config` is not given.
+pub const DEFAULT_PATH: &str = "sbb.toml";
+
+/// Environment variable with the build epoch for `reproducible`, as
+/// defined by reproducible-builds.org.
+pub const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+/// The options of `sbb build`, each optional so that settings from several
+/// places can be layered with [`overlay`](Self::overlay).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BuildConfig {
+    pub input: Option<String>,
+    pub key: Option<String>,
+    pub keys: Option<String>,
+    pub output: Option<String>,
+    pub output_dir: Option<String>,
+    pub name_template: Option<String>,
+    pub jobs: Option<u32>,
+    pub summary: Option<String>,
+    pub stub: Option<String>,
+    pub target: Option<String>,
+    pub registry: Option<String>,
+    pub force: Option<bool>,
+    pub cipher: Option<String>,
+    pub reproducible: Option<bool>,
+    pub build_epoch: Option<u64>,
+    #[serde(alias = "compression")]
+    pub compress: Option<String>,
+    pub bundle_libs: Option<bool>,
+    pub bundle_lib: Option<Vec<String>>,
+    pub entry: Option<String>,
+    pub record_components: Option<bool>,
+    pub signing_key: Option<String>,
+    pub not_before: Option<String>,
+    pub not_after: Option<String>,
+    pub max_launches: Option<u32>,
+    pub max_instances: Option<u32>,
+    pub allow_user: Option<Vec<String>>,
+    pub allow_group: Option<Vec<String>>,
+    pub allow_host: Option<Vec<String>>,
+    pub install_path: Option<Vec<String>>,
+    pub allow_parent: Option<Vec<String>>,
+    pub anti_debug: Option<Vec<String>>,
+    pub product: Option<String>,
+    pub version: Option<String>,
+    pub manifest_arg: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(default)]
+    pub build: BuildConfig,
+    #[serde(default)]
+    pub profile: BTreeMap<String, BuildConfig>,
+    /// Directory the file was read from.
+    #[serde(skip)]
+    pub base: PathBuf,
+}
+
+// Fields of `over` replace those of `base` when set
+macro_rules! overlay {
+    ($base:expr, $over:expr, $($field:ident),+ $(,)?) => {
+        $( if $over.$field.is_some() { $base.$field = $over.$field.clone(); } )+
+    };
+}
+
+impl BuildConfig {
+    /// Replaces the fields that `over` sets.
+    pub fn overlay(&mut self, over: &BuildConfig) {
+        overlay!(
+            self, over, input, key, keys, output, output_dir, name_template, jobs, summary, stub, target, registry,
+            force, cipher, reproducible, build_epoch, compress, bundle_libs, bundle_lib, entry, record_components,
+            signing_key, not_before, not_after, max_launches, max_instances, allow_user, allow_group, allow_host,
+            install_path, allow_parent, anti_debug, product, version, manifest_arg,
+        );
+    }
+
+    /// Makes the relative file paths relative to `base` instead.
+    pub fn resolve_paths(&mut self, base: &Path) {
+        for path in [
+            &mut self.input,
+            &mut self.key,
+            &mut self.keys,
+            &mut self.output,
+            &mut self.output_dir,
+            &mut self.summary,
+            &mut self.stub,
+            &mut self.registry,
+            &mut self.signing_key,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = base.join(&*path).to_string_lossy().into_owned();
+        }
+        for path in self.bundle_lib.iter_mut().flatten() {
+            *path = base.join(&*path).to_string_lossy().into_owned();
+        }
+    }
+
+    /// A builder for `input` with every setting but the key, which differs
+    /// between the builds of `keys`.
+    pub fn builder(&self) -> Result<SecureBuilder, Box<dyn Error>> {
+        let input = self
+            .input
+            .as_deref()
+            .ok_or_else(|| usage("No input binary given, on the command line or in the config file"))?;
+        let builder = self.options(SecureBuilder::new(input))?;
+        Ok(match self.epoch()? {
+            Some(epoch) => builder.reproducible(epoch),
+            None => builder,
+        })
+    }
+
+    /// Checks the settings without reading any file.
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        if self.key.is_some() && self.keys.is_some() {
+            return Err(usage("key and keys cannot both be set"));
+        }
+        let mut builder = self.options(SecureBuilder::new(self.input.as_deref().unwrap_or_default()))?;
+        // The epoch may only be known at build time
+        if self.reproducible == Some(true) {
+            builder = builder.reproducible(self.build_epoch.unwrap_or_default());
+        }
+        builder.check()
+    }
+
+    fn options(&self, mut builder: SecureBuilder) -> Result<SecureBuilder, Box<dyn Error>> {
+        // Settings the format reserves but this sbb cannot apply yet
+        if self.signing_key.is_some() {
+            return Err(usage("signing-key is set, but this sbb cannot sign secured binaries"));
+        }
+
+        let list = |l: &Option<Vec<String>>| l.clone().unwrap_or_default();
+        builder = builder.force(self.force.unwrap_or(false)).policy(Policy {
+            users: list(&self.allow_user),
+            groups: list(&self.allow_group),
+            hostnames: list(&self.allow_host),
+            install_paths: list(&self.install_path),
+            parent_processes: list(&self.allow_parent),
+        });
+        if let Some(target) = &self.target {
+            builder = builder.target(target);
+        }
+        if let Some(stub) = &self.stub {
+            builder = builder.stub(stub);
+        }
+        if let Some(registry) = &self.registry {
+            builder = builder.registry(registry);
+        }
+        if let Some(setting) = &self.compress {
+            builder = builder.compress(compress::parse_setting(setting).map_err(usage)?);
+        }
+        builder = builder.bundle_libs(self.bundle_libs.unwrap_or(false));
+        for path in self.bundle_lib.iter().flatten() {
+            builder = builder.bundle_lib(path);
+        }
+        if let Some(entry) = &self.entry {
+            builder = builder.entry(entry);
+        }
+        builder = builder.record_components(self.record_components.unwrap_or(false));
+        if let Some(name) = &self.cipher {
+            let cipher = Cipher::from_name(name).ok_or_else(|| {
+                let names: Vec<&str> = Cipher::ALL.iter().map(|c| c.name()).collect();
+                usage(format!("Unknown cipher '{}' (supported: {})", name, names.join(", ")))
+            })?;
+            builder = builder.cipher(cipher);
+        }
+        if let Some(time) = &self.not_before {
+            builder = builder.not_before(validity::parse_timestamp(time).map_err(usage)?);
+        }
+        if let Some(time) = &self.not_after {
+            builder = builder.not_after(validity::parse_timestamp(time).map_err(usage)?);
+        }
+        if let Some(n) = self.max_launches {
+            builder = builder.max_launches(n);
+        }
+        if let Some(n) = self.max_instances {
+            builder = builder.max_instances(n);
+        }
+        for setting in self.anti_debug.iter().flatten() {
+            for (check, action) in antidebug::parse_setting(setting).map_err(usage)? {
+                builder = builder.anti_debug(check, action);
+            }
+        }
+        if let Some(product) = &self.product {
+            builder = builder.product(product);
+        }
+        if let Some(version) = &self.version {
+            builder = builder.version(version);
+        }
+        if let Some(arg) = &self.manifest_arg {
+            builder = builder.manifest_arg(arg);
+        }
+        Ok(builder)
+    }
+
+    // build-epoch, or SOURCE_DATE_EPOCH, for a reproducible build
+    fn epoch(&self) -> Result<Option<u64>, Box<dyn Error>> {
+        if self.reproducible != Some(true) {
+            if self.build_epoch.is_some() {
+                return Err(usage("--build-epoch requires --reproducible"));
+            }
+            return Ok(None);
+        }
+        if let Some(epoch) = self.build_epoch {
+            return Ok(Some(epoch));
+        }
+        match std::env::var(SOURCE_DATE_EPOCH) {
+            Ok(value) => value
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|_| usage(format!("{} must be Unix seconds, not '{}'", SOURCE_DATE_EPOCH, value))),
+            Err(_) => Err(usage(format!("--reproducible needs --build-epoch or {}", SOURCE_DATE_EPOCH))),
+        }
+    }
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path).map_err(|e| usage(format!("Cannot read {}: {}", path.display(), e)))?;
+        let mut config: ConfigFile =
+            toml::from_str(&text).map_err(|e| usage(format!("Invalid {}: {}", path.display(), e)))?;
+        config.base = path.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+        Ok(config)
+    }
+
+    /// The `--config` file, or `sbb.toml` in the current directory if present.
+    pub fn find(path: Option<&str>) -> Result<Option<Self>, Box<dyn Error>> {
+        match path {
+            Some(path) => Ok(Some(Self::load(Path::new(path))?)),
+            None if Path::new(DEFAULT_PATH).is_file() => Ok(Some(Self::load(Path::new(DEFAULT_PATH))?)),
+            None => Ok(None),
+        }
+    }
+
+    /// `[build]` with the named profile applied on top, with paths
+    /// resolved against the config file's directory.
+    pub fn settings(&self, profile: Option<&str>) -> Result<BuildConfig, Box<dyn Error>> {
+        let mut settings = self.build.clone();
+        if let Some(name) = profile {
+            let over = self.profile.get(name).ok_or_else(|| {
+                let known: Vec<&str> = self.profile.keys().map(String::as_str).collect();
+                usage(format!("No profile '{}' in the config file (profiles: {})", name, known.join(", ")))
+            })?;
+            settings.overlay(over);
+        }
+        settings.resolve_paths(&self.base);
+        Ok(settings)
+    }
+
+    /// Checks `[build]` and every profile, or only `only`; returns one
+    /// message per problem.
+    pub fn validate(&self, only: Option<&str>) -> Vec<String> {
+        let names: Vec<Option<&str>> = match only {
+            Some(name) => vec![Some(name)],
+            None => std::iter::once(None).chain(self.profile.keys().map(|k| Some(k.as_str()))).collect(),
+        };
+
+        let mut problems = Vec::new();
+        for name in names {
+            let label = name.map_or_else(|| "[build]".to_string(), |n| format!("profile '{}'", n));
+            if let Err(e) = self.settings(name).and_then(|s| s.check()) {
+                problems.push(format!("{}: {}", label, e));
+            }
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(text: &str) -> ConfigFile {
+        let mut config: ConfigFile = toml::from_str(text).unwrap();
+        config.base = PathBuf::from("/project");
+        config
+    }
+
+    const EXAMPLE: &str = r#"
+        [build]
+        input = "target/release/app"
+        product = "App"
+        allow-user = ["deploy"]
+
+        [profile.eval]
+        not-after = "2026-12-31"
+        max-launches = 50
+
+        [profile.production]
+        keys = "fleet.csv"
+        allow-user = ["svc-app"]
+    "#;
+
+    #[test]
+    fn test_profiles() {
+        let config = config(EXAMPLE);
+        let eval = config.settings(Some("eval")).unwrap();
+        assert_eq!(eval.input.as_deref(), Some("/project/target/release/app"));
+        assert_eq!(eval.not_after.as_deref(), Some("2026-12-31"));
+        assert_eq!(eval.max_launches, Some(50));
+        assert_eq!(eval.allow_user, Some(vec!["deploy".to_string()]));
+
+        let production = config.settings(Some("production")).unwrap();
+        assert_eq!(production.keys.as_deref(), Some("/project/fleet.csv"));
+        assert_eq!(production.allow_user, Some(vec!["svc-app".to_string()]));
+        assert_eq!(production.max_launches, None);
+
+        assert!(config.settings(Some("missing")).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(config(EXAMPLE).validate(None).is_empty());
+
+        let broken = config(
+            r#"
+            [build]
+            input = "app"
+            [profile.a]
+            not-before = "2027-01-01"
+            not-after = "2026-01-01"
+            [profile.b]
+            compress = "zstd:99"
+            [profile.c]
+            anti-debug = ["everything=refuse"]
+            "#,
+        );
+        let problems = broken.validate(None);
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].starts_with("profile 'a'"));
+    }
+
+    #[test]
+    fn test_rejects_unknown_keys() {
+        assert!(toml::from_str::<ConfigFile>("[build]\nmax-launch = 3\n").is_err());
+        assert!(toml::from_str::<ConfigFile>("[builds]\n").is_err());
+    }
+}
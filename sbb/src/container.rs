@@ -0,0 +1,226 @@
+//! Reads the container appended to a secured binary.
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use common::{bundle, compress, crypto};
+use common::embed::{self, MAGIC_FOOTER, MAGIC_HEADER};
+use common::fingerprint;
+use common::header::{self, HEADER_MAGIC, Header};
+
+use crate::error::SbbError;
+
+/// The parts of a secured binary, as laid out by the builder:
+/// stub, optional embedded key, header, encrypted payload.
+#[derive(Debug, Clone)]
+pub struct Container {
+    /// Length of the stub image in front of the first payload.
+    pub stub_len: usize,
+    /// Key stored next to the payload when the build is not bound to a machine.
+    pub embedded_key: Option<String>,
+    pub header: Header,
+    pub header_bytes: Vec<u8>,
+    pub encrypted: Vec<u8>,
+    /// Where each part lies in the file, in file order.
+    pub sections: Vec<Section>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    Stub,
+    EmbeddedKey,
+    Header,
+    Payload,
+}
+
+impl SectionKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            SectionKind::Stub => "stub",
+            SectionKind::EmbeddedKey => "embedded-key",
+            SectionKind::Header => "header",
+            SectionKind::Payload => "payload",
+        }
+    }
+}
+
+/// What a decrypted payload holds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contents {
+    pub program: Vec<u8>,
+    /// Bundled shared libraries, by the name the loader looks for, or the
+    /// other files of a script directory, by their path inside it.
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+/// A part of the file; payload ranges exclude their markers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub kind: SectionKind,
+    pub range: Range<usize>,
+}
+
+impl Container {
+    pub fn parse(data: &[u8]) -> Result<Self, SbbError> {
+        let mut ranges = embed::locate_payloads(data);
+        check_complete(data, &ranges)?;
+        let encrypted = ranges.pop().ok_or_else(|| invalid("no embedded payload found"))?;
+        let header_range = ranges
+            .pop()
+            .filter(|r| header::is_header(&data[r.clone()]))
+            .ok_or_else(|| invalid("no container header found"))?;
+        let header_bytes = data[header_range.clone()].to_vec();
+        let header = Header::from_bytes(&header_bytes).map_err(|e| invalid(&e.to_string()))?;
+        if encrypted.len() < header.cipher.overhead() {
+            return Err(invalid("encrypted payload is truncated"));
+        }
+
+        // Any earlier payload is either the embedded key or, for a stub whose
+        // data section holds the markers, part of the stub itself
+        let embedded = ranges.pop().and_then(|r| {
+            let key = std::str::from_utf8(&data[r.clone()]).ok()?;
+            fingerprint::is_valid_fingerprint(key).then(|| (r, key.to_string()))
+        });
+        let first = embedded.as_ref().map_or(header_range.start, |(r, _)| r.start);
+        let stub_len = first - MAGIC_HEADER.len();
+
+        let mut sections = vec![Section { kind: SectionKind::Stub, range: 0..stub_len }];
+        if let Some((range, _)) = &embedded {
+            sections.push(Section { kind: SectionKind::EmbeddedKey, range: range.clone() });
+        }
+        sections.push(Section { kind: SectionKind::Header, range: header_range });
+        sections.push(Section { kind: SectionKind::Payload, range: encrypted.clone() });
+
+        Ok(Container {
+            stub_len,
+            embedded_key: embedded.map(|(_, key)| key),
+            header,
+            header_bytes,
+            encrypted: data[encrypted].to_vec(),
+            sections,
+        })
+    }
+
+    /// Container format version, read from the header.
+    pub fn version(&self) -> u8 {
+        self.header_bytes[HEADER_MAGIC.len()]
+    }
+
+    /// Decrypts the payload, authenticating the header along with it.
+    pub fn decrypt(&self, key: &str) -> Result<Vec<u8>, SbbError> {
+        crypto::decrypt(self.header.cipher, key, &self.encrypted, &self.header_bytes).ok_or(SbbError::WrongKey)
+    }
+
+    /// The program or entry script and the bundled files in a decrypted
+    /// payload, decompressed if the build was compressed.
+    pub fn contents(&self, payload: &[u8]) -> Result<Contents, SbbError> {
+        let plaintext = match &self.header.compression {
+            Some(compression) => compress::program(Some(compression), payload)
+                .map(Cow::Owned)
+                .map_err(|e| invalid(&format!("payload does not decompress: {}", e)))?,
+            None => Cow::Borrowed(payload),
+        };
+        if self.header.libraries == 0 && self.header.entry.is_none() {
+            return Ok(Contents { program: plaintext.into_owned(), files: Vec::new() });
+        }
+        let (program, files) =
+            bundle::unpack(&plaintext).map_err(|e| invalid(&format!("bundled files are damaged: {}", e)))?;
+        if self.header.entry.is_none() && files.len() != self.header.libraries as usize {
+            return Err(invalid("the number of bundled libraries does not match the header"));
+        }
+        Ok(Contents {
+            program: program.to_vec(),
+            files: files.into_iter().map(|(name, data)| (name, data.to_vec())).collect(),
+        })
+    }
+}
+
+// A start marker after the last complete payload, directly followed by a
+// header or after one, means the file was cut off inside the container
+fn check_complete(data: &[u8], ranges: &[Range<usize>]) -> Result<(), SbbError> {
+    let end = ranges.last().map_or(0, |r| r.end + MAGIC_FOOTER.len());
+    let Some(pos) = data[end..].windows(MAGIC_HEADER.len()).position(|w| w == MAGIC_HEADER) else {
+        return Ok(());
+    };
+    let after_header = ranges.last().is_some_and(|r| header::is_header(&data[r.clone()]));
+    if after_header || data[end + pos + MAGIC_HEADER.len()..].starts_with(HEADER_MAGIC) {
+        return Err(invalid("file is truncated: the last section has no end marker"));
+    }
+    Ok(())
+}
+
+fn invalid(msg: &str) -> SbbError {
+    SbbError::InvalidContainer(msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    fn secured(embed_key: bool) -> (Vec<u8>, Header) {
+        let header = Header::new();
+        let header_bytes = header.to_bytes();
+        let encrypted = crypto::encrypt_binary_with_aad(KEY, b"program", &header_bytes).unwrap();
+        let mut payloads = vec![header_bytes, encrypted];
+        if embed_key {
+            payloads.insert(0, KEY.as_bytes().to_vec());
+        }
+        // Marker constants in the stub's data section, in either order
+        let mut stub = b"STUB".to_vec();
+        stub.extend_from_slice(MAGIC_HEADER);
+        stub.extend_from_slice(b"--EMBED_END--rodata");
+        (embed::append_payloads(&stub, &payloads), header)
+    }
+
+    #[test]
+    fn test_parse_embedded_key() {
+        let (data, header) = secured(true);
+        let container = Container::parse(&data).unwrap();
+        assert_eq!(container.header, header);
+        assert_eq!(container.embedded_key.as_deref(), Some(KEY));
+        assert_eq!(container.stub_len, b"STUB--EMBED_START----EMBED_END--rodata".len());
+        assert_eq!(container.decrypt(KEY).unwrap(), b"program");
+    }
+
+    #[test]
+    fn test_parse_machine_bound() {
+        let (data, _) = secured(false);
+        let container = Container::parse(&data).unwrap();
+        assert_eq!(container.embedded_key, None);
+        assert_eq!(container.stub_len, b"STUB--EMBED_START----EMBED_END--rodata".len());
+        assert_eq!(container.decrypt(&KEY.replace('0', "1")), Err(SbbError::WrongKey));
+    }
+
+    #[test]
+    fn test_rejects_damaged_files() {
+        let (data, _) = secured(false);
+        assert!(matches!(Container::parse(b"plain file"), Err(SbbError::InvalidContainer(_))));
+        assert!(matches!(Container::parse(&data[..data.len() - 4]), Err(SbbError::InvalidContainer(_))));
+
+        // Cut inside the payload and inside the header
+        let header_end = data.windows(4).position(|w| w == b"SBBH").unwrap() + 20;
+        for len in [data.len() - 20, header_end] {
+            let err = Container::parse(&data[..len]).unwrap_err().to_string();
+            assert!(err.contains("truncated"), "{}", err);
+        }
+
+        // A payload too short to hold the nonce and tag
+        let header_bytes = Header::new().to_bytes();
+        let short = embed::append_payloads(b"STUB", &[header_bytes, vec![0; 8]]);
+        assert!(Container::parse(&short).unwrap_err().to_string().contains("truncated"));
+    }
+
+    #[test]
+    fn test_sections() {
+        let (data, _) = secured(true);
+        let container = Container::parse(&data).unwrap();
+        let kinds: Vec<_> = container.sections.iter().map(|s| s.kind).collect();
+        assert_eq!(kinds, [SectionKind::Stub, SectionKind::EmbeddedKey, SectionKind::Header, SectionKind::Payload]);
+        assert_eq!(&data[container.sections[1].range.clone()], KEY.as_bytes());
+        assert_eq!(&data[container.sections[3].range.clone()], &container.encrypted[..]);
+        assert_eq!(container.sections[3].range.end + MAGIC_FOOTER.len(), data.len());
+        assert_eq!(container.version(), header::HEADER_VERSION);
+    }
+}
@@ -0,0 +1,52 @@
+//! Failures that `sbb` reports with their own exit code.
+
+use std::error::Error;
+use std::fmt;
+
+/// Generic failure, such as an I/O error.
+pub const EXIT_FAILURE: i32 = 1;
+/// Invalid or conflicting options.
+pub const EXIT_USAGE: i32 = 2;
+/// The file is not a secured binary, or its container is damaged.
+pub const EXIT_INVALID_CONTAINER: i32 = 3;
+/// The key does not decrypt the container.
+pub const EXIT_WRONG_KEY: i32 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SbbError {
+    Usage(String),
+    InvalidContainer(String),
+    WrongKey,
+}
+
+impl SbbError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SbbError::Usage(_) => EXIT_USAGE,
+            SbbError::InvalidContainer(_) => EXIT_INVALID_CONTAINER,
+            SbbError::WrongKey => EXIT_WRONG_KEY,
+        }
+    }
+}
+
+impl fmt::Display for SbbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SbbError::Usage(msg) => write!(f, "{}", msg),
+            SbbError::InvalidContainer(msg) => write!(f, "Not a valid secured binary: {}", msg),
+            SbbError::WrongKey => write!(f, "The key does not decrypt this binary"),
+        }
+    }
+}
+
+impl Error for SbbError {}
+
+/// Exit code for an error returned by a command.
+pub fn exit_code(error: &(dyn Error + 'static)) -> i32 {
+    error.downcast_ref::<SbbError>().map_or(EXIT_FAILURE, SbbError::exit_code)
+}
+
+/// Shorthand for a usage error.
+pub fn usage(msg: impl Into<String>) -> Box<dyn Error> {
+    Box::new(SbbError::Usage(msg.into()))
+}
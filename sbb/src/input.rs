@@ -0,0 +1,189 @@
+//! Identifies the binary or script being secured, so it gets a stub that
+//! can run it.
+
+use std::fmt;
+
+use common::elf::{self, Elf};
+use common::pe::{self, Pe};
+use common::script;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Elf,
+    Pe,
+    /// A script starting with a `#!` line.
+    Script,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputInfo {
+    pub format: Format,
+    /// Architecture as named in target triples, if it is one we know.
+    pub arch: Option<&'static str>,
+    pub bits: u8,
+    /// Shared library or DLL rather than an executable.
+    pub library: bool,
+    /// Dynamic loader requested by an ELF executable, `None` if statically
+    /// linked; or the interpreter line of a script.
+    pub interpreter: Option<String>,
+}
+
+impl InputInfo {
+    /// Parses the ELF or PE headers or the `#!` line of `data`.
+    pub fn detect(data: &[u8]) -> Result<Self, String> {
+        if let Some(interpreter) = script::shebang(data) {
+            if !script::command(&interpreter).0.starts_with('/') {
+                return Err(format!("Script interpreter '{}' is not an absolute path", interpreter));
+            }
+            return Ok(InputInfo { format: Format::Script, arch: None, bits: 0, library: false, interpreter: Some(interpreter) });
+        }
+        if data.starts_with(elf::ELF_MAGIC) {
+            let elf = Elf::parse(data).map_err(|e| format!("Invalid ELF file: {}", e))?;
+            Ok(InputInfo {
+                format: Format::Elf,
+                arch: elf.arch(),
+                bits: if elf.is_64 { 64 } else { 32 },
+                library: elf.is_shared_library(data),
+                interpreter: elf.interpreter(data),
+            })
+        } else if data.starts_with(pe::MZ_MAGIC) {
+            let pe = Pe::parse(data).map_err(|e| format!("Invalid PE file: {}", e))?;
+            Ok(InputInfo {
+                format: Format::Pe,
+                arch: pe.arch(),
+                bits: if pe.is_64 { 64 } else { 32 },
+                library: pe.is_dll,
+                interpreter: None,
+            })
+        } else {
+            Err("Input is neither an ELF or PE executable nor a #! script".into())
+        }
+    }
+
+    fn is_musl(&self) -> bool {
+        self.interpreter.as_deref().is_some_and(|i| i.contains("ld-musl"))
+    }
+
+    /// Target triples whose stubs can run this binary, best first. `host`
+    /// breaks ties for statically linked ELF files, which run on either libc.
+    pub fn preferred_targets(&self, host: &str) -> Vec<String> {
+        let Some(arch) = self.arch else { return Vec::new() };
+        match self.format {
+            Format::Pe => vec![format!("{}-pc-windows-msvc", arch), format!("{}-pc-windows-gnu", arch)],
+            Format::Script => Vec::new(),
+            Format::Elf => {
+                let gnu = format!("{}-unknown-linux-gnu", arch);
+                let musl = format!("{}-unknown-linux-musl", arch);
+                if self.is_musl() || (self.interpreter.is_none() && host == musl) {
+                    vec![musl, gnu]
+                } else {
+                    vec![gnu, musl]
+                }
+            }
+        }
+    }
+
+    /// Why a stub for `target` cannot run this binary, if it cannot.
+    pub fn mismatch(&self, target: &str) -> Option<String> {
+        let target_arch = target.split('-').next().unwrap_or_default();
+        let target_windows = target.contains("windows");
+        if self.format == Format::Script {
+            return target_windows.then(|| format!("input is a script but the target is {}", target));
+        }
+        if (self.format == Format::Pe) != target_windows {
+            return Some(format!("input is a {} file but the target is {}", self.format, target));
+        }
+        match self.arch {
+            Some(arch) if arch != target_arch => {
+                Some(format!("input is built for {} but the target is {}", arch, target))
+            }
+            None => Some(format!("input architecture is not supported by any stub (target {})", target)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Format::Elf => "ELF",
+            Format::Pe => "PE",
+            Format::Script => "script",
+        })
+    }
+}
+
+impl fmt::Display for InputInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let (Format::Script, Some(interp)) = (&self.format, &self.interpreter) {
+            return write!(f, "script run by {}", interp);
+        }
+        write!(f, "{} {}-bit {}", self.format, self.bits, self.arch.unwrap_or("unknown architecture"))?;
+        if self.library {
+            write!(f, " library")?;
+        } else {
+            write!(f, " executable")?;
+        }
+        match (&self.format, &self.interpreter) {
+            (Format::Elf, Some(interp)) => write!(f, ", dynamically linked ({})", interp),
+            (Format::Elf, None) => write!(f, ", statically linked"),
+            (Format::Pe | Format::Script, _) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elf(arch: &'static str, interpreter: Option<&str>) -> InputInfo {
+        InputInfo {
+            format: Format::Elf,
+            arch: Some(arch),
+            bits: 64,
+            library: false,
+            interpreter: interpreter.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_preferred_targets() {
+        let musl = elf("x86_64", Some("/lib/ld-musl-x86_64.so.1"));
+        assert_eq!(musl.preferred_targets("x86_64-unknown-linux-gnu")[0], "x86_64-unknown-linux-musl");
+
+        let glibc = elf("aarch64", Some("/lib/ld-linux-aarch64.so.1"));
+        assert_eq!(glibc.preferred_targets("aarch64-unknown-linux-musl")[0], "aarch64-unknown-linux-gnu");
+
+        let fully_static = elf("x86_64", None);
+        assert_eq!(fully_static.preferred_targets("x86_64-unknown-linux-musl")[0], "x86_64-unknown-linux-musl");
+    }
+
+    #[test]
+    fn test_mismatch() {
+        let input = elf("aarch64", Some("/lib/ld-linux-aarch64.so.1"));
+        assert_eq!(input.mismatch("aarch64-unknown-linux-gnu"), None);
+        assert!(input.mismatch("x86_64-unknown-linux-gnu").unwrap().contains("built for aarch64"));
+        assert!(input.mismatch("aarch64-pc-windows-msvc").unwrap().contains("ELF file"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_detect_system_binary() {
+        let data = std::fs::read("/proc/self/exe").unwrap();
+        let info = InputInfo::detect(&data).unwrap();
+        assert_eq!(info.format, Format::Elf);
+        assert!(!info.library);
+    }
+
+    #[test]
+    fn test_detect_script() {
+        let info = InputInfo::detect(b"#!/usr/bin/env python3\nprint('hi')\n").unwrap();
+        assert_eq!(info.format, Format::Script);
+        assert_eq!(info.interpreter.as_deref(), Some("/usr/bin/env python3"));
+        assert_eq!(info.to_string(), "script run by /usr/bin/env python3");
+        assert_eq!(info.mismatch("aarch64-unknown-linux-musl"), None);
+        assert!(info.mismatch("x86_64-pc-windows-msvc").unwrap().contains("script"));
+        assert!(InputInfo::detect(b"#!python3\n").is_err());
+        assert!(InputInfo::detect(b"plain text\n").is_err());
+    }
+}
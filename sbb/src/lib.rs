@@ -0,0 +1,21 @@
+//! Secure Binary Builder: wraps executables in a stub that decrypts and
+//! runs them only on the machines and under the conditions they were built
+//! for.
+//!
+//! [`SecureBuilder`] is the entry point for builds; the `sbb` command line
+//! is a thin layer over it.
+
+pub mod builder;
+pub mod config;
+pub mod container;
+mod embed;
+pub mod error;
+pub mod input;
+pub mod libraries;
+pub mod scripts;
+pub mod stubs;
+
+pub use builder::{BuildPlan, BuildReport, SecureBuilder};
+pub use common::crypto::Cipher;
+pub use common::fingerprint::KeyFile;
+pub use common::policy::Policy;
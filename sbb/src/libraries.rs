@@ -0,0 +1,178 @@
+//! Finds the shared libraries to bundle with a dynamically linked program.
+//!
+//! `DT_NEEDED` entries are looked up the way the loader on the build machine
+//! would: in the object's rpath or runpath, `LD_LIBRARY_PATH` and the system
+//! library directories. The C library and the loader are never bundled, as
+//! they must match the kernel and loader of the machine the program runs on.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use common::bundle;
+use common::elf::{self, Elf};
+use common::log_info;
+
+use crate::error::usage;
+
+/// Searched after the rpath, runpath and `LD_LIBRARY_PATH`.
+const SYSTEM_DIRS: &[&str] = &[
+    "/lib/x86_64-linux-gnu",
+    "/usr/lib/x86_64-linux-gnu",
+    "/lib/aarch64-linux-gnu",
+    "/usr/lib/aarch64-linux-gnu",
+    "/lib/i386-linux-gnu",
+    "/usr/lib/i386-linux-gnu",
+    "/lib/arm-linux-gnueabihf",
+    "/usr/lib/arm-linux-gnueabihf",
+    "/lib/riscv64-linux-gnu",
+    "/usr/lib/riscv64-linux-gnu",
+    "/lib64",
+    "/usr/lib64",
+    "/lib",
+    "/usr/lib",
+    "/usr/local/lib",
+];
+
+/// Libraries that belong to the C library or the loader.
+const SYSTEM_LIBRARIES: &[&str] = &[
+    "libc.so",
+    "libm.so",
+    "libpthread.so",
+    "libdl.so",
+    "librt.so",
+    "libutil.so",
+    "libresolv.so",
+    "libanl.so",
+    "libnsl.so",
+    "ld-linux",
+    "ld64.so",
+    "ld-musl",
+    "linux-vdso",
+    "linux-gate",
+];
+
+/// A library to encrypt along with the program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Library {
+    /// Name the loader looks for: the `DT_NEEDED` entry or the SONAME.
+    pub name: String,
+    pub path: PathBuf,
+    pub data: Vec<u8>,
+}
+
+/// The libraries to bundle with `program`: the `explicit` files, and with
+/// `follow_needed` every library the program and those files need.
+pub fn resolve(
+    program: &[u8],
+    program_path: &Path,
+    explicit: &[PathBuf],
+    follow_needed: bool,
+) -> Result<Vec<Library>, Box<dyn Error>> {
+    let elf = Elf::parse(program)
+        .map_err(|_| usage(format!("{} is not an ELF program; only those can have bundled libraries", program_path.display())))?;
+    if elf.interpreter(program).is_none() {
+        log_info!("{} is statically linked; no libraries to bundle", program_path.display());
+        return Ok(Vec::new());
+    }
+
+    let mut libraries: Vec<Library> = Vec::new();
+    for path in explicit {
+        let data = fs::read(path).map_err(|e| usage(format!("Cannot read library {}: {}", path.display(), e)))?;
+        let lib = Elf::parse(&data)
+            .ok()
+            .filter(|lib| same_machine(&elf, lib))
+            .ok_or_else(|| usage(format!("{} is not a shared library for the program's architecture", path.display())))?;
+        let name = match lib.dynamic_strings(&data, elf::DT_SONAME).pop() {
+            Some(soname) => soname,
+            None => path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+        };
+        if !bundle::valid_name(&name) {
+            return Err(usage(format!("{} has the name {}, which cannot be bundled", path.display(), name)));
+        }
+        if libraries.iter().any(|l| l.name == name) {
+            return Err(usage(format!("Library {} is given twice", name)));
+        }
+        libraries.push(Library { name, path: path.clone(), data });
+    }
+    if !follow_needed {
+        return Ok(libraries);
+    }
+
+    // Breadth first, starting with the program and the explicit libraries
+    let mut queue: VecDeque<(Vec<u8>, PathBuf)> = VecDeque::from([(program.to_vec(), program_path.to_path_buf())]);
+    queue.extend(libraries.iter().map(|l| (l.data.clone(), l.path.clone())));
+    while let Some((data, path)) = queue.pop_front() {
+        let Ok(object) = Elf::parse(&data) else { continue };
+        for needed in object.dynamic_strings(&data, elf::DT_NEEDED) {
+            if is_system_library(&needed) || libraries.iter().any(|l| l.name == needed) {
+                continue;
+            }
+            if !bundle::valid_name(&needed) {
+                return Err(usage(format!("{} needs {} by path, which cannot be bundled", path.display(), needed)));
+            }
+            let (found, lib) = find(&needed, &object, &data, &path, &elf).ok_or_else(|| {
+                usage(format!("Cannot find {} needed by {}; give it with --bundle-lib", needed, path.display()))
+            })?;
+            log_info!("Bundling {} from {}", needed, found.display());
+            queue.push_back((lib.clone(), found.clone()));
+            libraries.push(Library { name: needed, path: found, data: lib });
+        }
+    }
+    Ok(libraries)
+}
+
+/// Whether `name` is part of the C library or the loader.
+pub fn is_system_library(name: &str) -> bool {
+    SYSTEM_LIBRARIES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+fn same_machine(a: &Elf, b: &Elf) -> bool {
+    a.e_machine == b.e_machine && a.is_64 == b.is_64
+}
+
+// Looks `name` up for `object` and returns its path and contents
+fn find(name: &str, object: &Elf, data: &[u8], path: &Path, program: &Elf) -> Option<(PathBuf, Vec<u8>)> {
+    let origin = path.parent().and_then(|p| fs::canonicalize(p).ok()).unwrap_or_default();
+    let expand = |list: Vec<String>| -> Vec<PathBuf> {
+        list.iter()
+            .flat_map(|l| l.split(':'))
+            .filter(|d| !d.is_empty())
+            .map(|d| PathBuf::from(d.replace("${ORIGIN}", "$ORIGIN").replace("$ORIGIN", &origin.to_string_lossy())))
+            .collect()
+    };
+    // The loader ignores the rpath of an object that has a runpath
+    let runpath = expand(object.dynamic_strings(data, elf::DT_RUNPATH));
+    let mut dirs = if runpath.is_empty() { expand(object.dynamic_strings(data, elf::DT_RPATH)) } else { Vec::new() };
+    if let Ok(paths) = std::env::var("LD_LIBRARY_PATH") {
+        dirs.extend(expand(vec![paths]));
+    }
+    dirs.extend(runpath);
+    dirs.extend(SYSTEM_DIRS.iter().map(PathBuf::from));
+
+    dirs.into_iter().map(|dir| dir.join(name)).find_map(|candidate| {
+        let contents = fs::read(&candidate).ok()?;
+        let lib = Elf::parse(&contents).ok()?;
+        same_machine(program, &lib).then_some((candidate, contents))
+    })
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skips_system_libraries() {
+        assert!(is_system_library("libc.so.6"));
+        assert!(is_system_library("ld-linux-x86-64.so.2"));
+        assert!(!is_system_library("libcrypto.so.3"));
+        assert!(!is_system_library("libcurl.so.4"));
+    }
+
+    #[test]
+    fn test_resolve_rejects_non_elf() {
+        let error = resolve(b"#!/bin/sh\n", Path::new("run.sh"), &[], true).unwrap_err();
+        assert!(error.to_string().contains("not an ELF program"), "{}", error);
+    }
+}
@@ -1,47 +1,363 @@
-mod builder;
+mod batch;
+mod commands;
+mod options;
 
-mod embed;
+use sbb::error;
 
 use clap::Parser;
 
 /// Secure Binary Builder
 #[derive(clap::Parser)]
-struct Args {
-    /// Path to the binary to secure
-    input: String,
+#[command(after_help = "Exit codes: 0 success, 1 failure, 2 invalid options, \
+3 not a valid secured binary, 4 wrong key")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Wrap a binary in the stub
+    Build(Box<BuildArgs>),
+    /// Show the manifest and layout of a secured binary
+    Inspect(InspectArgs),
+    /// Check that a secured binary decrypts with a key, without running it
+    Verify(VerifyArgs),
+    /// Recover the original binary
+    Extract(ExtractArgs),
+    /// Bind a secured binary to a different key
+    Rekey(RekeyArgs),
+    /// Write a key file for this machine
+    Keygen(KeygenArgs),
+    /// Show this machine's key id and the properties it is derived from
+    Fingerprint,
+    /// List and register the stubs available to build
+    Stubs {
+        #[command(subcommand)]
+        command: StubsCommand,
+    },
+    /// Check the build settings in sbb.toml
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum ConfigCommand {
+    /// Check the [build] table and every profile, or only --profile
+    Validate {
+        /// Config file [default: sbb.toml]
+        #[arg(long, value_name = "PATH")]
+        config: Option<String>,
+
+        /// Only check this profile
+        #[arg(long, value_name = "NAME")]
+        profile: Option<String>,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum StubsCommand {
+    /// List the available stubs with their SHA-256 and capabilities
+    List {
+        /// Also list the stubs in this registry
+        #[arg(long, value_name = "DIR")]
+        registry: Option<String>,
+    },
+    /// Copy a stub into a registry and write its sidecar manifest
+    Add {
+        /// Stub executable, built from this source tree
+        stub: String,
+
+        /// Registry directory to add it to
+        #[arg(long, value_name = "DIR")]
+        registry: String,
+
+        /// Entry name in the registry [default: the stub's target triple]
+        #[arg(long)]
+        name: Option<String>,
+    },
+}
+
+#[derive(clap::Args)]
+pub struct BuildArgs {
+    /// Path to the binary to secure [default: input from the config file]
+    input: Option<String>,
+
+    /// Read build settings from this file; options given here override it
+    /// [default: sbb.toml if it exists]
+    #[arg(long, value_name = "PATH")]
+    config: Option<String>,
 
-    /// Path to the key file (optional, required if --encrypt is set)
-    #[arg(value_name = "KEY", required = false)]
+    /// Apply this profile of the config file on top of its [build] table
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Bind the binary to the machine this key file was generated on;
+    /// without it, a random key is embedded in the output
+    #[arg(long, value_name = "KEY_FILE")]
     key: Option<String>,
 
-    /// Encrypt the binary using the provided key
+    /// Build one binary per machine of an inventory: a directory of key
+    /// files named after each host, or a CSV with hostname and key_file columns
+    #[arg(long, value_name = "DIR|CSV", conflicts_with_all = ["key", "output"])]
+    keys: Option<String>,
+
+    /// Where to write the secured binary [default: <INPUT>.secured]
+    #[arg(long, short, value_name = "PATH")]
+    output: Option<String>,
+
+    /// Output file name for each machine with --keys; placeholders are
+    /// {name}, {hostname}, {key_id} and any CSV column
+    /// [default: {name}-{hostname}.secured]
+    #[arg(long, value_name = "TEMPLATE", requires = "keys")]
+    name_template: Option<String>,
+
+    /// Directory for the outputs of --keys [default: current directory]
+    #[arg(long, value_name = "DIR", requires = "keys")]
+    output_dir: Option<String>,
+
+    /// Number of parallel builds with --keys [default: number of CPUs]
+    #[arg(long, value_name = "N", requires = "keys", value_parser = clap::value_parser!(u32).range(1..))]
+    jobs: Option<u32>,
+
+    /// Also write the --keys summary as CSV to this file
+    #[arg(long, value_name = "PATH", requires = "keys")]
+    summary: Option<String>,
+
+    /// Stub to wrap the binary in, instead of the one bundled for --target
+    #[arg(long, value_name = "PATH")]
+    stub: Option<String>,
+
+    /// Target triple the secured binary runs on [default: detected from the input]
+    #[arg(long, value_name = "TRIPLE")]
+    target: Option<String>,
+
+    /// Stub registry to pick the stub from, before the bundled stubs
+    #[arg(long, value_name = "DIR")]
+    registry: Option<String>,
+
+    /// Cipher to encrypt the binary with: aes-256-gcm or aes-256-gcm-siv
+    /// [default: aes-256-gcm, or aes-256-gcm-siv with --reproducible]
+    #[arg(long, value_name = "CIPHER")]
+    cipher: Option<String>,
+
+    /// Compress the binary before encryption: zstd, lz4 or xz, with an
+    /// optional level such as zstd:19 or xz:9
+    #[arg(long, value_name = "CODEC[:LEVEL]")]
+    compress: Option<String>,
+
+    /// Encrypt the shared libraries the program needs along with it, except
+    /// the C library and the loader
+    #[arg(long)]
+    bundle_libs: bool,
+
+    /// Encrypt this shared library along with the program; repeatable
+    #[arg(long, value_name = "PATH")]
+    bundle_lib: Vec<String>,
+
+    /// Script to run when the input is a directory, relative to it. The stub
+    /// unpacks the directory into a private tmpfs directory while the script
+    /// runs, where other processes of the same user can read it
+    #[arg(long, value_name = "PATH")]
+    entry: Option<String>,
+
+    /// Record slow, salted hashes of the key's machine components, so that
+    /// SBB_DIAGNOSE can tell which one changed. Weakens the key binding:
+    /// each component can then be guessed from the binary on its own
+    #[arg(long)]
+    record_components: bool,
+
+    /// Produce the same output for the same input, key, options and build
+    /// epoch, using AES-256-GCM-SIV with a derived nonce
     #[arg(long)]
-    encrypt: bool,
+    reproducible: bool,
+
+    /// Build time recorded by --reproducible, in Unix seconds
+    /// [default: $SOURCE_DATE_EPOCH]
+    #[arg(long, value_name = "SECONDS", requires = "reproducible")]
+    build_epoch: Option<u64>,
+
+    /// Wrap the input even if it is not an executable for the target, or
+    /// is already secured
+    #[arg(long)]
+    force: bool,
+
+    /// Refuse to run before this time (YYYY-MM-DD, YYYY-MM-DDTHH:MM:SSZ or Unix seconds, UTC)
+    #[arg(long, value_name = "TIME")]
+    not_before: Option<String>,
+
+    /// Refuse to run after this time (YYYY-MM-DD, YYYY-MM-DDTHH:MM:SSZ or Unix seconds, UTC)
+    #[arg(long, value_name = "TIME")]
+    not_after: Option<String>,
+
+    /// Allow at most this many launches per machine. The count is kept in
+    /// two per-user directories, and deleting both of them resets it
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_launches: Option<u32>,
 
-    /// Target Windows platform
-    #[arg(long, group = "platform")]
-    windows: bool,
+    /// Allow at most this many concurrently running instances per machine
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_instances: Option<u32>,
 
-    /// Target Linux platform
-    #[arg(long, group = "platform")]
-    linux: bool,
+    /// Only run as this user (name or UID); repeatable
+    #[arg(long, value_name = "USER")]
+    allow_user: Vec<String>,
+
+    /// Only run as a member of this group (name or GID); repeatable
+    #[arg(long, value_name = "GROUP")]
+    allow_group: Vec<String>,
+
+    /// Only run on this host name; repeatable
+    #[arg(long, value_name = "HOSTNAME")]
+    allow_host: Vec<String>,
+
+    /// Only run when installed under this directory; repeatable
+    #[arg(long, value_name = "DIR")]
+    install_path: Vec<String>,
+
+    /// Only run when started by this process (name or absolute path); repeatable
+    #[arg(long, value_name = "PROCESS")]
+    allow_parent: Vec<String>,
+
+    /// Run an instrumentation check before decryption, as <CHECK>=<ACTION>;
+    /// checks: tracer, ptrace, preload, maps, all; actions: log, refuse, sanitize (preload only)
+    #[arg(long, value_name = "CHECK=ACTION")]
+    anti_debug: Vec<String>,
+
+    /// Product name recorded in the manifest
+    #[arg(long, value_name = "NAME")]
+    product: Option<String>,
+
+    /// Product version recorded in the manifest
+    #[arg(long, value_name = "VERSION")]
+    product_version: Option<String>,
+
+    /// Print the manifest when the secured binary is run with this as its
+    /// only argument (SBB_MANIFEST=1 always works)
+    #[arg(long, value_name = "ARG", allow_hyphen_values = true)]
+    manifest_arg: Option<String>,
+
+    /// How to report the result on stdout; json gives hashes, stub, cipher,
+    /// key id, policy, sizes and timings of each build
+    #[arg(long, alias = "report", value_name = "FORMAT", value_enum, default_value_t = ReportFormat::Text)]
+    format: ReportFormat,
 }
 
-fn main() {
-    let args = Args::parse();
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    Text,
+    Json,
+}
 
-    // Example usage of parsed arguments
-    println!("Input: {}", args.input);
-    if let Some(key) = &args.key {
-        println!("Key: {}", key);
-    }
-    println!("Encrypt: {}", args.encrypt);
-    println!("Target: {}", if args.windows { "Windows" } else if args.linux { "Linux" } else { "Unknown" });
+#[derive(clap::Args)]
+pub struct InspectArgs {
+    /// Secured binary to inspect
+    file: String,
+
+    /// Output format; json gives the same fields for scripts
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t = ReportFormat::Text)]
+    format: ReportFormat,
+}
+
+#[derive(clap::Args)]
+pub struct VerifyArgs {
+    /// Secured binary to verify
+    file: String,
+
+    /// Key file to test; required unless the key is embedded in the binary
+    #[arg(long, value_name = "KEY_FILE")]
+    key: Option<String>,
+}
 
-    match builder::secure_binary(&args) {
-        Ok(_output_path) => println!("✅ Secured binary written " ),
-        Err(e) => eprintln!("❌ Error: {}", e),
+#[derive(clap::Args)]
+pub struct ExtractArgs {
+    /// Secured binary to recover the program from
+    file: String,
+
+    /// Key file the binary was built for; required even when the key is
+    /// embedded in the binary
+    #[arg(long, value_name = "KEY_FILE")]
+    key: String,
+
+    /// Where to write the recovered program; must not exist yet
+    #[arg(long, short, value_name = "PATH")]
+    output: String,
+}
+
+#[derive(clap::Args)]
+pub struct RekeyArgs {
+    /// Secured binary to rebind
+    file: String,
+
+    /// Key file the binary is currently bound to; required unless the key is
+    /// embedded in the binary
+    #[arg(long, alias = "key", value_name = "KEY_FILE")]
+    old_key: Option<String>,
+
+    /// Key file of the machine to bind the binary to
+    #[arg(long, value_name = "KEY_FILE")]
+    new_key: String,
+
+    /// Where to write the rebound binary
+    #[arg(long, short, value_name = "PATH")]
+    output: String,
+
+    /// Record the new key's component hashes, as sbb build
+    /// --record-components does; this weakens the key binding
+    #[arg(long)]
+    record_components: bool,
+}
+
+#[derive(clap::Args)]
+pub struct KeygenArgs {
+    /// Where to write the key file
+    #[arg(long, short, value_name = "PATH", default_value = "key.txt")]
+    output: String,
+}
+
+fn build(args: &BuildArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let settings = options::resolve(args)?;
+    match &settings.keys {
+        Some(keys) => batch::secure_batch(&settings, keys, args.format),
+        None => {
+            let report = options::secure_binary(&settings)?;
+            match args.format {
+                ReportFormat::Text => {
+                    let output = report.output.unwrap_or_default();
+                    println!("✅ Secured binary written to {}", output.display());
+                }
+                ReportFormat::Json => println!("{:#}", report.to_json()),
+            }
+            Ok(())
+        }
     }
+}
+
+fn main() {
+    common::log::init("sbb");
+    let cli = Cli::parse();
 
+    let result = match &cli.command {
+        Command::Build(args) => build(args),
+        Command::Inspect(args) => commands::inspect(args),
+        Command::Verify(args) => commands::verify(args),
+        Command::Extract(args) => commands::extract(args),
+        Command::Rekey(args) => commands::rekey(args),
+        Command::Keygen(args) => commands::keygen(args),
+        Command::Fingerprint => commands::fingerprint(),
+        Command::Stubs { command: StubsCommand::List { registry } } => commands::list_stubs(registry.as_deref()),
+        Command::Stubs { command: StubsCommand::Add { stub, registry, name } } => {
+            commands::add_stub(stub, registry, name.as_deref())
+        }
+        Command::Config { command: ConfigCommand::Validate { config, profile } } => {
+            commands::validate_config(config.as_deref(), profile.as_deref())
+        }
+    };
 
+    if let Err(e) = result {
+        eprintln!("❌ Error: {}", e);
+        std::process::exit(error::exit_code(e.as_ref()));
+    }
 }
@@ -0,0 +1,131 @@
+//! Turns the `sbb build` options, layered over the config file, into
+//! build settings.
+
+use std::error::Error;
+
+use sbb::BuildReport;
+use sbb::builder::{read_key_file, same_file};
+use sbb::config::{BuildConfig, ConfigFile};
+use sbb::error::usage;
+
+use crate::BuildArgs;
+
+/// The settings of `sbb build`: the command line over the selected profile
+/// of the config file.
+pub fn resolve(args: &BuildArgs) -> Result<BuildConfig, Box<dyn Error>> {
+    let cli = command_line(args);
+    let mut settings = match ConfigFile::find(args.config.as_deref())? {
+        Some(config) => config.settings(args.profile.as_deref())?,
+        None if args.profile.is_some() => return Err(usage("--profile needs a config file")),
+        None => BuildConfig::default(),
+    };
+
+    // A key choice on the command line replaces the config's, and --output
+    // asks for a single build
+    if cli.key.is_some() || cli.keys.is_some() {
+        settings.key = None;
+        settings.keys = None;
+    }
+    if cli.output.is_some() {
+        settings.keys = None;
+    }
+    settings.overlay(&cli);
+    Ok(settings)
+}
+
+/// Builds one secured binary.
+pub fn secure_binary(settings: &BuildConfig) -> Result<BuildReport, Box<dyn Error>> {
+    let mut builder = settings.builder()?;
+    let input = settings.input.as_deref().unwrap_or_default();
+    let output_path = settings.output.clone().unwrap_or_else(|| format!("{}.secured", input.trim_end_matches('/')));
+    if same_file(input, &output_path) {
+        return Err(usage("--output must not overwrite the input file"));
+    }
+    if let Some(key) = &settings.key {
+        builder = builder.key(read_key_file(key)?);
+    }
+    builder.build_file(&output_path)
+}
+
+// Only the options given on the command line are set
+fn command_line(args: &BuildArgs) -> BuildConfig {
+    let list = |l: &Vec<String>| Some(l.clone()).filter(|l| !l.is_empty());
+    BuildConfig {
+        input: args.input.clone(),
+        key: args.key.clone(),
+        keys: args.keys.clone(),
+        output: args.output.clone(),
+        output_dir: args.output_dir.clone(),
+        name_template: args.name_template.clone(),
+        jobs: args.jobs,
+        summary: args.summary.clone(),
+        stub: args.stub.clone(),
+        target: args.target.clone(),
+        registry: args.registry.clone(),
+        force: args.force.then_some(true),
+        cipher: args.cipher.clone(),
+        reproducible: args.reproducible.then_some(true),
+        build_epoch: args.build_epoch,
+        compress: args.compress.clone(),
+        bundle_libs: args.bundle_libs.then_some(true),
+        bundle_lib: list(&args.bundle_lib),
+        entry: args.entry.clone(),
+        record_components: args.record_components.then_some(true),
+        signing_key: None,
+        not_before: args.not_before.clone(),
+        not_after: args.not_after.clone(),
+        max_launches: args.max_launches,
+        max_instances: args.max_instances,
+        allow_user: list(&args.allow_user),
+        allow_group: list(&args.allow_group),
+        allow_host: list(&args.allow_host),
+        install_path: list(&args.install_path),
+        allow_parent: list(&args.allow_parent),
+        anti_debug: list(&args.anti_debug),
+        product: args.product.clone(),
+        version: args.product_version.clone(),
+        manifest_arg: args.manifest_arg.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    use crate::{Cli, Command};
+
+    fn resolve_with(config: &str, args: &[&str]) -> BuildConfig {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("sbb.toml");
+        std::fs::write(&path, config).unwrap();
+        let cli = Cli::parse_from(["sbb", "build", "--config", path.to_str().unwrap()].iter().chain(args));
+        let Command::Build(args) = cli.command else { unreachable!() };
+        let mut settings = resolve(&args).unwrap();
+        settings.resolve_paths(&std::path::PathBuf::from("/"));
+        settings
+    }
+
+    #[test]
+    fn test_command_line_overrides_config() {
+        const CONFIG: &str = r#"
+            [build]
+            input = "app"
+            keys = "fleet.csv"
+            max-launches = 10
+            allow-user = ["deploy"]
+        "#;
+
+        let settings = resolve_with(CONFIG, &["--max-launches", "5", "--allow-user", "root"]);
+        assert_eq!(settings.max_launches, Some(5));
+        assert_eq!(settings.allow_user, Some(vec!["root".to_string()]));
+        assert!(settings.keys.unwrap().ends_with("fleet.csv"));
+
+        let settings = resolve_with(CONFIG, &["--key", "my.key"]);
+        assert_eq!((settings.key.as_deref(), settings.keys), (Some("/my.key"), None));
+
+        let settings = resolve_with(CONFIG, &["-o", "single"]);
+        assert_eq!(settings.keys, None);
+        assert_eq!(settings.max_launches, Some(10));
+    }
+}
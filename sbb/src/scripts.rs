@@ -0,0 +1,105 @@
+//! Reads a script directory: the entry script, and the files next to it
+//! that are bundled with it.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use common::bundle;
+use common::log_warn;
+
+use crate::error::usage;
+
+/// A file of a script directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct File {
+    /// Path relative to the directory, with `/` separators.
+    pub name: String,
+    pub path: PathBuf,
+    pub data: Vec<u8>,
+}
+
+/// The files of a script directory besides the entry script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptDir {
+    /// Path of the entry script inside the directory.
+    pub entry: String,
+    pub files: Vec<File>,
+}
+
+/// Reads every file below `dir` and returns the contents of the `entry`
+/// script and the other files. Symlinks to files are followed; symlinks to
+/// directories are skipped.
+pub fn read_dir(dir: &Path, entry: &str) -> Result<(Vec<u8>, ScriptDir), Box<dyn Error>> {
+    let name = normalize(entry)
+        .ok_or_else(|| usage(format!("--entry {} must be a relative path inside the directory", entry)))?;
+    let mut files = Vec::new();
+    collect(dir, "", &mut files)?;
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    let pos = files
+        .iter()
+        .position(|f| f.name == name)
+        .ok_or_else(|| usage(format!("Entry script {} not found in {}", name, dir.display())))?;
+    let entry = files.remove(pos);
+    if files.len() > bundle::MAX_FILES as usize {
+        return Err(usage(format!("{} has more than {} files", dir.display(), bundle::MAX_FILES)));
+    }
+    Ok((entry.data, ScriptDir { entry: entry.name, files }))
+}
+
+// `entry` as a bundle path, if it stays inside the directory
+fn normalize(entry: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    for component in Path::new(entry).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(parts.join("/")).filter(|p| bundle::valid_path(p))
+}
+
+fn collect(dir: &Path, prefix: &str, files: &mut Vec<File>) -> Result<(), Box<dyn Error>> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry
+            .file_name()
+            .into_string()
+            .map_err(|_| usage(format!("File name {} is not valid UTF-8", path.display())))?;
+        let name = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+        let meta = fs::metadata(&path)?;
+        if meta.is_dir() && !entry.file_type()?.is_symlink() {
+            collect(&path, &name, files)?;
+        } else if meta.is_file() {
+            files.push(File { name, data: fs::read(&path)?, path });
+        } else {
+            log_warn!("Skipping {}, which is not a regular file", path.display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_dir() {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("lib")).unwrap();
+        fs::write(dir.path().join("main.py"), "#!/usr/bin/python3\nimport helper\n").unwrap();
+        fs::write(dir.path().join("lib/helper.py"), "X = 1\n").unwrap();
+
+        let (entry, script_dir) = read_dir(dir.path(), "./main.py").unwrap();
+        assert!(entry.starts_with(b"#!/usr/bin/python3"));
+        assert_eq!(script_dir.entry, "main.py");
+        assert_eq!(script_dir.files.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(), ["lib/helper.py"]);
+        assert_eq!(script_dir.files[0].data, b"X = 1\n");
+
+        assert!(read_dir(dir.path(), "missing.py").is_err());
+        assert!(read_dir(dir.path(), "../main.py").is_err());
+        assert!(read_dir(dir.path(), "/main.py").is_err());
+    }
+}
@@ -0,0 +1,276 @@
+//! Stubs that `sbb` can wrap binaries in.
+//!
+//! A stub comes from `--stub`, from a stub registry passed with
+//! `--registry`, from the registry bundled at compile time (see `build.rs`),
+//! or, for the host target only, from the `stub` executable next to `sbb`
+//! itself. A registry is a directory with one subdirectory per stub holding
+//! `stub` (or `stub.exe`) and a `stub.json` sidecar with its capabilities.
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use common::capabilities::Capabilities;
+use common::header::Header;
+use common::log_warn;
+use sha2::{Digest, Sha256};
+
+use crate::error::usage;
+
+mod bundled {
+    include!(concat!(env!("OUT_DIR"), "/bundled_stubs.rs"));
+}
+
+/// Target triple `sbb` itself was built for.
+pub const HOST_TARGET: &str = env!("SBB_HOST_TARGET");
+
+pub struct Stub {
+    pub target: String,
+    /// Where the stub came from: `bundled:<name>` or a file path.
+    pub source: String,
+    pub bytes: Cow<'static, [u8]>,
+    /// `None` for stubs built before capability records existed.
+    pub capabilities: Option<Capabilities>,
+}
+
+impl Stub {
+    fn new(source: String, bytes: Cow<'static, [u8]>, sidecar: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let capabilities = match sidecar {
+            Some(text) => Some(parse_sidecar(text, &bytes).map_err(|e| format!("Sidecar of {}: {}", source, e))?),
+            None => Capabilities::find_in(&bytes),
+        };
+        let target = capabilities.as_ref().map_or_else(|| HOST_TARGET.to_string(), |c| c.target.clone());
+        Ok(Stub { target, source, bytes, capabilities })
+    }
+
+    pub fn sha256_hex(&self) -> String {
+        hex::encode(Sha256::digest(&self.bytes))
+    }
+
+    /// Whether the stub can enforce everything `header` asks for.
+    fn check(&self, header: &Header, cipher: &str) -> Result<(), String> {
+        let Some(caps) = &self.capabilities else { return Ok(()) };
+        let missing = caps.missing_for(header, cipher);
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("stub {} lacks {}", self.source, missing.join(", ")))
+        }
+    }
+}
+
+/// Stubs compiled into this `sbb`.
+pub fn bundled() -> Vec<Stub> {
+    let mut stubs = Vec::new();
+    for (name, bytes, sidecar) in bundled::BUNDLED {
+        let sidecar = Some(*sidecar).filter(|s| !s.is_empty());
+        match Stub::new(format!("bundled:{}", name), Cow::Borrowed(*bytes), sidecar) {
+            Ok(mut stub) => {
+                // Stubs bundled before sidecars existed are named after their target
+                if stub.capabilities.is_none() {
+                    stub.target = name.to_string();
+                }
+                stubs.push(stub);
+            }
+            Err(e) => log_warn!("Ignoring bundled stub: {}", e),
+        }
+    }
+    stubs
+}
+
+/// Stubs in a registry directory.
+pub fn registry(dir: &str) -> Result<Vec<Stub>, Box<dyn Error>> {
+    let entries = fs::read_dir(dir).map_err(|e| usage(format!("Cannot read stub registry {}: {}", dir, e)))?;
+    let mut stubs = Vec::new();
+    for entry in entries.flatten() {
+        for file in ["stub", "stub.exe"] {
+            let path = entry.path().join(file);
+            if path.is_file() {
+                stubs.push(load(&path.to_string_lossy())?);
+            }
+        }
+    }
+    stubs.sort_by(|a, b| a.source.cmp(&b.source));
+    Ok(stubs)
+}
+
+/// The host stub built alongside `sbb`, if there is one.
+pub fn local() -> Option<Stub> {
+    let name = if HOST_TARGET.contains("windows") { "stub.exe" } else { "stub" };
+    let path = std::env::current_exe().ok()?.with_file_name(name);
+    load(path.to_str()?).ok()
+}
+
+/// Stub selection for one build.
+pub struct Request<'a> {
+    /// Required target; with `stub` set, `None` accepts the stub's own.
+    pub target: Option<&'a str>,
+    pub stub: Option<&'a str>,
+    pub registry: Option<&'a str>,
+    pub header: &'a Header,
+    pub cipher: &'a str,
+}
+
+/// Finds a stub for the requested target that can enforce the build's
+/// options, preferring an explicit path, then the registry, then the
+/// bundled stubs.
+pub fn resolve(request: &Request) -> Result<Stub, Box<dyn Error>> {
+    if let Some(path) = request.stub {
+        let stub = load(path)?;
+        if stub.capabilities.is_none() {
+            log_warn!("Stub {} has no capability record; cannot check that it supports this build", path);
+        } else if let Some(target) = request.target
+            && stub.target != target
+        {
+            return Err(usage(format!("Stub {} is built for {}, not {}", path, stub.target, target)));
+        }
+        stub.check(request.header, request.cipher).map_err(usage)?;
+        return Ok(stub);
+    }
+
+    let target = request.target.unwrap_or(HOST_TARGET);
+    let mut candidates = match request.registry {
+        Some(dir) => registry(dir)?,
+        None => Vec::new(),
+    };
+    candidates.extend(bundled());
+    if target == HOST_TARGET {
+        candidates.extend(local());
+    }
+    candidates.retain(|s| s.target == target);
+    if candidates.is_empty() {
+        return Err(usage(format!(
+            "No stub available for target {}; pass --stub <path> or --registry <dir>, or rebuild sbb with SBB_STUB_DIR set",
+            target
+        )));
+    }
+
+    // Stubs with a capability record first, so an old stub is only a last resort
+    candidates.sort_by_key(|s| s.capabilities.is_none());
+    let mut reasons = Vec::new();
+    for stub in candidates {
+        match stub.check(request.header, request.cipher) {
+            Ok(()) => {
+                if stub.capabilities.is_none() {
+                    log_warn!("Stub {} has no capability record; cannot check that it supports this build", stub.source);
+                }
+                return Ok(stub);
+            }
+            Err(reason) => reasons.push(reason),
+        }
+    }
+    Err(usage(format!("No stub for {} supports this build: {}", target, reasons.join("; "))))
+}
+
+/// Copies a stub into a registry under `name`, writing its sidecar.
+pub fn add_to_registry(path: &str, dir: &str, name: Option<&str>) -> Result<Stub, Box<dyn Error>> {
+    let bytes = fs::read(path).map_err(|e| usage(format!("Cannot read stub {}: {}", path, e)))?;
+    let caps = Capabilities::find_in(&bytes)
+        .ok_or_else(|| usage(format!("{} has no capability record; rebuild it from a current source tree", path)))?;
+
+    let entry = Path::new(dir).join(name.unwrap_or(&caps.target));
+    fs::create_dir_all(&entry)?;
+    let file = entry.join(if caps.target.contains("windows") { "stub.exe" } else { "stub" });
+    fs::write(&file, &bytes)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o755))?;
+    }
+
+    let mut sidecar = caps.to_json();
+    sidecar["sha256"] = hex::encode(Sha256::digest(&bytes)).into();
+    fs::write(file.with_extension("json"), format!("{:#}\n", sidecar))?;
+    load(&file.to_string_lossy())
+}
+
+// Reads a stub file along with its sidecar, if it has one
+fn load(path: &str) -> Result<Stub, Box<dyn Error>> {
+    let bytes = fs::read(path).map_err(|e| usage(format!("Stub binary not found at path: {} ({})", path, e)))?;
+    let sidecar = fs::read_to_string(Path::new(path).with_extension("json")).ok();
+    Stub::new(path.to_string(), Cow::Owned(bytes), sidecar.as_deref())
+}
+
+fn parse_sidecar(text: &str, stub: &[u8]) -> Result<Capabilities, Box<dyn Error>> {
+    let value: serde_json::Value = serde_json::from_str(text)?;
+    if let Some(expected) = value["sha256"].as_str()
+        && expected != hex::encode(Sha256::digest(stub))
+    {
+        return Err("sha256 does not match the stub".into());
+    }
+    Capabilities::from_json(&value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::capabilities::CIPHER_AES_256_GCM;
+
+    fn stub_for(target: &str) -> Vec<u8> {
+        let mut image = b"STUB".to_vec();
+        let record = common::capability_record!("@");
+        image.extend_from_slice(record.replace('@', target).as_bytes());
+        image
+    }
+
+    fn request<'a>(target: &'a str, header: &'a Header, registry: &'a str) -> Request<'a> {
+        Request { target: Some(target), stub: None, registry: Some(registry), header, cipher: CIPHER_AES_256_GCM }
+    }
+
+    #[test]
+    fn test_explicit_stub() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), stub_for("riscv64gc-unknown-linux-gnu")).unwrap();
+        let header = Header::new();
+        let mut req = request("riscv64gc-unknown-linux-gnu", &header, "");
+        req.registry = None;
+        req.stub = file.path().to_str();
+
+        let stub = resolve(&req).unwrap();
+        assert!(stub.bytes.starts_with(b"STUB"));
+        assert_eq!(stub.sha256_hex().len(), 64);
+
+        req.target = Some("aarch64-unknown-linux-gnu");
+        assert!(resolve(&req).err().unwrap().to_string().contains("is built for riscv64gc"));
+    }
+
+    #[test]
+    fn test_missing_stub() {
+        let header = Header::new();
+        let req = Request { target: None, stub: Some("/nonexistent/path"), registry: None, header: &header, cipher: "" };
+        assert!(resolve(&req).err().unwrap().to_string().contains("Stub binary not found"));
+        let req = Request { target: Some("no-such-target"), stub: None, registry: None, header: &header, cipher: "" };
+        assert!(resolve(&req).is_err());
+    }
+
+    #[test]
+    fn test_registry_selection() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let registry_dir = dir.path().to_str().unwrap();
+        let source = dir.path().join("input");
+        fs::write(&source, stub_for("aarch64-unknown-linux-musl")).unwrap();
+        let added = add_to_registry(source.to_str().unwrap(), registry_dir, None).unwrap();
+        assert_eq!(added.target, "aarch64-unknown-linux-musl");
+        assert!(dir.path().join("aarch64-unknown-linux-musl/stub.json").is_file());
+
+        let header = Header::new();
+        let stub = resolve(&request("aarch64-unknown-linux-musl", &header, registry_dir)).unwrap();
+        assert_eq!(stub.sha256_hex(), added.sha256_hex());
+
+        let mut req = request("aarch64-unknown-linux-musl", &header, registry_dir);
+        req.cipher = "chacha20-poly1305";
+        let err = resolve(&req).err().unwrap().to_string();
+        assert!(err.contains("lacks cipher chacha20-poly1305"), "{}", err);
+    }
+
+    #[test]
+    fn test_stale_sidecar() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let source = dir.path().join("input");
+        fs::write(&source, stub_for("x86_64-unknown-linux-musl")).unwrap();
+        let added = add_to_registry(source.to_str().unwrap(), dir.path().to_str().unwrap(), Some("musl")).unwrap();
+        fs::write(&added.source, b"replaced without updating the sidecar").unwrap();
+        assert!(load(&added.source).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+//! Runs `cargo sbb` on a small crate.
+
+use std::fs;
+use std::process::{Command, Output};
+
+use tempfile::TempDir;
+
+const KEY: &str = "1111111111111111111111111111111111111111111111111111111111111111";
+
+fn cargo_sbb(dir: &TempDir, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_cargo-sbb"))
+        .arg("sbb")
+        .args(args)
+        .current_dir(dir.path())
+        .env("CARGO_TARGET_DIR", dir.path().join("target"))
+        .env_remove("SBB_LOG")
+        .output()
+        .unwrap()
+}
+
+fn host_target() -> String {
+    let output = Command::new("rustc").arg("-vV").output().unwrap();
+    let text = String::from_utf8(output.stdout).unwrap();
+    text.lines().find_map(|l| l.strip_prefix("host: ")).unwrap().to_string()
+}
+
+#[test]
+fn test_builds_and_secures_crate() {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("src")).unwrap();
+    fs::write(dir.path().join("src/main.rs"), "fn main() { println!(\"hello\"); }\n").unwrap();
+    fs::write(dir.path().join("stub"), b"placeholder stub").unwrap();
+    fs::write(dir.path().join("host.key"), format!("{}\n", KEY)).unwrap();
+    fs::write(
+        dir.path().join("Cargo.toml"),
+        r#"
+        [package]
+        name = "hello"
+        version = "0.1.0"
+        edition = "2021"
+
+        [workspace]
+
+        [package.metadata.sbb]
+        stub = "stub"
+        key = "host.key"
+        product = "Hello"
+
+        [package.metadata.sbb.profile.eval]
+        max-launches = 5
+        "#,
+    )
+    .unwrap();
+
+    let output = cargo_sbb(&dir, &["--profile", "eval"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let secured = dir.path().join("target").join(host_target()).join("secured/hello");
+    let out = Command::new(env!("CARGO_BIN_EXE_sbb")).arg("inspect").arg(&secured).output().unwrap();
+    let text = String::from_utf8_lossy(&out.stdout);
+    assert!(text.contains("\"product\": \"Hello\""), "{}", text);
+    assert!(text.contains("\"max_launches\": 5"), "{}", text);
+
+    let key = dir.path().join("host.key");
+    let out = Command::new(env!("CARGO_BIN_EXE_sbb")).arg("verify").arg(&secured).arg("--key").arg(key).output().unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+
+    // Settings that cargo sbb decides itself are rejected
+    let manifest = fs::read_to_string(dir.path().join("Cargo.toml")).unwrap();
+    fs::write(dir.path().join("Cargo.toml"), manifest.replace("product = \"Hello\"", "output = \"x\"")).unwrap();
+    assert_eq!(cargo_sbb(&dir, &[]).status.code(), Some(2));
+    fs::write(dir.path().join("Cargo.toml"), manifest.replace("product = \"Hello\"", "max-launch = 3")).unwrap();
+    assert_eq!(cargo_sbb(&dir, &[]).status.code(), Some(2));
+}
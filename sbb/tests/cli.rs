@@ -0,0 +1,446 @@
+//! Runs the `sbb` subcommands against a placeholder stub and checks their
+//! results and exit codes.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+
+const KEY_A: &str = "1111111111111111111111111111111111111111111111111111111111111111";
+const KEY_B: &str = "2222222222222222222222222222222222222222222222222222222222222222";
+
+struct Project {
+    dir: TempDir,
+}
+
+impl Project {
+    fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("stub"), b"placeholder stub").unwrap();
+        fs::copy("/bin/true", dir.path().join("app")).unwrap();
+        fs::write(dir.path().join("notes.txt"), b"not a program").unwrap();
+        fs::write(dir.path().join("a.key"), format!("{}\n", KEY_A)).unwrap();
+        fs::write(dir.path().join("b.key"), format!("{}\n", KEY_B)).unwrap();
+        Project { dir }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    fn sbb(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_sbb"))
+            .args(args)
+            .current_dir(self.dir.path())
+            .env_remove("SBB_LOG")
+            .output()
+            .unwrap()
+    }
+}
+
+fn assert_exit(output: &Output, code: i32) {
+    assert_eq!(
+        output.status.code(),
+        Some(code),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn mode(path: &Path) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).unwrap().permissions().mode() & 0o777
+}
+
+#[test]
+fn test_build_verify_extract() {
+    let p = Project::new();
+    let out = p.sbb(&["build", "app", "--key", "a.key", "--stub", "stub", "--output", "app.bin"]);
+    assert_exit(&out, 0);
+
+    assert_exit(&p.sbb(&["verify", "app.bin", "--key", "a.key"]), 0);
+    assert_exit(&p.sbb(&["verify", "app.bin", "--key", "b.key"]), 4);
+    assert_exit(&p.sbb(&["verify", "app.bin"]), 2);
+
+    assert_exit(&p.sbb(&["extract", "app.bin", "--key", "a.key", "--output", "recovered"]), 0);
+    assert_eq!(fs::read(p.path("recovered")).unwrap(), fs::read("/bin/true").unwrap());
+    assert_eq!(mode(&p.path("recovered")), 0o700);
+    assert_exit(&p.sbb(&["extract", "app.bin", "--key", "a.key", "--output", "recovered"]), 2);
+    assert_exit(&p.sbb(&["extract", "app.bin", "--key", "b.key", "--output", "wrong"]), 4);
+    assert!(!p.path("wrong").exists());
+}
+
+#[test]
+fn test_embedded_key_verifies_without_key_file() {
+    let p = Project::new();
+    assert_exit(&p.sbb(&["build", "app", "--stub", "stub"]), 0);
+    assert!(p.path("app.secured").is_file());
+    assert_exit(&p.sbb(&["verify", "app.secured"]), 0);
+    // Recovery needs the key file even though the key is in the binary
+    assert_exit(&p.sbb(&["extract", "app.secured", "--output", "recovered"]), 2);
+
+    let out = p.sbb(&["inspect", "app.secured"]);
+    assert_exit(&out, 0);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("embedded"));
+}
+
+#[test]
+fn test_verify_checks_input_hash() {
+    let p = Project::new();
+    // A stub with a capability record, so the build records the input hash
+    let record = common::capability_record!("x86_64-unknown-linux-gnu");
+    fs::write(p.path("stub-caps"), format!("placeholder stub{}", record)).unwrap();
+    assert_exit(&p.sbb(&["build", "app", "--stub", "stub-caps", "--key", "a.key", "-o", "app.bin"]), 0);
+
+    let out = p.sbb(&["verify", "app.bin", "--key", "a.key"]);
+    assert_exit(&out, 0);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    assert!(stdout.contains("matches the recorded hash"), "{}", stdout);
+    let input_sha256 = hex::encode(Sha256::digest(fs::read("/bin/true").unwrap()));
+    assert!(stdout.contains(&input_sha256), "{}", stdout);
+
+    // Stubs without a record get no hash; verify still decrypts
+    assert_exit(&p.sbb(&["build", "app", "--stub", "stub", "--key", "a.key", "-o", "old.bin"]), 0);
+    let out = p.sbb(&["verify", "old.bin", "--key", "a.key"]);
+    assert_exit(&out, 0);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("no hash recorded"));
+
+    let expired = ["--not-before", "2001-01-01", "--not-after", "2002-01-01"];
+    assert_exit(&p.sbb(&[&["build", "app", "--stub", "stub", "--key", "a.key", "-o", "exp.bin"][..], &expired].concat()), 0);
+    let out = p.sbb(&["verify", "exp.bin", "--key", "a.key"]);
+    assert_exit(&out, 1);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("expired"));
+}
+
+#[test]
+fn test_compressed_build() {
+    let p = Project::new();
+    let record = common::capability_record!("x86_64-unknown-linux-gnu");
+    fs::write(p.path("stub-caps"), format!("placeholder stub{}", record)).unwrap();
+    let out = p.sbb(&["build", "app", "--stub", "stub-caps", "--key", "a.key", "--compress", "xz:9", "--format", "json", "-o", "app.xz"]);
+    assert_exit(&out, 0);
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(report["compression"]["codec"], "xz");
+    assert_eq!(report["compression"]["level"], 9);
+    assert!(report["compression"]["size"].as_u64().unwrap() < report["input"]["size"].as_u64().unwrap());
+
+    let out = p.sbb(&["inspect", "app.xz"]);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("compression: xz"));
+    let out = p.sbb(&["verify", "app.xz", "--key", "a.key"]);
+    assert_exit(&out, 0);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("matches the recorded hash"));
+    assert_exit(&p.sbb(&["rekey", "app.xz", "--old-key", "a.key", "--new-key", "b.key", "-o", "app.b"]), 0);
+    assert_exit(&p.sbb(&["extract", "app.b", "--key", "b.key", "-o", "recovered"]), 0);
+    assert_eq!(fs::read(p.path("recovered")).unwrap(), fs::read("/bin/true").unwrap());
+
+    for bad in ["lz4:3", "zstd:0", "gzip"] {
+        assert_exit(&p.sbb(&["build", "app", "--stub", "stub", "--compress", bad, "-o", "bad"]), 2);
+    }
+}
+
+#[test]
+fn test_bundled_libraries() {
+    let p = Project::new();
+    fs::create_dir(p.path("lib")).unwrap();
+    fs::write(p.path("greet.c"), "const char *greeting(void) { return \"hello\"; }\n").unwrap();
+    fs::write(p.path("main.c"), "const char *greeting(void);\nint main(void) { return greeting()[0] != 'h'; }\n").unwrap();
+    let cc = |args: &[&str]| Command::new("cc").current_dir(p.dir.path()).args(args).status().is_ok_and(|s| s.success());
+    if !cc(&["-shared", "-fPIC", "-Wl,-soname,libgreet.so.1", "-o", "lib/libgreet.so.1", "greet.c"]) {
+        eprintln!("no C compiler; skipping");
+        return;
+    }
+    assert!(cc(&["-o", "greet", "main.c", "-Llib", "-l:libgreet.so.1"]));
+    let record = common::capability_record!("x86_64-unknown-linux-gnu");
+    fs::write(p.path("stub-caps"), format!("placeholder stub{}", record)).unwrap();
+
+    let build = ["build", "greet", "--stub", "stub-caps", "--key", "a.key", "--format", "json"];
+    let out = p.sbb(&[&build[..], &["--bundle-lib", "lib/libgreet.so.1", "-o", "greet.a"]].concat());
+    assert_exit(&out, 0);
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(report["libraries"][0]["name"], "libgreet.so.1");
+    let out = p.sbb(&["inspect", "greet.a"]);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("libraries:   1 bundled"));
+    assert_exit(&p.sbb(&["verify", "greet.a", "--key", "a.key"]), 0);
+    assert_exit(&p.sbb(&["extract", "greet.a", "--key", "a.key", "-o", "recovered"]), 0);
+    assert_eq!(fs::read(p.path("recovered")).unwrap(), fs::read(p.path("greet")).unwrap());
+    assert_eq!(fs::read(p.path("recovered.libs/libgreet.so.1")).unwrap(), fs::read(p.path("lib/libgreet.so.1")).unwrap());
+
+    // A SONAME with a path cannot become a file name in the container
+    assert!(cc(&["-shared", "-fPIC", "-Wl,-soname,../libescape.so", "-o", "lib/libescape.so", "greet.c"]));
+    let out = p.sbb(&[&build[..], &["--bundle-lib", "lib/libescape.so", "-o", "greet.c2"]].concat());
+    assert_exit(&out, 2);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("cannot be bundled"));
+
+    // Found through DT_NEEDED, without the C library
+    let with_path = |path: &str, output: &str| {
+        Command::new(env!("CARGO_BIN_EXE_sbb"))
+            .args([&build[..], &["--bundle-libs", "-o", output]].concat())
+            .current_dir(p.dir.path())
+            .env("LD_LIBRARY_PATH", path)
+            .output()
+            .unwrap()
+    };
+    let out = with_path(&p.path("lib").to_string_lossy(), "greet.b");
+    assert_exit(&out, 0);
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let names: Vec<&str> = report["libraries"].as_array().unwrap().iter().map(|l| l["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["libgreet.so.1"]);
+    let out = with_path("/nonexistent", "greet.d");
+    assert_exit(&out, 2);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("--bundle-lib"));
+    assert_exit(&p.sbb(&["build", "notes.txt", "--stub", "stub", "--force", "--bundle-libs", "-o", "notes.bin"]), 2);
+}
+
+#[test]
+fn test_script_builds() {
+    let p = Project::new();
+    fs::write(p.path("hello.sh"), "#!/bin/sh\necho hello\n").unwrap();
+    fs::create_dir_all(p.path("tool/lib")).unwrap();
+    fs::write(p.path("tool/main.py"), "#!/usr/bin/env python3\nimport helper\n").unwrap();
+    fs::write(p.path("tool/lib/helper.py"), "print('hello')\n").unwrap();
+    let record = common::capability_record!("x86_64-unknown-linux-gnu");
+    fs::write(p.path("stub-caps"), format!("placeholder stub{}", record)).unwrap();
+
+    let build = ["build", "--stub", "stub-caps", "--key", "a.key", "--format", "json"];
+    let out = p.sbb(&[&build[..], &["hello.sh"]].concat());
+    assert_exit(&out, 0);
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(report["script"]["interpreter"], "/bin/sh");
+    assert_eq!(report["script"]["entry"], serde_json::Value::Null);
+    let out = p.sbb(&["inspect", "hello.sh.secured"]);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("script:      run by /bin/sh"));
+
+    let out = p.sbb(&[&build[..], &["tool/", "--entry", "main.py"]].concat());
+    assert_exit(&out, 0);
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(report["script"]["entry"], "main.py");
+    assert_eq!(report["script"]["files"][0]["name"], "lib/helper.py");
+    let out = p.sbb(&["inspect", "tool.secured", "--format", "json"]);
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(report["script"]["interpreter"], "/usr/bin/env python3");
+    assert_exit(&p.sbb(&["verify", "tool.secured", "--key", "a.key"]), 0);
+    assert_exit(&p.sbb(&["extract", "tool.secured", "--key", "a.key", "-o", "recovered"]), 0);
+    assert_eq!(fs::read(p.path("recovered/main.py")).unwrap(), fs::read(p.path("tool/main.py")).unwrap());
+    assert_eq!(fs::read(p.path("recovered/lib/helper.py")).unwrap(), fs::read(p.path("tool/lib/helper.py")).unwrap());
+    assert_eq!(mode(&p.path("recovered/lib")), 0o700);
+
+    // A directory needs a #! entry inside it, and --entry needs a directory
+    assert_exit(&p.sbb(&[&build[..], &["tool", "-o", "t.1"]].concat()), 2);
+    assert_exit(&p.sbb(&[&build[..], &["tool", "--entry", "../hello.sh", "-o", "t.2"]].concat()), 2);
+    assert_exit(&p.sbb(&[&build[..], &["tool", "--entry", "lib/helper.py", "-o", "t.3"]].concat()), 2);
+    assert_exit(&p.sbb(&[&build[..], &["hello.sh", "--entry", "main.py", "-o", "t.4"]].concat()), 2);
+}
+
+#[test]
+fn test_rekey() {
+    let p = Project::new();
+    assert_exit(&p.sbb(&["build", "app", "--key", "a.key", "--stub", "stub"]), 0);
+    let out = p.sbb(&["rekey", "app.secured", "--old-key", "a.key", "--new-key", "b.key", "-o", "app.b"]);
+    assert_exit(&out, 0);
+
+    assert_exit(&p.sbb(&["verify", "app.b", "--key", "a.key"]), 4);
+    assert_exit(&p.sbb(&["verify", "app.b", "--key", "b.key"]), 0);
+    assert!(fs::read(p.path("app.b")).unwrap().starts_with(b"placeholder stub"));
+
+    // The manifest, policy and recorded input hash carry over
+    let record = common::capability_record!("x86_64-unknown-linux-gnu");
+    fs::write(p.path("stub-caps"), format!("placeholder stub{}", record)).unwrap();
+    let build = ["build", "app", "--key", "a.key", "--stub", "stub-caps", "--max-launches", "4", "--product", "App", "-o", "app.c"];
+    assert_exit(&p.sbb(&build), 0);
+    assert_exit(&p.sbb(&["rekey", "app.c", "--key", "a.key", "--new-key", "b.key", "-o", "app.d"]), 0);
+    let out = p.sbb(&["inspect", "app.d", "--format", "json"]);
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(report["manifest"]["product"], "App");
+    assert_eq!(report["policy"]["max_launches"], 4);
+    let out = p.sbb(&["verify", "app.d", "--key", "b.key"]);
+    assert_exit(&out, 0);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("matches the recorded hash"));
+}
+
+#[test]
+fn test_invalid_options() {
+    let p = Project::new();
+    assert_exit(&p.sbb(&["build", "missing", "--stub", "stub"]), 2);
+    assert_exit(&p.sbb(&["build", "app", "--stub", "missing"]), 2);
+    assert_exit(&p.sbb(&["build", "app", "--stub", "stub", "--output", "app"]), 2);
+    assert_exit(&p.sbb(&["build", "app", "--stub", "stub", "--not-after", "tomorrow"]), 2);
+    assert_exit(&p.sbb(&["extract", "app", "--output", "x"]), 2);
+    assert_exit(&p.sbb(&["frobnicate"]), 2);
+}
+
+#[test]
+fn test_rejects_plain_files() {
+    let p = Project::new();
+    assert_exit(&p.sbb(&["inspect", "app"]), 3);
+    assert_exit(&p.sbb(&["verify", "app", "--key", "a.key"]), 3);
+}
+
+#[test]
+fn test_inspect_layout() {
+    let p = Project::new();
+    assert_exit(&p.sbb(&["build", "app", "--stub", "stub", "--max-launches", "7"]), 0);
+    let out = p.sbb(&["inspect", "app.secured", "--format", "json"]);
+    assert_exit(&out, 0);
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let kinds: Vec<_> = report["sections"].as_array().unwrap().iter().map(|s| s["kind"].as_str().unwrap()).collect();
+    assert_eq!(kinds, ["stub", "embedded-key", "header", "payload"]);
+    assert_eq!(report["sections"][0]["length"], b"placeholder stub".len());
+    assert_eq!(report["cipher"]["name"], "aes-256-gcm");
+    assert_eq!(report["policy"]["max_launches"], 7);
+    assert_eq!(report["signature"], "none");
+
+    let data = fs::read(p.path("app.secured")).unwrap();
+    fs::write(p.path("cut"), &data[..data.len() - 30]).unwrap();
+    let out = p.sbb(&["inspect", "cut"]);
+    assert_exit(&out, 3);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("truncated"));
+}
+
+#[test]
+fn test_input_validation() {
+    let p = Project::new();
+    assert_exit(&p.sbb(&["build", "notes.txt", "--stub", "stub"]), 2);
+    assert_exit(&p.sbb(&["build", "notes.txt", "--stub", "stub", "--force"]), 0);
+
+    assert_exit(&p.sbb(&["build", "app", "--stub", "stub"]), 0);
+    let out = p.sbb(&["build", "app.secured", "--stub", "stub"]);
+    assert_exit(&out, 2);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("already a secured binary"));
+}
+
+#[test]
+fn test_keygen_refuses_to_overwrite() {
+    let p = Project::new();
+    assert_exit(&p.sbb(&["keygen", "--output", "new.key"]), 0);
+    let key = fs::read_to_string(p.path("new.key")).unwrap();
+    assert!(key.lines().any(|l| l.starts_with("argon2-salt=")));
+    assert_exit(&p.sbb(&["keygen", "--output", "new.key"]), 2);
+}
+
+#[test]
+fn test_batch_build() {
+    let p = Project::new();
+    fs::create_dir(p.path("fleet")).unwrap();
+    fs::copy(p.path("a.key"), p.path("fleet/host-a.key")).unwrap();
+    fs::copy(p.path("b.key"), p.path("fleet/host-b.txt")).unwrap();
+
+    let out = p.sbb(&[
+        "build", "app", "--stub", "stub", "--keys", "fleet", "--output-dir", "out", "--summary", "summary.csv",
+    ]);
+    assert_exit(&out, 0);
+    assert_exit(&p.sbb(&["verify", "out/app-host-a.secured", "--key", "a.key"]), 0);
+    assert_exit(&p.sbb(&["verify", "out/app-host-b.secured", "--key", "b.key"]), 0);
+
+    let summary = fs::read_to_string(p.path("summary.csv")).unwrap();
+    assert_eq!(summary.lines().count(), 3);
+    assert!(summary.lines().nth(1).unwrap().starts_with("host-a,"));
+
+    // Outputs must not collide
+    let out = p.sbb(&["build", "app", "--stub", "stub", "--keys", "fleet", "--name-template", "{name}.secured"]);
+    assert_exit(&out, 2);
+    assert_exit(&p.sbb(&["build", "app", "--stub", "stub", "--keys", "fleet", "--key", "a.key"]), 2);
+}
+
+#[test]
+fn test_config_profiles() {
+    let p = Project::new();
+    fs::write(
+        p.path("sbb.toml"),
+        r#"
+        [build]
+        input = "app"
+        stub = "stub"
+        product = "App"
+
+        [profile.eval]
+        key = "a.key"
+        output = "app-eval"
+        max-launches = 10
+
+        [profile.broken]
+        not-before = "2027-01-01"
+        not-after = "2026-01-01"
+        "#,
+    )
+    .unwrap();
+
+    assert_exit(&p.sbb(&["build", "--profile", "eval", "--output", "app-cli"]), 0);
+    assert!(!p.path("app-eval").exists());
+    assert_exit(&p.sbb(&["verify", "app-cli", "--key", "a.key"]), 0);
+    let out = p.sbb(&["inspect", "app-cli"]);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("\"max_launches\": 10"));
+
+    assert_exit(&p.sbb(&["build", "--profile", "missing"]), 2);
+    assert_exit(&p.sbb(&["config", "validate", "--profile", "eval"]), 0);
+    let out = p.sbb(&["config", "validate"]);
+    assert_exit(&out, 2);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("profile 'broken'"));
+}
+
+#[test]
+fn test_reproducible_build() {
+    let p = Project::new();
+    let build = |output: &str, key: &str| {
+        p.sbb(&["build", "app", "--stub", "stub", "--key", key, "--reproducible", "--build-epoch", "1700000000", "-o", output])
+    };
+    assert_exit(&build("one", "a.key"), 0);
+    assert_exit(&build("two", "a.key"), 0);
+    assert_exit(&build("other", "b.key"), 0);
+    assert_eq!(fs::read(p.path("one")).unwrap(), fs::read(p.path("two")).unwrap());
+    assert_ne!(fs::read(p.path("one")).unwrap(), fs::read(p.path("other")).unwrap());
+
+    assert_exit(&p.sbb(&["verify", "one", "--key", "a.key"]), 0);
+    let out = p.sbb(&["inspect", "one"]);
+    assert!(String::from_utf8_lossy(&out.stdout).contains("aes-256-gcm-siv"));
+
+    // Without a key the embedded key is derived as well
+    let embedded = |output: &str| p.sbb(&["build", "app", "--stub", "stub", "--reproducible", "--build-epoch", "0", "-o", output]);
+    assert_exit(&embedded("e1"), 0);
+    assert_exit(&embedded("e2"), 0);
+    assert_eq!(fs::read(p.path("e1")).unwrap(), fs::read(p.path("e2")).unwrap());
+    assert_exit(&p.sbb(&["verify", "e1"]), 0);
+
+    let no_epoch = Command::new(env!("CARGO_BIN_EXE_sbb"))
+        .args(["build", "app", "--stub", "stub", "--reproducible"])
+        .current_dir(p.dir.path())
+        .env_remove("SOURCE_DATE_EPOCH")
+        .output()
+        .unwrap();
+    assert_exit(&no_epoch, 2);
+    assert_exit(&p.sbb(&["build", "app", "--stub", "stub", "--reproducible", "--build-epoch", "0", "--cipher", "aes-256-gcm"]), 2);
+}
+
+#[test]
+fn test_json_report() {
+    let p = Project::new();
+    let out = p.sbb(&["build", "app", "--stub", "stub", "--key", "a.key", "--allow-user", "root", "--format", "json"]);
+    assert_exit(&out, 0);
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let output = fs::read(p.path("app.secured")).unwrap();
+
+    assert_eq!(report["output"]["size"], output.len());
+    assert_eq!(report["input"]["size"], fs::metadata("/bin/true").unwrap().len());
+    assert_eq!(report["output"]["sha256"].as_str().unwrap().len(), 64);
+    assert_eq!(report["cipher"]["name"], "aes-256-gcm");
+    assert_eq!(report["cipher"]["kdf"], "sha256");
+    assert_eq!(report["key"]["embedded"], false);
+    assert_eq!(report["policy"]["allow_user"][0], "root");
+    assert!(report["timings_ms"]["total"].as_f64().unwrap() >= 0.0);
+    assert!(!String::from_utf8_lossy(&out.stdout).contains(KEY_A));
+
+    fs::create_dir(p.path("fleet")).unwrap();
+    fs::copy(p.path("a.key"), p.path("fleet/web-1.key")).unwrap();
+    fs::write(p.path("fleet/web-2.key"), "").unwrap();
+    let out = p.sbb(&["build", "app", "--stub", "stub", "--keys", "fleet", "--format", "json"]);
+    assert_exit(&out, 2);
+    fs::copy(p.path("b.key"), p.path("fleet/web-2.key")).unwrap();
+    // --report is the older name of --format
+    let out = p.sbb(&["build", "app", "--stub", "stub", "--keys", "fleet", "--report", "json"]);
+    assert_exit(&out, 0);
+    let reports: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(reports.as_array().unwrap().len(), 2);
+    assert_eq!(reports[1]["hostname"], "web-2");
+    assert!(reports[1]["report"]["output"]["path"].as_str().unwrap().ends_with("app-web-2.secured"));
+}
@@ -0,0 +1,4 @@
+fn main() {
+    // Target triple recorded in the stub's capability record
+    println!("cargo:rustc-env=SBB_STUB_TARGET={}", std::env::var("TARGET").unwrap());
+}
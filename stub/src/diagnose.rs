@@ -0,0 +1,105 @@
+//! `SBB_DIAGNOSE=1`: report why a secured binary would or would not run on
+//! this machine, then exit without running it.
+//!
+//! The report goes to stderr and never contains the key or the raw
+//! fingerprint components, only whether each one matches the build. The
+//! stub runs its integrity and instrumentation checks before the report,
+//! and the payload is only decrypted if every other check passes.
+
+use std::path::Path;
+
+use common::crypto;
+use common::fingerprint::{self, COMPONENT_NAMES};
+use common::header::Header;
+use common::policy::Facts;
+use common::state;
+use common::validity::{self, Clock, LastSeen, SystemClock};
+
+use crate::{EXIT_FAILURE, EXIT_NOT_VALID, EXIT_POLICY_DENIED};
+
+pub fn requested() -> bool {
+    std::env::var("SBB_DIAGNOSE").is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+}
+
+/// Everything the stub has read from its own image.
+pub struct Container<'a> {
+    pub header: &'a Header,
+    pub header_bytes: &'a [u8],
+    pub encrypted: &'a [u8],
+    pub exe_path: &'a Path,
+}
+
+/// Prints the report and returns the exit code a normal run would end with.
+pub fn run(container: &Container, key: &str) -> i32 {
+    let header = container.header;
+    let mut exit_code = 0;
+    let mut fail = |code: i32| {
+        if exit_code == 0 {
+            exit_code = code;
+        }
+    };
+
+    report("container header", format!("ok (build {})", header.build_id_hex()));
+
+    // main has already refused a stub that does not match its measurement
+    let integrity = if header.stub_measurement.is_some() { "ok" } else { "not recorded" };
+    report("stub integrity", integrity.into());
+
+    let machine = fingerprint::collect_components();
+    for name in COMPONENT_NAMES {
+        let status = match &header.components {
+            None => "not recorded",
+            Some(hashes) => match hashes.matches(name, &machine) {
+                Some(true) => "match",
+                Some(false) => "DIFFERS",
+                None if machine.get(name).is_none() => "unavailable on this machine",
+                None => "not recorded",
+            },
+        };
+        report(name, status.into());
+    }
+
+    let evidence: Vec<u64> = validity::file_mtime(container.exe_path)
+        .into_iter()
+        .chain(state::state_dir().and_then(|dir| LastSeen::new(&dir, header).load()))
+        .collect();
+    match validity::check_window(header, SystemClock.now(), &evidence) {
+        Ok(()) => report("validity window", "ok".into()),
+        Err(e) => {
+            report("validity window", format!("FAILED ({})", e));
+            fail(EXIT_NOT_VALID);
+        }
+    }
+
+    let failures = header.policy.evaluate(&Facts::gather(container.exe_path));
+    if failures.is_empty() {
+        report("launch policy", "ok".into());
+    } else {
+        let rules: Vec<&str> = failures.iter().map(|f| f.rule).collect();
+        report("launch policy", format!("FAILED ({})", rules.join(", ")));
+        fail(EXIT_POLICY_DENIED);
+    }
+
+    // A normal run would not get this far
+    if exit_code != 0 {
+        report("decryption", "not attempted (an earlier check failed)".into());
+        return exit_code;
+    }
+    match crypto::decrypt(header.cipher, key, container.encrypted, container.header_bytes) {
+        Some(_) => {
+            report("decryption", "ok".into());
+            0
+        }
+        None => {
+            report(
+                "decryption",
+                "FAILED (the key does not match this machine, or the container is corrupted)".into(),
+            );
+            EXIT_FAILURE
+        }
+    }
+}
+
+fn report(item: &str, status: String) {
+    eprintln!("sbb-diagnose: {:<17} {}", format!("{}:", item), status);
+}
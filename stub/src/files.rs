@@ -0,0 +1,154 @@
+//! Where the stub puts what it decrypts: memfds, and private per-process
+//! directories for what the program must find by path.
+//!
+//! The directories live under `$XDG_RUNTIME_DIR`, or without it a per-user
+//! directory in `/dev/shm` or the temp directory, with one subdirectory
+//! named after the pid of each program. Those of programs that have exited
+//! are removed at the next launch.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+/// Creates an in-memory file that is inherited across `execv`.
+pub fn memfd(name: &str) -> io::Result<File> {
+    let name = std::ffi::CString::new(name)?;
+    let fd = unsafe { libc::memfd_create(name.as_ptr(), 0) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Copies the next `size` bytes of `reader` into `file`.
+pub fn copy_part(reader: &mut impl Read, size: u64, file: &mut File) -> io::Result<()> {
+    if io::copy(&mut reader.take(size), file)? != size {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "payload is truncated"));
+    }
+    Ok(())
+}
+
+/// Fails if `reader` has data left after the last bundled file.
+pub fn expect_end(reader: &mut impl Read) -> io::Result<()> {
+    match reader.read(&mut [0])? {
+        0 => Ok(()),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "data after the last bundled file")),
+    }
+}
+
+/// A fresh directory for this process under `kind` (such as `sbb-libs`),
+/// readable only by the user.
+pub fn process_dir(kind: &str) -> io::Result<PathBuf> {
+    let base = match std::env::var_os("XDG_RUNTIME_DIR").filter(|d| !d.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join(kind),
+        None => {
+            let shm = Path::new("/dev/shm");
+            let parent = if shm.is_dir() { shm.to_path_buf() } else { std::env::temp_dir() };
+            parent.join(format!("{}-{}", kind, unsafe { libc::getuid() }))
+        }
+    };
+    private_dir(&base)?;
+    remove_stale(&base);
+
+    let dir = base.join(std::process::id().to_string());
+    // Left behind by an earlier process with our pid
+    let _ = fs::remove_dir_all(&dir);
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    Ok(dir)
+}
+
+/// Whether `path` is on a tmpfs, which is kept in memory and loses its
+/// contents on a crash or reboot.
+pub fn in_memory(path: &Path) -> io::Result<bool> {
+    let path = std::ffi::CString::new(path.as_os_str().as_encoded_bytes())?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_type == libc::TMPFS_MAGIC)
+}
+
+/// Removes `dir` when this process exits, after it has become the program.
+/// A detached watcher process waits for that; if it cannot be started, the
+/// directory is left to the cleanup of the next launch.
+pub fn remove_on_exit(dir: &Path) {
+    let pid = std::process::id() as libc::pid_t;
+    // Opened before forking, so the watcher cannot miss our exit. It is
+    // close-on-exec, so the program does not inherit it.
+    let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) } as RawFd;
+    match unsafe { libc::fork() } {
+        -1 => {}
+        0 => {
+            // Detach from the program, so it never has to reap the watcher
+            if unsafe { libc::fork() } != 0 {
+                unsafe { libc::_exit(0) };
+            }
+            detach(pidfd);
+            wait_for_exit(pid, pidfd);
+            let _ = fs::remove_dir_all(dir);
+            unsafe { libc::_exit(0) };
+        }
+        child => unsafe {
+            libc::waitpid(child, std::ptr::null_mut(), 0);
+        },
+    }
+    if pidfd >= 0 {
+        unsafe { libc::close(pidfd) };
+    }
+}
+
+// Lets go of the terminal and of every file the program has open, such as
+// its output pipes and the instance slot lock
+fn detach(keep: RawFd) {
+    unsafe {
+        libc::setsid();
+        let null = libc::open(c"/dev/null".as_ptr(), libc::O_RDWR);
+        for fd in 0..3 {
+            libc::dup2(null, fd);
+        }
+        if keep > 3 {
+            libc::syscall(libc::SYS_close_range, 3, keep - 1, 0);
+        }
+        libc::syscall(libc::SYS_close_range, keep.max(2) + 1, u32::MAX, 0);
+    }
+}
+
+fn wait_for_exit(pid: libc::pid_t, pidfd: RawFd) {
+    if pidfd >= 0 {
+        let mut poll = libc::pollfd { fd: pidfd, events: libc::POLLIN, revents: 0 };
+        while unsafe { libc::poll(&mut poll, 1, -1) } == -1 {}
+        return;
+    }
+    // Kernels before 5.3 have no pidfds
+    while unsafe { libc::kill(pid, 0) } == 0 {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+}
+
+// Creates `dir` if needed and checks that nobody else can change it, since
+// the program trusts what is inside
+fn private_dir(dir: &Path) -> io::Result<()> {
+    match fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+    let meta = fs::symlink_metadata(dir)?;
+    if !meta.is_dir() || meta.uid() != unsafe { libc::getuid() } || meta.mode() & 0o077 != 0 {
+        return Err(io::Error::other(format!("{} is not a private directory", dir.display())));
+    }
+    Ok(())
+}
+
+// Removes the directories of processes that have exited
+fn remove_stale(base: &Path) {
+    let Ok(entries) = fs::read_dir(base) else { return };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(pid) = name.to_str().and_then(|n| n.parse::<u32>().ok()) else { continue };
+        if !Path::new("/proc").join(pid.to_string()).exists() {
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
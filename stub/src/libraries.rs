@@ -0,0 +1,54 @@
+//! Makes bundled shared libraries visible to the program's loader.
+//!
+//! Each library is decrypted into its own memfd, which stays open across
+//! `execv`. The loader searches directories by file name, so a private
+//! directory per process holds symlinks named after the libraries that
+//! point at `/proc/<pid>/fd/<n>`, and goes first in `LD_LIBRARY_PATH`. The
+//! directory holds no library contents; only the links, and it is removed
+//! when the program exits.
+//!
+//! Children of the program inherit `LD_LIBRARY_PATH`. While the program
+//! runs, they find the bundled libraries through it as well; once it has
+//! exited the directory is gone, and the loader skips the missing entry.
+
+use std::io::{self, Read};
+use std::os::fd::{IntoRawFd, RawFd};
+
+use common::bundle::{self, Index};
+use common::log_debug;
+
+use crate::files;
+
+/// Reads each library of `index` from `plaintext` into a memfd of its own.
+pub fn load(index: &Index, plaintext: &mut impl Read) -> io::Result<Vec<(String, RawFd)>> {
+    let mut fds = Vec::new();
+    for (name, size) in &index.files {
+        if !bundle::valid_name(name) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid library name"));
+        }
+        let mut file = files::memfd(name)?;
+        files::copy_part(plaintext, *size, &mut file)?;
+        fds.push((name.clone(), file.into_raw_fd()));
+    }
+    Ok(fds)
+}
+
+/// Links the libraries' memfds into this process's directory and puts it
+/// first in `LD_LIBRARY_PATH`. Must run before the stub starts any threads.
+pub fn expose(fds: &[(String, RawFd)]) -> io::Result<()> {
+    let dir = files::process_dir("sbb-libs")?;
+    files::remove_on_exit(&dir);
+    let pid = std::process::id();
+    for (name, fd) in fds {
+        std::os::unix::fs::symlink(format!("/proc/{}/fd/{}", pid, fd), dir.join(name))?;
+    }
+    log_debug!("Exposed {} bundled libraries in {}", fds.len(), dir.display());
+
+    let mut path = dir.into_os_string();
+    if let Some(old) = std::env::var_os("LD_LIBRARY_PATH").filter(|p| !p.is_empty()) {
+        path.push(":");
+        path.push(old);
+    }
+    unsafe { std::env::set_var("LD_LIBRARY_PATH", path) };
+    Ok(())
+}
@@ -1,48 +1,101 @@
 //! A stub that decrypts and runs the embedded binary in memory.
 extern crate libc;
 
+mod diagnose;
+#[cfg(unix)]
+mod files;
+#[cfg(unix)]
+mod libraries;
+#[cfg(unix)]
+mod scripts;
+
+use common::compress;
 use common::crypto;
 use common::fingerprint;
+use common::elf;
 use common::embed;
+use common::antidebug::{self, Action, Check};
+use common::header::{self, Header};
+use common::metering::{self, LaunchCounter, MeterError};
+use common::policy::Facts;
+use common::state;
+use common::validity::{self, Clock, LastSeen, SystemClock};
+use common::{log_debug, log_error, log_info, log_warn};
+use std::path::Path;
 
+/// Generic failure (missing payload, decryption error, exec failure).
+const EXIT_FAILURE: i32 = 1;
+/// Run outside the build's validity window, or the clock was set back.
+const EXIT_NOT_VALID: i32 = 3;
+/// Launch or concurrent instance limit exceeded.
+const EXIT_LIMIT_EXCEEDED: i32 = 4;
+/// A launch policy rule (user, group, host, path, parent) failed.
+const EXIT_POLICY_DENIED: i32 = 5;
+/// A debugger or injected code was detected.
+const EXIT_INSTRUMENTED: i32 = 6;
+/// The stub image does not match its build-time measurement, or the usage
+/// state was edited, deleted or restored from a copy.
+const EXIT_TAMPERED: i32 = 7;
 
-#[cfg(unix)]
-use std::os::fd::{FromRawFd};
+/// Read by `sbb` from the image to check what this stub can enforce.
+#[used]
+static CAPABILITIES: &str = common::capability_record!(env!("SBB_STUB_TARGET"));
 
 
 fn main() {
-    println!("[*] Stub running...");
+    common::log::init("stub");
+    log_info!("Stub running");
 
     // 1. Read self
-    let exe_path = std::env::current_exe().unwrap();
-    println!("[*] Current exe path: {:?}", exe_path);
-    let exe_data = std::fs::read(&exe_path).expect("Failed to read current executable");
-    println!("[+] Read {} bytes", exe_data.len());
+    let exe_path = std::env::current_exe().unwrap_or_else(|e| {
+        log_error!("Cannot locate current executable: {}", e);
+        std::process::exit(EXIT_FAILURE);
+    });
+    log_debug!("Current exe path: {:?}", exe_path);
+    let exe_data = std::fs::read(&exe_path).unwrap_or_else(|e| {
+        log_error!("Failed to read current executable: {}", e);
+        std::process::exit(EXIT_FAILURE);
+    });
+    log_debug!("Read {} bytes", exe_data.len());
 
     // 2. Extract all embedded payloads
     let mut payloads = embed::extract_from_stub(&exe_data);
-    println!("[+] Extracted {} payload(s)", payloads.len());
+    log_debug!("Extracted {} payload(s)", payloads.len());
 
     if payloads.is_empty() {
-        eprintln!("❌ No embedded binary found in stub.");
-        std::process::exit(1);
+        log_error!("No embedded binary found in stub.");
+        std::process::exit(EXIT_FAILURE);
     }
 
     // Debug: print payload sizes to help identify them
     for (i, payload) in payloads.iter().enumerate() {
-        println!("[DEBUG] Payload {}: {} bytes", i, payload.len());
+        log_debug!("Payload {}: {} bytes", i, payload.len());
     }
 
-    // 3. Smart payload identification - get the last payloads (most recent embeddings)
-    let (fingerprint, encrypted_binary) = if payloads.len() >= 2 {
-        // Pop the encrypted binary (should be the largest, last payload)
-        let encrypted_binary = payloads.pop().unwrap();
-        
-        // Pop the potential fingerprint (second to last)
-        let potential_fp = payloads.pop().unwrap();
-        
-        println!("[DEBUG] Encrypted binary size: {} bytes", encrypted_binary.len());
-        println!("[DEBUG] Potential fingerprint size: {} bytes", potential_fp.len());
+    // 3. The last payload is the encrypted binary, preceded by the container header
+    let encrypted_binary = payloads.pop().unwrap();
+    let header_bytes = match payloads.pop() {
+        Some(p) if header::is_header(&p) => p,
+        _ => {
+            log_error!("No container header found.");
+            std::process::exit(EXIT_FAILURE);
+        }
+    };
+    let header = Header::from_bytes(&header_bytes).unwrap_or_else(|e| {
+        log_error!("Invalid container header: {}", e);
+        std::process::exit(EXIT_FAILURE);
+    });
+    log_debug!("Encrypted binary size: {} bytes", encrypted_binary.len());
+
+    // Metadata query: print the plain-text manifest without decrypting
+    if manifest_requested(&header) {
+        println!("{:#}", header.manifest());
+        std::process::exit(0);
+    }
+
+    // 4. Smart fingerprint identification - an embedded fingerprint precedes the header
+    let fingerprint = if let Some(potential_fp) = payloads.pop() {
+        log_debug!("Potential fingerprint size: {} bytes", potential_fp.len());
         
         // Check if the potential fingerprint looks like a valid fingerprint
         // SHA256 hex string should be 64 characters (32 bytes * 2), but we also accept other reasonable sizes
@@ -51,106 +104,240 @@ fn main() {
             match String::from_utf8(potential_fp.clone()) {
                 Ok(fp_str) => {
                     // Check if it's a valid hex string or reasonable fingerprint format
-                    if is_valid_fingerprint(&fp_str) {
-                        println!("[*] Two valid payloads detected - using embedded fingerprint");
-                        println!("[*] Using fingerprint: {}", fp_str);
-                        (fp_str, encrypted_binary)
+                    if fingerprint::is_valid_fingerprint(&fp_str) {
+                        log_debug!("Embedded fingerprint detected - using it");
+                        fp_str
                     } else {
-                        println!("[*] Invalid fingerprint format, using machine fingerprint instead");
-                        let fp = fingerprint::generate_fingerprint();
-                        println!("[*] Generated fingerprint: {}", fp);
-                        (fp, encrypted_binary)
+                        log_debug!("Invalid fingerprint format, using machine fingerprint instead");
+                        fingerprint::generate_fingerprint()
                     }
                 },
                 Err(_) => {
-                    println!("[*] Fingerprint not valid UTF-8, using machine fingerprint instead");
-                    let fp = fingerprint::generate_fingerprint();
-                    println!("[*] Generated fingerprint: {}", fp);
-                    (fp, encrypted_binary)
+                    log_debug!("Fingerprint not valid UTF-8, using machine fingerprint instead");
+                    fingerprint::generate_fingerprint()
                 }
             }
         } else {
-            println!("[*] Potential fingerprint size invalid ({} bytes), using machine fingerprint", potential_fp.len());
-            let fp = fingerprint::generate_fingerprint();
-            println!("[*] Generated fingerprint: {}", fp);
-            (fp, encrypted_binary)
+            log_debug!("Potential fingerprint size invalid ({} bytes), using machine fingerprint", potential_fp.len());
+            fingerprint::generate_fingerprint()
         }
-    } else if payloads.len() == 1 {
-        // Only one payload - must be encrypted binary, use machine fingerprint
-        println!("[*] Single payload detected - using machine fingerprint");
-        let fp = fingerprint::generate_fingerprint();
-        println!("[*] Generated fingerprint: {}", fp);
-        let encrypted_binary = payloads.pop().unwrap();
-        (fp, encrypted_binary)
     } else {
-        eprintln!("❌ No valid payloads found.");
-        std::process::exit(1);
+        // No embedded fingerprint - use machine fingerprint
+        log_debug!("No embedded fingerprint - using machine fingerprint");
+        fingerprint::generate_fingerprint()
     };
+    log_info!("Using key {}", fingerprint::key_id(&fingerprint));
 
-    println!("[+] Final encrypted binary size: {} bytes", encrypted_binary.len());
+    // 5. Verify our own code against the build-time measurement
+    if let Some(expected) = &header.stub_measurement
+        && let Err(e) = verify_self(expected, &exe_data)
+    {
+        log_error!("Stub integrity check failed: {}", e);
+        std::process::exit(EXIT_TAMPERED);
+    }
 
-    // 4. Decrypt the binary
-    println!("[*] Decrypting binary...");
-    let decrypted = crypto::decrypt_binary(&fingerprint, &encrypted_binary)
+    // 6. Look for debuggers and injected code
+    check_instrumentation(&header, &exe_path);
+
+    // Diagnostics mode: explain what would happen, without running the
+    // program. It decrypts, so it only runs past the checks above.
+    if diagnose::requested() {
+        let container = diagnose::Container {
+            header: &header,
+            header_bytes: &header_bytes,
+            encrypted: &encrypted_binary,
+            exe_path: &exe_path,
+        };
+        std::process::exit(diagnose::run(&container, &fingerprint));
+    }
+
+    // 7. Refuse to decrypt outside the validity window
+    if let Err(e) = check_validity(&header, &exe_path) {
+        log_error!("{}", e);
+        std::process::exit(EXIT_NOT_VALID);
+    }
+
+    // 8. Evaluate the launch policy
+    if !header.policy.is_empty() {
+        let failures = header.policy.evaluate(&Facts::gather(&exe_path));
+        for failure in &failures {
+            log_error!("{}", failure);
+        }
+        if !failures.is_empty() {
+            std::process::exit(EXIT_POLICY_DENIED);
+        }
+    }
+
+    // 9. Decrypt the binary, authenticating the header along with it
+    log_debug!("Decrypting binary");
+    let decrypted = crypto::decrypt(header.cipher, &fingerprint, &encrypted_binary, &header_bytes)
         .unwrap_or_else(|| {
-            eprintln!("❌ Decryption failed. Wrong fingerprint or corrupted data.");
-            std::process::exit(1);
+            log_error!("Decryption failed. Wrong fingerprint or corrupted data. Run with SBB_DIAGNOSE=1 for details.");
+            std::process::exit(EXIT_FAILURE);
         });
 
-    println!("[+] Decryption succeeded. Decrypted binary size: {} bytes", decrypted.len());
+    log_info!("Decryption succeeded. Decrypted binary size: {} bytes", decrypted.len());
 
-    // 5. Execute in memory
-    println!("[*] Attempting to execute decrypted binary in memory...");
-    if let Err(e) = run_in_memory(&decrypted) {
-        eprintln!("❌ Failed to run binary: {}", e);
-        std::process::exit(1);
+    // 10. Count this launch and take an instance slot
+    if let Err(e) = enforce_usage(&header, &fingerprint) {
+        log_error!("{}", e);
+        std::process::exit(match e {
+            MeterError::LaunchLimitReached { .. } | MeterError::NoFreeInstance { .. } => EXIT_LIMIT_EXCEEDED,
+            MeterError::Tampered => EXIT_TAMPERED,
+            MeterError::Io(_) => EXIT_FAILURE,
+        });
+    }
+
+    // 11. Execute in memory
+    log_debug!("Executing decrypted binary in memory");
+    let args: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    if let Err(e) = run_in_memory(&decrypted, &header, &args) {
+        log_error!("Failed to run binary: {}", e);
+        std::process::exit(EXIT_FAILURE);
     }
 }
 
-/// Check if a string looks like a valid fingerprint
-fn is_valid_fingerprint(s: &str) -> bool {
-    // Check if it's a valid hex string (for SHA256-based fingerprints)
-    if s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit()) {
+/// Whether this invocation asks for the manifest: `SBB_MANIFEST=1` in the
+/// environment, or the build's manifest argument as the only argument.
+fn manifest_requested(header: &Header) -> bool {
+    if std::env::var("SBB_MANIFEST").is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true")) {
         return true;
     }
-    
-    // Check if it's a reasonable fingerprint format (alphanumeric with some special chars)
-    if s.len() >= 16 && s.len() <= 128 && 
-       s.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == ':') {
-        return true;
+    let Some(magic) = &header.manifest_arg else { return false };
+    let mut args = std::env::args_os().skip(1);
+    matches!((args.next(), args.next()), (Some(arg), None) if arg == magic.as_str())
+}
+
+/// Recomputes the measurement of the running image. On Linux the image is
+/// read through `/proc/self/exe`, which always refers to the mapped file.
+fn verify_self(expected: &[u8; 32], exe_data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let image = if cfg!(target_os = "linux") {
+        std::fs::read("/proc/self/exe")?
+    } else {
+        exe_data.to_vec()
+    };
+
+    if elf::measure_load_segments(&image)? != *expected {
+        return Err("image does not match the build-time measurement".into());
+    }
+    Ok(())
+}
+
+/// Runs the instrumentation checks configured at build time, exiting if
+/// one is set to refuse.
+fn check_instrumentation(header: &Header, exe_path: &Path) {
+    let mut sanitize = false;
+    for &(check, action) in &header.anti_debug {
+        let Some(finding) = antidebug::run_check(check, exe_path) else { continue };
+        match action {
+            Action::Log => log_warn!("{}", finding),
+            Action::Sanitize if check == Check::Preload => {
+                log_warn!("{} (removed from the environment)", finding);
+                sanitize = true;
+            }
+            Action::Refuse | Action::Sanitize => {
+                log_error!("{}", finding);
+                std::process::exit(EXIT_INSTRUMENTED);
+            }
+        }
+    }
+    if sanitize {
+        antidebug::sanitize_environment();
     }
-    
-    false
 }
 
-/// Execute a binary directly from memory (Unix)
+/// Applies the header's launch policy. The instance slot lock is handed
+/// over to the executed program and released when it exits.
+fn enforce_usage(header: &Header, fingerprint: &str) -> Result<(), MeterError> {
+    if header.max_launches.is_none() && header.max_instances.is_none() {
+        return Ok(());
+    }
+    let dir = state::state_dir().ok_or_else(|| MeterError::Io("no state directory".into()))?;
+
+    if let Some(max) = header.max_instances {
+        let slot = metering::acquire_slot(&dir, header, max)?;
+        log_info!("Holding instance slot {} of {}", slot.index + 1, max);
+        slot.keep_across_exec()?;
+    }
+    if let Some(max) = header.max_launches {
+        let mut counter = LaunchCounter::new(&dir, header, fingerprint);
+        if let Some(journal) = state::journal_dir() {
+            counter = counter.with_journal(&journal);
+        }
+        let launches = counter.record_launch(max)?;
+        log_info!("Launch {} of {}", launches, max);
+    }
+    Ok(())
+}
+
+/// Checks the header's validity window, using the persisted last-seen time
+/// and our own modification time to detect a clock that was set back.
+fn check_validity(header: &Header, exe_path: &Path) -> Result<(), validity::ValidityError> {
+    let evidence: Vec<u64> = validity::file_mtime(exe_path).into_iter().collect();
+    match state::state_dir() {
+        Some(dir) => validity::enforce(header, &SystemClock, &LastSeen::new(&dir, header), &evidence),
+        None => validity::check_window(header, SystemClock.now(), &evidence),
+    }
+}
+
+/// Execute a binary directly from memory (Unix), passing our own arguments on.
+/// A compressed payload is decompressed straight into the memfd, and each
+/// bundled library gets a memfd of its own. Scripts run with their
+/// interpreter.
 #[cfg(unix)]
-fn run_in_memory(binary: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
-    use std::ffi::CString;
-    use std::io::Write;
+fn run_in_memory(
+    payload: &[u8],
+    header: &Header,
+    args: &[std::ffi::OsString],
+) -> Result<(), Box<dyn std::error::Error>> {
+    use common::bundle::Index;
+    use std::os::fd::AsRawFd;
 
-    let name = CString::new("sbb_temp")?;
-    let fd = unsafe { libc::memfd_create(name.as_ptr(), 0) };
-    if fd == -1 { return Err("memfd_create failed".into()); }
+    let mut plaintext = compress::reader(header.compression.as_ref(), payload)?;
+    if let Some(interpreter) = &header.interpreter {
+        return scripts::run(interpreter, header.entry.as_deref(), &mut plaintext, args);
+    }
+    let mut file = files::memfd("sbb_temp")?;
+    if header.libraries == 0 {
+        std::io::copy(&mut plaintext, &mut file)?;
+    } else {
+        let index = Index::read_from(&mut plaintext)?;
+        if index.files.len() != header.libraries as usize {
+            return Err("bundled libraries do not match the header".into());
+        }
+        files::copy_part(&mut plaintext, index.program_size, &mut file)?;
+        let fds = libraries::load(&index, &mut plaintext)?;
+        files::expect_end(&mut plaintext)?;
+        libraries::expose(&fds)?;
+    }
+    exec(&format!("/proc/self/fd/{}", file.as_raw_fd()), args)
+}
 
-    let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
-    file.write_all(binary)?;
+/// Replaces the stub with the program at `path`, run with `argv`.
+#[cfg(unix)]
+fn exec(path: &str, argv: &[std::ffi::OsString]) -> Result<(), Box<dyn std::error::Error>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
 
-    let path = format!("/proc/self/fd/{}", fd);
     let path_cstr = CString::new(path)?;
-    let args_cstr = CString::new("")?;
+    let args_cstr = argv
+        .iter()
+        .map(|arg| CString::new(arg.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut argv: Vec<*const libc::c_char> = args_cstr.iter().map(|arg| arg.as_ptr()).collect();
+    argv.push(std::ptr::null());
 
     unsafe {
-        libc::execl(
-            path_cstr.as_ptr(),
-            args_cstr.as_ptr(),
-            std::ptr::null::<std::ffi::c_void>(),
-        );
+        libc::execv(path_cstr.as_ptr(), argv.as_ptr());
     }
-    Err("Failed to execute binary".into())
+    Err(format!("Failed to execute {}: {}", path, std::io::Error::last_os_error()).into())
 }
 #[cfg(windows)]
-fn run_in_memory(binary: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+fn run_in_memory(
+    payload: &[u8],
+    header: &Header,
+    args: &[std::ffi::OsString],
+) -> Result<(), Box<dyn std::error::Error>> {
     use std::fs;
     use std::path::Path;
     use std::ffi::CString;
@@ -159,11 +346,22 @@ fn run_in_memory(binary: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
     
     // Write to temporary file (more reliable for Windows)
     let temp_path = std::env::temp_dir().join("sbb_temp.exe");
-    fs::write(&temp_path, binary)?;
+    if header.libraries > 0 || header.interpreter.is_some() {
+        return Err("bundled libraries and scripts are not supported on Windows".into());
+    }
+    compress::write_program(header.compression.as_ref(), payload, &mut fs::File::create(&temp_path)?)?;
     
     // Convert path to CString
     let path_str = temp_path.to_string_lossy().to_string();
     let path_cstr = CString::new(path_str)?;
+
+    // Command line: the program path followed by our own arguments, quoted
+    let mut command_line = quote_windows_arg(&temp_path.to_string_lossy());
+    for arg in args.iter().skip(1) {
+        command_line += " ";
+        command_line += &quote_windows_arg(&arg.to_string_lossy());
+    }
+    let mut command_line = CString::new(command_line)?.into_bytes_with_nul();
     
     // Initialize process structures
     let mut startup_info: STARTUPINFOA = unsafe { std::mem::zeroed() };
@@ -175,7 +373,7 @@ fn run_in_memory(binary: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
     let success = unsafe {
         CreateProcessA(
             path_cstr.as_ptr(),
-            std::ptr::null_mut(),
+            command_line.as_mut_ptr() as *mut i8,
             std::ptr::null_mut(),
             std::ptr::null_mut(),
             FALSE,
@@ -195,4 +393,42 @@ fn run_in_memory(binary: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
     }
     
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Quotes `arg` for a Windows command line the way the MSVC runtime splits
+/// it again: backslashes only escape a quote, so a run of them is doubled
+/// before an inner quote and before the closing quote.
+#[cfg(any(windows, test))]
+fn quote_windows_arg(arg: &str) -> String {
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        if c == '\\' {
+            backslashes += 1;
+            continue;
+        }
+        let escapes = if c == '"' { backslashes * 2 + 1 } else { backslashes };
+        quoted.extend(std::iter::repeat_n('\\', escapes));
+        quoted.push(c);
+        backslashes = 0;
+    }
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_windows_arg() {
+        assert_eq!(quote_windows_arg("plain"), r#""plain""#);
+        assert_eq!(quote_windows_arg("two words"), r#""two words""#);
+        assert_eq!(quote_windows_arg(""), r#""""#);
+        assert_eq!(quote_windows_arg(r"C:\dir\"), r#""C:\dir\\""#);
+        assert_eq!(quote_windows_arg(r"\\server\share"), r#""\\server\share""#);
+        assert_eq!(quote_windows_arg(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(quote_windows_arg(r#"a\"b"#), r#""a\\\"b""#);
+    }
+}
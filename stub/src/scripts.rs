@@ -0,0 +1,81 @@
+//! Runs scripts with the interpreter in their `#!` line.
+//!
+//! A single script reaches the interpreter as `/proc/self/fd/<n>` of a
+//! memfd rather than over stdin, so the script keeps its standard input.
+//! Interpreters look for the other files of a script directory next to the
+//! script, and some resolve symlinks to find it, so a directory is unpacked
+//! into a private per-process directory. That directory must be on a tmpfs,
+//! so the files never reach a disk, and a watcher removes it when the
+//! program exits. Until then, other processes of the same user can read
+//! them.
+
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::Read;
+use std::os::fd::IntoRawFd;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::Path;
+
+use common::bundle::{self, Index};
+use common::{log_debug, script};
+
+use crate::files;
+
+/// Replaces the stub with `interpreter` running the script in `plaintext`:
+/// the script alone, or with `entry` the script directory.
+pub fn run(
+    interpreter: &str,
+    entry: Option<&str>,
+    plaintext: &mut impl Read,
+    args: &[OsString],
+) -> Result<(), Box<dyn Error>> {
+    let script_path = match entry {
+        None => {
+            let mut file = files::memfd("sbb_script")?;
+            std::io::copy(plaintext, &mut file)?;
+            format!("/proc/self/fd/{}", file.into_raw_fd())
+        }
+        Some(entry) => {
+            if !bundle::valid_path(entry) {
+                return Err("invalid entry point".into());
+            }
+            let index = Index::read_from(plaintext)?;
+            let dir = files::process_dir("sbb-scripts")?;
+            if !files::in_memory(&dir)? {
+                let _ = fs::remove_dir(&dir);
+                return Err(format!(
+                    "{} is not on a tmpfs; set XDG_RUNTIME_DIR to one to run script directories",
+                    dir.display()
+                )
+                .into());
+            }
+            files::remove_on_exit(&dir);
+            unpack(&dir, entry, index.program_size, plaintext)?;
+            for (name, size) in &index.files {
+                unpack(&dir, name, *size, plaintext)?;
+            }
+            files::expect_end(plaintext)?;
+            log_debug!("Unpacked {} files next to {} in {}", index.files.len(), entry, dir.display());
+            dir.join(entry).to_string_lossy().into_owned()
+        }
+    };
+
+    let (path, arg) = script::command(interpreter);
+    let mut argv: Vec<OsString> = vec![path.into()];
+    argv.extend(arg.map(OsString::from));
+    argv.push(script_path.into());
+    argv.extend(args.iter().skip(1).cloned());
+    crate::exec(path, &argv)
+}
+
+// Writes the next file of the bundle to `dir/name`
+fn unpack(dir: &Path, name: &str, size: u64, plaintext: &mut impl Read) -> Result<(), Box<dyn Error>> {
+    let path = dir.join(name);
+    if let Some(parent) = path.parent() {
+        fs::DirBuilder::new().recursive(true).mode(0o700).create(parent)?;
+    }
+    let mut file = OpenOptions::new().write(true).create_new(true).mode(0o700).open(&path)?;
+    files::copy_part(plaintext, size, &mut file)?;
+    Ok(())
+}
@@ -0,0 +1,148 @@
+//! Runs secured binaries under ptrace and with loader injection to check
+//! the stub's pre-decryption instrumentation checks.
+#![cfg(target_os = "linux")]
+
+mod support;
+
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use common::antidebug::{Action, Check};
+use common::header::Header;
+use tempfile::TempDir;
+
+const EXIT_INSTRUMENTED: i32 = 6;
+
+fn secure(dir: &TempDir, payload: &str, anti_debug: &[(Check, Action)]) -> PathBuf {
+    let mut header = Header::new();
+    header.anti_debug = anti_debug.to_vec();
+    support::secure(dir, payload, &header)
+}
+
+fn run(path: &Path) -> i32 {
+    Command::new(path).output().unwrap().status.code().unwrap()
+}
+
+/// Starts `path` as a ptrace tracee and drives it to completion, like a
+/// debugger that just continues. Returns the exit code.
+fn run_traced(path: &Path) -> i32 {
+    let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    let devnull = CString::new("/dev/null").unwrap();
+    unsafe {
+        let child = libc::fork();
+        assert!(child >= 0, "fork failed");
+        if child == 0 {
+            let null = libc::open(devnull.as_ptr(), libc::O_WRONLY);
+            libc::dup2(null, 1);
+            libc::dup2(null, 2);
+            libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0);
+            let argv = [path.as_ptr(), std::ptr::null()];
+            libc::execv(path.as_ptr(), argv.as_ptr());
+            libc::_exit(127);
+        }
+
+        loop {
+            let mut status = 0;
+            assert_eq!(libc::waitpid(child, &mut status, 0), child);
+            if libc::WIFEXITED(status) {
+                return libc::WEXITSTATUS(status);
+            }
+            if libc::WIFSIGNALED(status) {
+                return 128 + libc::WTERMSIG(status);
+            }
+            // Swallow the exec traps, forward everything else.
+            let sig = libc::WSTOPSIG(status);
+            let forward = if sig == libc::SIGTRAP { 0 } else { sig };
+            libc::ptrace(libc::PTRACE_CONT, child, 0, forward);
+        }
+    }
+}
+
+#[test]
+fn test_untraced_run_passes_all_checks() {
+    let dir = TempDir::new().unwrap();
+    let secured = secure(&dir, "/bin/true", &[
+        (Check::Tracer, Action::Refuse),
+        (Check::Ptrace, Action::Refuse),
+        (Check::Preload, Action::Refuse),
+        (Check::Maps, Action::Refuse),
+    ]);
+    assert_eq!(run(&secured), 0);
+}
+
+#[test]
+fn test_tracer_pid_refuses_under_ptrace() {
+    let dir = TempDir::new().unwrap();
+    let secured = secure(&dir, "/bin/true", &[(Check::Tracer, Action::Refuse)]);
+    assert_eq!(run_traced(&secured), EXIT_INSTRUMENTED);
+}
+
+#[test]
+fn test_attach_probe_refuses_under_ptrace() {
+    let dir = TempDir::new().unwrap();
+    let secured = secure(&dir, "/bin/true", &[(Check::Ptrace, Action::Refuse)]);
+    assert_eq!(run_traced(&secured), EXIT_INSTRUMENTED);
+}
+
+#[test]
+fn test_log_action_continues_under_ptrace() {
+    let dir = TempDir::new().unwrap();
+    let secured = secure(&dir, "/bin/true", &[(Check::Tracer, Action::Log)]);
+    assert_eq!(run_traced(&secured), 0);
+}
+
+#[test]
+fn test_preload_refused() {
+    let dir = TempDir::new().unwrap();
+    let secured = secure(&dir, "/bin/true", &[(Check::Preload, Action::Refuse)]);
+    let status = Command::new(&secured)
+        .env("LD_PRELOAD", "/nonexistent/libhook.so")
+        .output()
+        .unwrap()
+        .status;
+    assert_eq!(status.code(), Some(EXIT_INSTRUMENTED));
+}
+
+#[test]
+fn test_preload_sanitized() {
+    let dir = TempDir::new().unwrap();
+    let secured = secure(&dir, "/usr/bin/env", &[(Check::Preload, Action::Sanitize)]);
+    let output = Command::new(&secured)
+        .env("LD_PRELOAD", "/nonexistent/libhook.so")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.lines().any(|l| l.starts_with("PATH=")), "payload did not run");
+    assert!(!stdout.lines().any(|l| l.starts_with("LD_PRELOAD=")));
+}
+
+#[test]
+fn test_maps_sanitize_refuses_mapped_library() {
+    // A library that is really mapped, as /etc/ld.so.preload would leave it;
+    // removing LD_PRELOAD afterwards cannot unload it
+    let build = TempDir::new().unwrap();
+    fs::write(build.path().join("hook.c"), "int hook(void) { return 0; }\n").unwrap();
+    let built = Command::new("cc")
+        .current_dir(build.path())
+        .args(["-shared", "-fPIC", "-o", "libhook.so", "hook.c"])
+        .status()
+        .is_ok_and(|s| s.success());
+    if !built {
+        eprintln!("no C compiler; skipping");
+        return;
+    }
+
+    let dir = TempDir::new().unwrap();
+    let secured = secure(&dir, "/usr/bin/env", &[(Check::Maps, Action::Sanitize)]);
+    let output = Command::new(&secured)
+        .env("LD_PRELOAD", build.path().join("libhook.so"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(EXIT_INSTRUMENTED));
+    assert!(output.stdout.is_empty());
+}
@@ -0,0 +1,24 @@
+//! Runs containers encrypted with each supported cipher.
+#![cfg(target_os = "linux")]
+
+mod support;
+
+use std::process::Command;
+
+use common::crypto::Cipher;
+use common::header::Header;
+use tempfile::TempDir;
+
+#[test]
+fn test_runs_each_cipher() {
+    for cipher in Cipher::ALL {
+        let dir = TempDir::new().unwrap();
+        let mut header = Header::new();
+        header.cipher = cipher;
+        let secured = support::secure(&dir, "/bin/echo", &header);
+
+        let output = Command::new(&secured).arg("hello").output().unwrap();
+        assert!(output.status.success(), "{}: {}", cipher.name(), String::from_utf8_lossy(&output.stderr));
+        assert_eq!(output.stdout, b"hello\n");
+    }
+}
@@ -0,0 +1,41 @@
+//! Runs containers whose payload is compressed with each codec.
+#![cfg(target_os = "linux")]
+
+mod support;
+
+use std::fs;
+use std::process::Command;
+
+use common::compress::{Codec, Compression};
+use common::header::Header;
+use tempfile::TempDir;
+
+#[test]
+fn test_runs_each_codec() {
+    let original_size = fs::metadata("/bin/echo").unwrap().len();
+    for codec in Codec::ALL {
+        let dir = TempDir::new().unwrap();
+        let mut header = Header::new();
+        header.compression = Some(Compression { codec, original_size });
+        let secured = support::secure(&dir, "/bin/echo", &header);
+        assert!(fs::metadata(&secured).unwrap().len() < fs::metadata(env!("CARGO_BIN_EXE_stub")).unwrap().len() + original_size);
+
+        let output = Command::new(&secured).arg("hello").output().unwrap();
+        assert!(output.status.success(), "{}: {}", codec.name(), String::from_utf8_lossy(&output.stderr));
+        assert_eq!(output.stdout, b"hello\n");
+    }
+}
+
+#[test]
+fn test_refuses_wrong_size() {
+    let dir = TempDir::new().unwrap();
+    let mut header = Header::new();
+    let original_size = fs::metadata("/bin/echo").unwrap().len() - 1;
+    header.compression = Some(Compression { codec: Codec::Zstd, original_size });
+    let secured = support::secure(&dir, "/bin/echo", &header);
+
+    let output = Command::new(&secured).arg("hello").env("SBB_LOG", "error").output().unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("expected"), "{}", String::from_utf8_lossy(&output.stderr));
+}
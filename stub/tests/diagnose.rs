@@ -0,0 +1,50 @@
+//! Checks the `SBB_DIAGNOSE=1` report.
+#![cfg(target_os = "linux")]
+
+mod support;
+
+use std::process::Command;
+
+use common::header::Header;
+use tempfile::TempDir;
+
+#[test]
+fn test_diagnose_reports_without_running() {
+    let dir = TempDir::new().unwrap();
+    let secured = support::secure(&dir, "/usr/bin/env", &Header::new());
+    let output = Command::new(&secured).env("SBB_DIAGNOSE", "1").output().unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty(), "protected program must not run");
+
+    let report = String::from_utf8(output.stderr).unwrap();
+    assert!(report.contains("decryption:       ok"));
+    assert!(report.contains("hostname:         not recorded"));
+    assert!(!report.contains(support::FP));
+}
+
+#[test]
+fn test_diagnose_reports_expired_window() {
+    let dir = TempDir::new().unwrap();
+    let mut header = Header::new();
+    header.not_after = Some(86_400);
+    let secured = support::secure(&dir, "/bin/true", &header);
+    let output = Command::new(&secured).env("SBB_DIAGNOSE", "1").output().unwrap();
+
+    assert_eq!(output.status.code(), Some(3));
+    let report = String::from_utf8(output.stderr).unwrap();
+    assert!(report.contains("validity window:  FAILED"));
+    assert!(report.contains("decryption:       not attempted"));
+}
+
+#[test]
+fn test_diagnose_refuses_modified_stub() {
+    let dir = TempDir::new().unwrap();
+    let mut header = Header::new();
+    header.stub_measurement = Some([0; 32]);
+    let secured = support::secure(&dir, "/bin/true", &header);
+    let output = Command::new(&secured).env("SBB_DIAGNOSE", "1").output().unwrap();
+
+    assert_eq!(output.status.code(), Some(7));
+    assert!(!String::from_utf8(output.stderr).unwrap().contains("decryption:"));
+}
@@ -0,0 +1,50 @@
+//! Checks that the stub refuses to run once its code has been patched.
+#![cfg(target_os = "linux")]
+
+mod support;
+
+use std::fs;
+use std::process::Command;
+
+use common::elf::{self, Elf, PT_LOAD};
+use common::header::Header;
+use tempfile::TempDir;
+
+const EXIT_TAMPERED: i32 = 7;
+
+fn measured_header() -> Header {
+    let stub = fs::read(env!("CARGO_BIN_EXE_stub")).unwrap();
+    let mut header = Header::new();
+    header.stub_measurement = Some(elf::measure_load_segments(&stub).unwrap());
+    header
+}
+
+#[test]
+fn test_unmodified_stub_runs() {
+    let dir = TempDir::new().unwrap();
+    let secured = support::secure(&dir, "/bin/true", &measured_header());
+    assert_eq!(Command::new(&secured).output().unwrap().status.code(), Some(0));
+}
+
+#[test]
+fn test_patched_stub_refuses() {
+    let dir = TempDir::new().unwrap();
+    let secured = support::secure(&dir, "/bin/true", &measured_header());
+
+    // Flip a byte in the middle of the read-only data segment; the stub
+    // still starts, but its image no longer matches.
+    let mut image = fs::read(&secured).unwrap();
+    let parsed = Elf::parse(&image).unwrap();
+    let rodata = parsed
+        .program_headers
+        .iter()
+        .filter(|ph| ph.p_type == PT_LOAD && ph.flags == 4)
+        .max_by_key(|ph| ph.filesz)
+        .unwrap();
+    let at = (rodata.offset + rodata.filesz / 2) as usize;
+    image[at] ^= 0x01;
+    fs::write(&secured, image).unwrap();
+
+    let output = Command::new(&secured).output().unwrap();
+    assert_eq!(output.status.code(), Some(EXIT_TAMPERED));
+}
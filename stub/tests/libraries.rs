@@ -0,0 +1,80 @@
+//! Runs a program whose shared library exists only inside the container.
+#![cfg(target_os = "linux")]
+
+mod support;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use common::bundle;
+use common::compress::{Codec, Compression};
+use common::header::Header;
+use tempfile::TempDir;
+
+const LIBRARY: &str = "const char *greeting(void) { return \"hello from libgreet\"; }\n";
+const PROGRAM: &str = "#include <stdio.h>\n\
+    const char *greeting(void);\n\
+    int main(int argc, char **argv) { printf(\"%s %s\\n\", greeting(), argc > 1 ? argv[1] : \"\"); return 0; }\n";
+
+// Builds libgreet.so.1 and a program that needs it, or returns None without
+// a C compiler
+fn build_program(dir: &Path) -> Option<(Vec<u8>, Vec<u8>)> {
+    fs::write(dir.join("greet.c"), LIBRARY).unwrap();
+    fs::write(dir.join("main.c"), PROGRAM).unwrap();
+    let cc = |args: &[&str]| Command::new("cc").current_dir(dir).args(args).status().is_ok_and(|s| s.success());
+    if !cc(&["-shared", "-fPIC", "-Wl,-soname,libgreet.so.1", "-o", "libgreet.so.1", "greet.c"]) {
+        eprintln!("no C compiler; skipping");
+        return None;
+    }
+    assert!(cc(&["-o", "app", "main.c", "-L.", "-l:libgreet.so.1"]));
+    Some((fs::read(dir.join("app")).unwrap(), fs::read(dir.join("libgreet.so.1")).unwrap()))
+}
+
+#[test]
+fn test_runs_with_bundled_library() {
+    let build = TempDir::new().unwrap();
+    let Some((program, library)) = build_program(build.path()) else { return };
+    // The library is only in the container from here on
+    let plain = bundle::pack(&program, &[("libgreet.so.1".to_string(), library)]);
+    drop(build);
+
+    for codec in [None, Some(Codec::Zstd)] {
+        let dir = TempDir::new().unwrap();
+        let runtime = TempDir::new().unwrap();
+        let mut header = Header::new();
+        header.libraries = 1;
+        header.compression = codec.map(|codec| Compression { codec, original_size: plain.len() as u64 });
+        let secured = support::secure_bytes(&dir, plain.clone(), &header);
+
+        let output = Command::new(&secured)
+            .arg("world")
+            .env("XDG_RUNTIME_DIR", runtime.path())
+            .env("LD_LIBRARY_PATH", "/nonexistent")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(output.stdout, b"hello from libgreet world\n");
+        // The links to the memfds go once the program has exited
+        let libs = runtime.path().join("sbb-libs");
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while fs::read_dir(&libs).unwrap().next().is_some() {
+            assert!(Instant::now() < deadline, "{} was not cleaned up", libs.display());
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+#[test]
+fn test_refuses_payload_without_index() {
+    let dir = TempDir::new().unwrap();
+    let mut header = Header::new();
+    header.libraries = 1;
+    let secured = support::secure(&dir, "/bin/echo", &header);
+
+    let output = Command::new(&secured).arg("hello").env("SBB_LOG", "error").output().unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("do not match"), "{}", String::from_utf8_lossy(&output.stderr));
+}
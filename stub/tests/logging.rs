@@ -0,0 +1,43 @@
+//! Checks that the stub stays out of the protected program's output and
+//! never logs key material.
+#![cfg(target_os = "linux")]
+
+mod support;
+
+use std::process::Command;
+
+use common::header::Header;
+use tempfile::TempDir;
+
+#[test]
+fn test_silent_by_default() {
+    let dir = TempDir::new().unwrap();
+    let secured = support::secure(&dir, "/bin/true", &Header::new());
+    let output = Command::new(&secured).env_remove("SBB_LOG").output().unwrap();
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_debug_logs_go_to_stderr_without_secrets() {
+    let dir = TempDir::new().unwrap();
+    let secured = support::secure(&dir, "/bin/true", &Header::new());
+    let output = Command::new(&secured)
+        .env("SBB_LOG", "debug")
+        .env("SBB_LOG_FORMAT", "json")
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.lines().count() > 3);
+    for line in stderr.lines() {
+        let record: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert_eq!(record["component"], "stub");
+    }
+    assert!(!stderr.contains(support::FP));
+}
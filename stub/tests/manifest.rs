@@ -0,0 +1,58 @@
+//! Checks the manifest query and argument forwarding.
+#![cfg(target_os = "linux")]
+
+mod support;
+
+use std::process::Command;
+
+use common::header::Header;
+use tempfile::TempDir;
+
+fn header() -> Header {
+    let mut header = Header::new();
+    header.product = Some("Example Tool".into());
+    header.version = Some("2.4.1".into());
+    header.not_after = Some(4_102_444_800);
+    header.manifest_arg = Some("--sbb-manifest".into());
+    header
+}
+
+#[test]
+fn test_manifest_from_environment() {
+    let dir = TempDir::new().unwrap();
+    let header = header();
+    let secured = support::secure(&dir, "/bin/echo", &header);
+    let output = Command::new(&secured).env("SBB_MANIFEST", "1").arg("hello").output().unwrap();
+
+    assert!(output.status.success());
+    let manifest: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(manifest["product"], "Example Tool");
+    assert_eq!(manifest["version"], "2.4.1");
+    assert_eq!(manifest["build_id"], header.build_id_hex());
+    assert_eq!(manifest["not_after"], "2100-01-01T00:00:00Z");
+}
+
+#[test]
+fn test_manifest_from_agreed_argument() {
+    let dir = TempDir::new().unwrap();
+    let secured = support::secure(&dir, "/bin/echo", &header());
+    let output = Command::new(&secured).arg("--sbb-manifest").output().unwrap();
+
+    assert!(output.status.success());
+    let manifest: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(manifest["product"], "Example Tool");
+}
+
+#[test]
+fn test_arguments_reach_the_program() {
+    let dir = TempDir::new().unwrap();
+    let secured = support::secure(&dir, "/bin/echo", &header());
+
+    // The agreed argument is only special on its own
+    let output = Command::new(&secured).args(["--sbb-manifest", "two words"]).output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"--sbb-manifest two words\n");
+
+    let output = Command::new(&secured).arg("--sbb-manifest=1").output().unwrap();
+    assert_eq!(output.stdout, b"--sbb-manifest=1\n");
+}
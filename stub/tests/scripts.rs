@@ -0,0 +1,97 @@
+//! Runs `#!` scripts and script directories from the container.
+#![cfg(target_os = "linux")]
+
+mod support;
+
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use common::bundle;
+use common::header::Header;
+use tempfile::TempDir;
+
+const SCRIPT: &str = "#!/bin/sh -e\necho \"args: $*\"\nread line\necho \"stdin: $line\"\n";
+const ENTRY: &str = "#!/bin/sh\n. \"$(dirname \"$0\")/../lib/greet.sh\"\ngreet \"$1\"\n";
+const HELPER: &str = "greet() { echo \"hello $1 from $(basename \"$(pwd)\")\"; }\n";
+
+#[test]
+fn test_runs_script_with_its_stdin() {
+    let dir = TempDir::new().unwrap();
+    let mut header = Header::new();
+    header.interpreter = Some("/bin/sh -e".to_string());
+    let secured = support::secure_bytes(&dir, SCRIPT.as_bytes().to_vec(), &header);
+
+    let mut child = Command::new(&secured)
+        .args(["one", "two"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"typed in\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(output.stdout, b"args: one two\nstdin: typed in\n");
+}
+
+#[test]
+fn test_runs_script_directory_and_removes_it() {
+    let dir = TempDir::new().unwrap();
+    let runtime = TempDir::new_in("/dev/shm").unwrap();
+    let work = TempDir::new().unwrap();
+    let mut header = Header::new();
+    header.interpreter = Some("/bin/sh".to_string());
+    header.entry = Some("bin/run.sh".to_string());
+    let plain = bundle::pack(ENTRY.as_bytes(), &[("lib/greet.sh".to_string(), HELPER.as_bytes().to_vec())]);
+    let secured = support::secure_bytes(&dir, plain, &header);
+
+    let output = Command::new(&secured)
+        .arg("world")
+        .current_dir(work.path())
+        .env("XDG_RUNTIME_DIR", runtime.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // The script runs in the caller's directory, not the unpacked one
+    let expected = format!("hello world from {}\n", work.path().file_name().unwrap().to_string_lossy());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+    // The watcher removes the unpacked files once the script has exited
+    let scripts = runtime.path().join("sbb-scripts");
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while fs::read_dir(&scripts).unwrap().next().is_some() {
+        assert!(Instant::now() < deadline, "{} was not cleaned up", scripts.display());
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn test_refuses_entry_outside_directory() {
+    let dir = TempDir::new().unwrap();
+    let mut header = Header::new();
+    header.interpreter = Some("/bin/sh".to_string());
+    header.entry = Some("../run.sh".to_string());
+    let secured = support::secure(&dir, "/bin/echo", &header);
+
+    let output = Command::new(&secured).env("SBB_LOG", "error").output().unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_refuses_to_unpack_on_disk() {
+    let dir = TempDir::new().unwrap();
+    let runtime = TempDir::new_in(env!("CARGO_TARGET_TMPDIR")).unwrap();
+    let mut header = Header::new();
+    header.interpreter = Some("/bin/sh".to_string());
+    header.entry = Some("bin/run.sh".to_string());
+    let plain = bundle::pack(ENTRY.as_bytes(), &[("lib/greet.sh".to_string(), HELPER.as_bytes().to_vec())]);
+    let secured = support::secure_bytes(&dir, plain, &header);
+
+    let output = Command::new(&secured).env("XDG_RUNTIME_DIR", runtime.path()).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not on a tmpfs"));
+    assert_eq!(fs::read_dir(runtime.path().join("sbb-scripts")).unwrap().count(), 0);
+}
@@ -0,0 +1,38 @@
+//! Helpers shared by the stub integration tests.
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use common::compress;
+use common::crypto::{self, Seal};
+use common::embed;
+use common::header::Header;
+use tempfile::TempDir;
+
+pub const FP: &str = "5f0c3a7e9d2b4c6a8e1f3b5d7c9a0e2f4b6d8c0a1e3f5b7d9c2a4e6f8b0d1c3a";
+
+/// Wraps `payload` in the freshly built stub with an embedded key, the way
+/// `sbb` does without `--encrypt`.
+/// A compression record in `header` must give the codec; the payload is
+/// compressed at its default level.
+pub fn secure(dir: &TempDir, payload: &str, header: &Header) -> PathBuf {
+    secure_bytes(dir, fs::read(payload).unwrap(), header)
+}
+
+/// [`secure`] for a plaintext held in memory.
+pub fn secure_bytes(dir: &TempDir, mut plain: Vec<u8>, header: &Header) -> PathBuf {
+    let stub = fs::read(env!("CARGO_BIN_EXE_stub")).unwrap();
+    if let Some(c) = &header.compression {
+        plain = compress::compress(compress::parse_setting(c.codec.name()).unwrap(), &plain).unwrap();
+    }
+    let header_bytes = header.to_bytes();
+
+    let encrypted = crypto::encrypt(header.cipher, FP, &plain, &header_bytes, &Seal::random()).unwrap();
+    let secured = embed::append_payloads(&stub, &[FP.as_bytes().to_vec(), header_bytes, encrypted]);
+
+    let path = dir.path().join("app.secured");
+    fs::write(&path, secured).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}